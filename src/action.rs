@@ -6,11 +6,7 @@ use std::{
 
 use educe::Educe;
 use futures::Future;
-use tokio::{
-    sync::Notify,
-    sync::{futures::Notified, RwLock},
-    task::JoinHandle,
-};
+use tokio::{sync::Notify, sync::RwLock, task::JoinHandle};
 
 #[derive(Educe)]
 #[educe(Debug)]
@@ -22,7 +18,7 @@ pub(crate) struct Action<I: Debug + Clone + Send + Sync + 'static, O: Debug + Se
 
     #[educe(Debug(ignore))]
     #[allow(clippy::complexity)]
-    action_fn: Arc<dyn Fn(&I) -> Pin<Box<dyn Future<Output = O> + Send + Sync>> + Send + Sync>,
+    action_fn: Arc<dyn Fn(&I) -> Pin<Box<dyn Future<Output = O> + Send>> + Send + Sync>,
 
     /// Might be Some if there still is an ongoing operation.
     pending: Arc<AtomicBool>,
@@ -39,15 +35,33 @@ pub(crate) struct Action<I: Debug + Clone + Send + Sync + 'static, O: Debug + Se
     version: Arc<RwLock<usize>>,
 }
 
+// Note: Not derived, as deriving would require `I` and `O` to be `Clone` as well.
+impl<I: Debug + Clone + Send + Sync + 'static, O: Debug + Send + Sync + 'static> Clone
+    for Action<I, O>
+{
+    /// Creates a handle to the same action, sharing its state.
+    fn clone(&self) -> Self {
+        Self {
+            input: self.input.clone(),
+            action_fn: self.action_fn.clone(),
+            pending: self.pending.clone(),
+            notify: self.notify.clone(),
+            value: self.value.clone(),
+            value_received: self.value_received.clone(),
+            version: self.version.clone(),
+        }
+    }
+}
+
 impl<I: Debug + Clone + Send + Sync + 'static, O: Debug + Send + Sync + 'static> Action<I, O> {
     pub(crate) fn new<F, Fu>(action_fn: F) -> Self
     where
         F: Fn(&I) -> Fu + Send + Sync + 'static,
-        Fu: Future<Output = O> + Send + Sync + 'static,
+        Fu: Future<Output = O> + Send + 'static,
     {
         let action_fn = Arc::new(move |input: &I| {
             let fut = action_fn(input);
-            Box::pin(fut) as Pin<Box<dyn Future<Output = O> + Send + Sync>>
+            Box::pin(fut) as Pin<Box<dyn Future<Output = O> + Send>>
         });
 
         Self {
@@ -61,26 +75,31 @@ impl<I: Debug + Clone + Send + Sync + 'static, O: Debug + Send + Sync + 'static>
         }
     }
 
-    pub(crate) fn notified(&self) -> Notified<'_> {
-        self.notify.notified()
-    }
-
     pub(crate) fn is_pending(&self) -> bool {
         self.pending.load(std::sync::atomic::Ordering::Acquire)
     }
 
+    /// Waits until the currently pending operation (if any) resolved.
+    pub(crate) async fn wait(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        // Register for notifications before checking the pending state. Otherwise, we could miss the notification.
+        notified.as_mut().enable();
+        if self.is_pending() {
+            notified.await;
+        }
+    }
+
     pub(crate) async fn value(&self) -> tokio::sync::RwLockReadGuard<'_, std::option::Option<O>> {
         self.value.read().await
     }
 
-    #[allow(dead_code)]
     pub(crate) async fn value_received(
         &self,
     ) -> tokio::sync::RwLockReadGuard<'_, std::option::Option<time::OffsetDateTime>> {
         self.value_received.read().await
     }
 
-    #[allow(dead_code)]
     pub(crate) async fn version(&self) -> usize {
         *self.version.read().await
     }
@@ -94,9 +113,11 @@ impl<I: Debug + Clone + Send + Sync + 'static, O: Debug + Send + Sync + 'static>
         let value = self.value.clone();
         let value_received = self.value_received.clone();
 
+        // Mark the action as pending immediately, so that callers can rely on `is_pending` right after dispatching.
+        pending.store(true, std::sync::atomic::Ordering::Release);
+
         tokio::spawn(async move {
             *input.write().await = Some(action_input.clone());
-            pending.store(true, std::sync::atomic::Ordering::Release);
             let new_value = fut.await;
             let new_value_received_at = time::OffsetDateTime::now_utc();
             *value.write().await = Some(new_value);
@@ -0,0 +1,132 @@
+use serde::de::DeserializeOwned;
+
+use crate::{
+    decode::{KeycloakToken, ProfileAndEmail},
+    error::AuthError,
+    role::Role,
+};
+
+/// Decides whether a request may be processed, based on the validated token and the request itself.
+///
+/// Configure an implementation as the `authorizer` of the `KeycloakAuthLayer` to implement attribute-based access control,
+/// e.g. by querying an OPA or Cedar policy engine with the method and path of the request and the claims of the token.
+/// It is consulted last, after the token passed all other validations (including the role, scope and group checks).
+/// Return `AuthError::AccessDenied` to reject the request with a status and body of your choice, or any other `AuthError`.
+#[axum::async_trait]
+pub trait Authorizer<R, Extra = ProfileAndEmail>: Send + Sync
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    async fn authorize(
+        &self,
+        token: &KeycloakToken<R, Extra>,
+        request: &http::request::Parts,
+    ) -> Result<(), AuthError>;
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::{routing::get, Router};
+    use serde_json::json;
+
+    use crate::{
+        decode::{KeycloakToken, ProfileAndEmail},
+        error::AuthError,
+        layer::KeycloakAuthLayer,
+        testing::test::{secret_builder, serve},
+        PassthroughMode,
+    };
+
+    use super::Authorizer;
+
+    /// Only lets admins delete, and requires a role named like the requested path.
+    struct PathPolicy;
+
+    #[axum::async_trait]
+    impl Authorizer<String> for PathPolicy {
+        async fn authorize(
+            &self,
+            token: &KeycloakToken<String, ProfileAndEmail>,
+            request: &http::request::Parts,
+        ) -> Result<(), AuthError> {
+            if request.method == http::Method::DELETE && !token.has_role("admin") {
+                return Err(AuthError::AccessDenied {
+                    status: http::StatusCode::METHOD_NOT_ALLOWED,
+                    body: json!({ "error": "Only admins may delete." }),
+                });
+            }
+            let resource = request.uri.path().trim_start_matches('/');
+            match token.has_role(resource) {
+                true => Ok(()),
+                false => Err(AuthError::AccessDenied {
+                    status: http::StatusCode::FORBIDDEN,
+                    body: json!({ "denied": resource }),
+                }),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn authorizes_requests_as_last_step() {
+        let builder = secret_builder();
+        let router = Router::new()
+            .route(
+                "/invoices",
+                get(|| async { "ok" }).delete(|| async { "ok" }),
+            )
+            .route("/payroll", get(|| async { "ok" }))
+            .layer(
+                KeycloakAuthLayer::<String>::builder()
+                    .instance(builder.instance().unwrap())
+                    .passthrough_mode(PassthroughMode::Block)
+                    .expected_audiences(vec![String::from("account")])
+                    .authorizer(Arc::new(PathPolicy))
+                    .build(),
+            );
+        let url = serve(router).await;
+
+        let token = builder.clone().realm_role("invoices").build().unwrap();
+        let send = |method: reqwest::Method, path: &str, token: String| {
+            let request = reqwest::Client::new()
+                .request(method, format!("{url}{path}"))
+                .bearer_auth(token);
+            async move {
+                let response = request.send().await.unwrap();
+                (response.status(), response.text().await.unwrap())
+            }
+        };
+
+        assert_eq!(
+            send(reqwest::Method::GET, "/invoices", token.clone()).await,
+            (http::StatusCode::OK, String::from("ok"))
+        );
+        assert_eq!(
+            send(reqwest::Method::GET, "/payroll", token.clone()).await,
+            (
+                http::StatusCode::FORBIDDEN,
+                String::from(r#"{"denied":"payroll"}"#)
+            )
+        );
+        assert_eq!(
+            send(reqwest::Method::DELETE, "/invoices", token).await,
+            (
+                http::StatusCode::METHOD_NOT_ALLOWED,
+                String::from(r#"{"error":"Only admins may delete."}"#)
+            )
+        );
+
+        // Invalid tokens are rejected before the authorizer is consulted.
+        let expired = builder
+            .realm_role("invoices")
+            .expired(std::time::Duration::from_secs(60))
+            .build()
+            .unwrap();
+        assert_eq!(
+            send(reqwest::Method::GET, "/invoices", expired).await.0,
+            http::StatusCode::UNAUTHORIZED
+        );
+    }
+}
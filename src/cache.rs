@@ -0,0 +1,377 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::{decode::ValidatedToken, role::Role};
+
+/// Key of a cached token: The SHA-256 hash of the raw token and the audiences it was validated against.
+///
+/// Cached tokens are deliberately not keyed by their 'jti' claim. Anyone could present a forged token reusing the
+/// JWT ID of a cached one, which would then be accepted without its signature ever being verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey([u8; 32]);
+
+impl CacheKey {
+    pub(crate) fn new(raw_token: &str, expected_audiences: &[String]) -> Self {
+        // Requests may skip the audience validation (see `SkipAudienceValidation`).
+        // Tokens validated that way must not be handed out to requests which do require an audience.
+        let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+        for audience in expected_audiences {
+            context.update(audience.as_bytes());
+            context.update(&[0]);
+        }
+        context.update(&[0]);
+        context.update(raw_token.as_bytes());
+        let mut key = [0; 32];
+        key.copy_from_slice(context.finish().as_ref());
+        Self(key)
+    }
+}
+
+/// Bounded cache of validated tokens, skipping the signature verification and parsing of tokens presented repeatedly.
+///
+/// Configure it as the `token_cache` of a `KeycloakAuthLayer`. It holds up to `capacity` tokens,
+/// evicting expired tokens first and the least recently used ones after that.
+/// On every use of a cached token the time-dependent checks (expiry, not-before time and `max_token_age`)
+/// and the `jti_blocklist` of the layer are consulted again, so revocations still take effect immediately.
+/// Tokens are cached until their expiry. Cache hits are not reported to the `metrics` of the layer, as nothing is decoded.
+///
+/// Clones share the same cached tokens. Do not share one cache between differently configured layers:
+/// A token validated by one layer would be accepted by any other layer using the same cache.
+pub struct TokenCache<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    capacity: usize,
+    entries: Arc<Mutex<Entries<R, Extra>>>,
+}
+
+struct Entries<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    /// Incremented on every use, ordering the entries by their last use.
+    clock: u64,
+    by_key: HashMap<CacheKey, Entry<R, Extra>>,
+    by_last_use: BTreeMap<u64, CacheKey>,
+}
+
+struct Entry<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    last_use: u64,
+    validated: ValidatedToken<R, Extra>,
+}
+
+impl<R, Extra> TokenCache<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    /// Creates a cache holding up to `capacity` tokens. A capacity of `0` caches nothing.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Arc::new(Mutex::new(Entries {
+                clock: 0,
+                by_key: HashMap::new(),
+                by_last_use: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// The maximum number of tokens held.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of tokens currently held, possibly including expired ones not yet evicted.
+    pub fn len(&self) -> usize {
+        self.lock().by_key.len()
+    }
+
+    /// Returns true if no tokens are held.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all cached tokens.
+    pub fn clear(&self) {
+        let mut entries = self.lock();
+        entries.by_key.clear();
+        entries.by_last_use.clear();
+    }
+
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<ValidatedToken<R, Extra>> {
+        let mut entries = self.lock();
+        let entries = &mut *entries;
+        entries.clock += 1;
+        let entry = entries.by_key.get_mut(key)?;
+        entries.by_last_use.remove(&entry.last_use);
+        entries.by_last_use.insert(entries.clock, *key);
+        entry.last_use = entries.clock;
+        Some(entry.validated.clone())
+    }
+
+    pub(crate) fn insert(
+        &self,
+        key: CacheKey,
+        validated: ValidatedToken<R, Extra>,
+        now: time::OffsetDateTime,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.lock();
+        let entries = &mut *entries;
+        entries.remove(&key);
+        if entries.by_key.len() >= self.capacity {
+            entries
+                .by_key
+                .retain(|_, entry| entry.validated.token.expires_at > now);
+            let by_key = &entries.by_key;
+            entries
+                .by_last_use
+                .retain(|_, key| by_key.contains_key(key));
+        }
+        while entries.by_key.len() >= self.capacity {
+            match entries.by_last_use.pop_first() {
+                Some((_, least_recently_used)) => entries.by_key.remove(&least_recently_used),
+                None => break,
+            };
+        }
+        entries.clock += 1;
+        entries.by_last_use.insert(entries.clock, key);
+        entries.by_key.insert(
+            key,
+            Entry {
+                last_use: entries.clock,
+                validated,
+            },
+        );
+    }
+
+    pub(crate) fn remove(&self, key: &CacheKey) {
+        self.lock().remove(key);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries<R, Extra>> {
+        // Entries are always left consistent, a panic while holding the lock does not invalidate them.
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<R, Extra> Entries<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.by_key.remove(key) {
+            self.by_last_use.remove(&entry.last_use);
+        }
+    }
+}
+
+impl<R, Extra> Clone for TokenCache<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            capacity: self.capacity,
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<R, Extra> std::fmt::Debug for TokenCache<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenCache")
+            .field("capacity", &self.capacity)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use url::Url;
+
+    use crate::{
+        clock::Clock,
+        error::AuthError,
+        instance::KeycloakConfig,
+        layer::KeycloakAuthLayer,
+        metrics::AuthMetrics,
+        revocation::JtiBlocklist,
+        testing::{TestKey, TestTokenBuilder},
+    };
+
+    use super::TokenCache;
+
+    #[derive(Default)]
+    struct CountingMetrics {
+        decode_succeeded: AtomicUsize,
+    }
+
+    impl AuthMetrics for CountingMetrics {
+        fn decode_succeeded(&self) {
+            self.decode_succeeded.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    struct ManualClock(Mutex<time::OffsetDateTime>);
+
+    impl Clock for ManualClock {
+        fn now(&self) -> time::OffsetDateTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    struct Revoked(Mutex<HashSet<String>>);
+
+    #[axum::async_trait]
+    impl JtiBlocklist for Revoked {
+        async fn is_revoked(&self, jti: &str) -> bool {
+            self.0.lock().unwrap().contains(jti)
+        }
+    }
+
+    fn builder(clock: Arc<ManualClock>) -> TestTokenBuilder {
+        TestTokenBuilder::new(
+            TestKey::secret(b"secret"),
+            KeycloakConfig::builder()
+                .server(Url::parse("https://localhost:8443/").unwrap())
+                .realm(String::from("MyRealm"))
+                .leeway(Duration::from_secs(30))
+                .clock(clock)
+                .build(),
+        )
+    }
+
+    fn layer(
+        builder: &TestTokenBuilder,
+        cache: &TokenCache<String, crate::decode::ProfileAndEmail>,
+        metrics: Arc<CountingMetrics>,
+    ) -> KeycloakAuthLayer<String> {
+        KeycloakAuthLayer::<String>::builder()
+            .instance(builder.instance().unwrap())
+            .expected_audiences(vec![String::from("account")])
+            .metrics(metrics)
+            .token_cache(cache.clone())
+            .build()
+    }
+
+    fn clock() -> Arc<ManualClock> {
+        Arc::new(ManualClock(Mutex::new(time::OffsetDateTime::now_utc())))
+    }
+
+    #[tokio::test]
+    async fn skips_decoding_of_cached_tokens() {
+        let builder = builder(clock());
+        let cache = TokenCache::new(10);
+        let metrics = Arc::new(CountingMetrics::default());
+        let layer = layer(&builder, &cache, metrics.clone());
+
+        let token = builder.clone().jwt_id("first").build().unwrap();
+        for _ in 0..3 {
+            let (_, validated) = layer.validate_raw_token(&token).await.unwrap();
+            assert_eq!(validated.jwt_id, "first");
+        }
+        assert_eq!(metrics.decode_succeeded.load(Ordering::SeqCst), 1);
+
+        let other = builder.jwt_id("second").build().unwrap();
+        layer.validate_raw_token(&other).await.unwrap();
+        assert_eq!(metrics.decode_succeeded.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.len(), 2);
+
+        // Tokens validated without an audience are not reused when one is required.
+        let mut lenient = layer.clone();
+        lenient.expected_audiences = Vec::new();
+        lenient.validate_raw_token(&token).await.unwrap();
+        assert_eq!(metrics.decode_succeeded.load(Ordering::SeqCst), 3);
+
+        // Tampered tokens are never a cache hit.
+        let tampered = format!("{}x", token);
+        assert!(layer.validate_raw_token(&tampered).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn checks_cached_tokens_on_every_use() {
+        let clock = clock();
+        let builder = builder(clock.clone());
+        let cache = TokenCache::new(10);
+        let blocklist = Arc::new(Revoked(Mutex::new(HashSet::new())));
+        let mut layer = layer(&builder, &cache, Arc::default());
+        layer.jti_blocklist = Some(blocklist.clone());
+
+        let token = builder.clone().jwt_id("revoked").build().unwrap();
+        layer.validate_raw_token(&token).await.unwrap();
+        blocklist.0.lock().unwrap().insert(String::from("revoked"));
+        assert!(matches!(
+            layer.validate_raw_token(&token).await,
+            Err(AuthError::TokenRevoked)
+        ));
+
+        let token = builder.jwt_id("expiring").build().unwrap();
+        layer.validate_raw_token(&token).await.unwrap();
+        *clock.0.lock().unwrap() += Duration::from_secs(300 + 31);
+        assert!(matches!(
+            layer.validate_raw_token(&token).await,
+            Err(AuthError::TokenExpired)
+        ));
+        // Only the revoked token remains.
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_tokens() {
+        let builder = builder(clock());
+        let cache = TokenCache::new(2);
+        let metrics = Arc::new(CountingMetrics::default());
+        let layer = layer(&builder, &cache, metrics.clone());
+        let decodes = || metrics.decode_succeeded.load(Ordering::SeqCst);
+
+        let [first, second, third] =
+            ["first", "second", "third"].map(|jti| builder.clone().jwt_id(jti).build().unwrap());
+        layer.validate_raw_token(&first).await.unwrap();
+        layer.validate_raw_token(&second).await.unwrap();
+        layer.validate_raw_token(&first).await.unwrap();
+        assert_eq!(decodes(), 2);
+
+        // Evicts the second token, which was used least recently.
+        layer.validate_raw_token(&third).await.unwrap();
+        assert_eq!(cache.len(), 2);
+        layer.validate_raw_token(&first).await.unwrap();
+        assert_eq!(decodes(), 3);
+        layer.validate_raw_token(&second).await.unwrap();
+        assert_eq!(decodes(), 4);
+
+        let uncached = TokenCache::new(0);
+        let layer = self::layer(&builder, &uncached, metrics.clone());
+        layer.validate_raw_token(&first).await.unwrap();
+        assert!(uncached.is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+/// Source of the current time, used to decide whether tokens are expired or not yet valid.
+///
+/// Configure an implementation as the `clock` of the `KeycloakConfig`, e.g. to pin the time in tests of expiry logic.
+/// The `SystemClock` is used by default.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> time::OffsetDateTime;
+}
+
+/// Reads the current time of the system (`time::OffsetDateTime::now_utc`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> time::OffsetDateTime {
+        time::OffsetDateTime::now_utc()
+    }
+}
+
+/// Always reports the same point in time. Allows for deterministic tests of token expiry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedClock(pub time::OffsetDateTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> time::OffsetDateTime {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use url::Url;
+
+    use crate::{
+        error::AuthError,
+        instance::KeycloakConfig,
+        layer::KeycloakAuthLayer,
+        testing::{TestKey, TestTokenBuilder},
+    };
+
+    use super::FixedClock;
+
+    fn layer(builder: &TestTokenBuilder) -> KeycloakAuthLayer<String> {
+        KeycloakAuthLayer::<String>::builder()
+            .instance(builder.instance().unwrap())
+            .expected_audiences(vec![String::from("account")])
+            .build()
+    }
+
+    #[tokio::test]
+    async fn validates_expiry_against_configured_clock() {
+        // Long expired, according to the system clock.
+        let expires_at = time::OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let config = |now: time::OffsetDateTime| {
+            KeycloakConfig::builder()
+                .server(Url::parse("https://localhost:8443/").unwrap())
+                .realm(String::from("MyRealm"))
+                .leeway(Duration::from_secs(30))
+                .clock(Arc::new(FixedClock(now)))
+                .build()
+        };
+        let token = TestTokenBuilder::new(TestKey::secret(b"secret"), config(expires_at))
+            .expires_at(expires_at)
+            .build()
+            .unwrap();
+
+        for (now, accepted) in [
+            (expires_at, true),
+            (expires_at + time::Duration::seconds(30), true),
+            (expires_at + time::Duration::seconds(31), false),
+        ] {
+            let builder = TestTokenBuilder::new(TestKey::secret(b"secret"), config(now));
+            let result = layer(&builder).validate_raw_token(&token).await;
+            match accepted {
+                true => assert!(result.is_ok(), "{now}: {result:?}"),
+                false => assert!(
+                    matches!(result, Err(AuthError::TokenExpired)),
+                    "{now}: {result:?}"
+                ),
+            }
+        }
+    }
+}
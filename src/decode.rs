@@ -1,7 +1,8 @@
-use std::collections::HashMap;
-use std::i64;
-use std::sync::Arc;
 use jsonwebtoken::{Algorithm, DecodingKey};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -10,22 +11,67 @@ use serde_with::{serde_as, OneOrMany};
 use snafu::ResultExt;
 use tracing::debug;
 
-use crate::error::DecodeHeaderSnafu;
 use crate::error::DecodeSnafu;
-use crate::instance::KeycloakAuthInstance;
+use crate::instance::{CustomizeValidation, DecodingKeys, KeycloakAuthInstance};
+use crate::layer::KeycloakAuthLayer;
+use crate::metrics::{AuthMetrics, NoopAuthMetrics};
 use crate::role::ExpectRoles;
 use crate::role::KeycloakRole;
 use crate::role::NumRoles;
+use crate::role::RequiredRolesMode;
+use crate::role::RoleExpansion;
+use crate::role::RoleMatching;
+use crate::role::UnknownRoles;
+use crate::role::{push_permission, push_role};
 
 use super::{error::AuthError, role::ExtractRoles, role::Role};
 
 pub type RawClaims = HashMap<String, serde_json::Value>;
 
+/// Target of the tracing event emitted whenever a token was only validated after refreshing the decoding keys.
+/// Count these events (e.g. with a `tracing_subscriber` filter on this target) to monitor key rotations.
+pub const KEY_REFRESH_TRACING_TARGET: &str = "axum_keycloak_auth::key_refresh";
+
+/// The compact (serialized) JWT a request was authenticated with, e.g. to relay it to a downstream service.
+/// Stored as an `Extension` when `KeycloakAuthLayer::persist_raw_token` is set.
+///
+/// Its `Debug` representation is redacted, so that the token does not end up in logs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RawJwt(String);
+
+impl RawJwt {
+    pub(crate) fn new(raw_token: String) -> Self {
+        Self(raw_token)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl std::fmt::Debug for RawJwt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RawJwt(<redacted>)")
+    }
+}
+
 pub(crate) struct RawToken<'a>(pub(crate) &'a str);
 
 impl<'a> RawToken<'a> {
     pub(crate) fn decode_header(&self) -> Result<jsonwebtoken::Header, AuthError> {
-        let jwt_header = jsonwebtoken::decode_header(self.0).context(DecodeHeaderSnafu {})?;
+        // Defense in depth: jsonwebtoken can not represent algorithms like "none" and fails to decode such headers.
+        // We do not want to rely on that, and report such tokens distinctly.
+        let jwt_header =
+            jsonwebtoken::decode_header(self.0).map_err(|source| {
+                match peek_algorithm(self.0).filter(|alg| Algorithm::from_str(alg).is_err()) {
+                    Some(algorithm) => AuthError::DisallowedAlgorithm { algorithm },
+                    None => AuthError::DecodeHeader { source },
+                }
+            })?;
         tracing::debug!(?jwt_header, "Decoded JWT header");
         Ok(jwt_header)
     }
@@ -34,10 +80,17 @@ impl<'a> RawToken<'a> {
         &self,
         header: &jsonwebtoken::Header,
         expected_audiences: &[String],
+        expected_issuers: &[String],
         decoding_keys: impl Iterator<Item = &'d jsonwebtoken::DecodingKey>,
+        customize_validation: Option<&CustomizeValidation>,
     ) -> Result<RawClaims, AuthError> {
         let mut validation = jsonwebtoken::Validation::new(header.alg);
 
+        // jsonwebtoken always reads the system time. Expiry and not-before time are instead validated
+        // against the `KeycloakConfig::clock` once the claims are parsed (see `KeycloakToken::assert_active_at`).
+        // The 'exp' claim is still required.
+        validation.validate_exp = false;
+        validation.validate_nbf = false;
 
         if !expected_audiences.is_empty() {
             validation.set_audience(expected_audiences);
@@ -47,6 +100,14 @@ impl<'a> RawToken<'a> {
             validation.validate_aud = false;
         }
 
+        if !expected_issuers.is_empty() {
+            validation.set_issuer(expected_issuers);
+        }
+
+        if let Some(customize_validation) = customize_validation {
+            customize_validation(&mut validation);
+        }
+
         let mut token_data: Result<
             jsonwebtoken::TokenData<HashMap<String, serde_json::Value>>,
             AuthError,
@@ -54,51 +115,174 @@ impl<'a> RawToken<'a> {
         for key in decoding_keys {
             token_data =
                 jsonwebtoken::decode::<RawClaims>(self.0, key, &validation).context(DecodeSnafu {});
-            if token_data.is_ok() {
-                break;
+            match &token_data {
+                Err(AuthError::Decode { source }) if should_check_with_another_key(source) => {
+                    continue
+                }
+                _ => break,
             }
         }
-        let token_data = token_data?;
+        let token_data = token_data.map_err(|err| match err {
+            AuthError::Decode { source }
+                if *source.kind() == jsonwebtoken::errors::ErrorKind::InvalidIssuer =>
+            {
+                AuthError::InvalidIssuer
+            }
+            other => other,
+        })?;
         let raw_claims = token_data.claims;
         debug!(?raw_claims, "Decoded JWT data");
 
         Ok(raw_claims)
     }
+
+    /// Decodes the token using the given keys, unless none of them may be used with the algorithm announced in the header.
+    pub(crate) fn decode_and_validate_with(
+        &self,
+        header: &jsonwebtoken::Header,
+        expected_audiences: &[String],
+        expected_issuers: &[String],
+        decoding_keys: &DecodingKeys<'_>,
+        customize_validation: Option<&CustomizeValidation>,
+    ) -> Result<RawClaims, AuthError> {
+        if !decoding_keys.allow(header.alg) {
+            return Err(AuthError::DisallowedAlgorithm {
+                algorithm: format!("{:?}", header.alg),
+            });
+        }
+        self.decode_and_validate(
+            header,
+            expected_audiences,
+            expected_issuers,
+            decoding_keys.candidates(header.kid.as_deref()),
+            customize_validation,
+        )
+    }
+}
+
+/// Whether decoding with a different key might succeed.
+/// Errors not related to the key (for example an expired token) will not be resolved by trying further keys.
+fn should_check_with_another_key(err: &jsonwebtoken::errors::Error) -> bool {
+    matches!(
+        err.kind(),
+        jsonwebtoken::errors::ErrorKind::InvalidSignature
+            | jsonwebtoken::errors::ErrorKind::InvalidAlgorithm
+            | jsonwebtoken::errors::ErrorKind::InvalidKeyFormat
+            | jsonwebtoken::errors::ErrorKind::InvalidRsaKey(_)
+            | jsonwebtoken::errors::ErrorKind::InvalidEcdsaKey
+    )
+}
+
+/// Rejects tokens longer than `max_token_length` (see `KeycloakConfig::max_token_length`). Run this before decoding any part of a token.
+pub(crate) fn check_token_length(
+    raw_token: &str,
+    max_token_length: usize,
+) -> Result<(), AuthError> {
+    match raw_token.len() > max_token_length {
+        true => Err(AuthError::MalformedAuthorizationHeader {
+            reason: format!(
+                "The token is {} bytes long, exceeding the maximum of {max_token_length} bytes",
+                raw_token.len()
+            ),
+        }),
+        false => Ok(()),
+    }
 }
 
 pub(crate) async fn decode_and_validate(
     kc_instance: &KeycloakAuthInstance,
     raw_token: RawToken<'_>,
     expected_audiences: &[String],
+    expected_issuers: &[String],
+    metrics: &dyn AuthMetrics,
+) -> Result<RawClaims, AuthError> {
+    let raw_claims = decode_and_validate_with_retry(
+        kc_instance,
+        raw_token,
+        expected_audiences,
+        expected_issuers,
+        metrics,
+    )
+    .await;
+    match &raw_claims {
+        Ok(_) => metrics.decode_succeeded(),
+        Err(err) => metrics.decode_failed(err),
+    }
+    raw_claims
+}
+
+async fn decode_and_validate_with_retry(
+    kc_instance: &KeycloakAuthInstance,
+    raw_token: RawToken<'_>,
+    expected_audiences: &[String],
+    expected_issuers: &[String],
+    metrics: &dyn AuthMetrics,
 ) -> Result<RawClaims, AuthError> {
+    // Checked first, as even the header of a huge token would take large allocations to decode.
+    check_token_length(raw_token.0, kc_instance.config.max_token_length)?;
+
+    // Introspected tokens may be opaque. Keycloak decides about their validity.
+    if let Some(introspection) = &kc_instance.introspection {
+        let raw_claims = introspection
+            .introspect(raw_token.0, expected_audiences)
+            .await?;
+        return match raw_claims.get("iss") {
+            _ if expected_issuers.is_empty() => Ok(raw_claims),
+            Some(Value::String(iss)) if expected_issuers.contains(iss) => Ok(raw_claims),
+            _ => Err(AuthError::InvalidIssuer),
+        };
+    }
+
     let header = raw_token.decode_header()?;
 
+    // The header is not yet verified. Never let it pick an algorithm we did not agree to.
+    if let Some(allowed_algorithms) = &kc_instance.config.allowed_algorithms {
+        if !allowed_algorithms.contains(&header.alg) {
+            return Err(AuthError::DisallowedAlgorithm {
+                algorithm: format!("{:?}", header.alg),
+            });
+        }
+    }
+
     // First decode. This may fail if known decoding keys are out of date (for example if the Keycloak server changed).
-    let mut raw_claims = {
+    let (mut raw_claims, keys_version) = {
         let decoding_keys = kc_instance.decoding_keys().await;
-        raw_token.decode_and_validate(&header, expected_audiences, decoding_keys.iter())
+        let raw_claims = raw_token.decode_and_validate_with(
+            &header,
+            expected_audiences,
+            expected_issuers,
+            &decoding_keys,
+            kc_instance.config.customize_validation.as_ref(),
+        );
+        (raw_claims, decoding_keys.version())
     };
 
+    // Statically configured keys can not be refreshed. Retrying would not change the outcome.
+    if !kc_instance.uses_discovery() {
+        let raw_claims = match raw_claims {
+            Err(AuthError::Decode { source }) if should_check_with_another_key(&source) => {
+                Err(AuthError::NoMatchingDecodingKey)
+            }
+            other => other,
+        };
+        if let Err(err) = &raw_claims {
+            log_decode_failure(kc_instance, &header, err).await;
+        }
+        return raw_claims;
+    }
+
     if raw_claims.is_err() {
         // Reload decoding keys. This may delay handling of the request in flight by a substantial amount of time
         // but may allow us to acknowledge it in the end without rejecting the call immediately,
         // which would then require a retry from our caller!
         #[allow(clippy::unwrap_used)]
-            let retry = match raw_claims.as_ref().unwrap_err() {
-            AuthError::NoDecodingKeys => {
-                kc_instance.perform_oidc_discovery().await;
-                true
-            }
-            AuthError::Decode { source: _ } => {
-                let realm = kc_instance.config.realm.clone();
-                let keys = kc_instance.decoding_keys().await;
-                let decoding_key = keys.iter().next();
-
-                if contains_realm(decoding_key, raw_token.0, realm) {
-                    kc_instance.perform_oidc_discovery().await;
-                    true
-                } else {
-                    false
+        let retry = match raw_claims.as_ref().unwrap_err() {
+            AuthError::NoDecodingKeys => kc_instance.perform_oidc_discovery(keys_version).await,
+            // Keycloak may have switched to keys of another algorithm.
+            AuthError::Decode { source: _ } | AuthError::DisallowedAlgorithm { algorithm: _ } => {
+                match contains_realm(raw_token.0, &kc_instance.config.realm) {
+                    true => kc_instance.perform_oidc_discovery(keys_version).await,
+                    false => false,
                 }
             }
             _ => false,
@@ -106,78 +290,392 @@ pub(crate) async fn decode_and_validate(
 
         // Second decode
         if retry {
+            metrics.keys_refreshed(&kc_instance.config.realm);
             let decoding_keys = kc_instance.decoding_keys().await;
 
-            raw_claims = raw_token.decode_and_validate(&header, expected_audiences, decoding_keys.iter());
+            raw_claims = raw_token.decode_and_validate_with(
+                &header,
+                expected_audiences,
+                expected_issuers,
+                &decoding_keys,
+                kc_instance.config.customize_validation.as_ref(),
+            );
+            if raw_claims.is_ok() {
+                tracing::Span::current().record("key_refreshed", true);
+                tracing::info!(
+                    target: KEY_REFRESH_TRACING_TARGET,
+                    kid = header.kid,
+                    realm = kc_instance.config.realm,
+                    "Token was only validated after refreshing the decoding keys."
+                );
+                metrics.decode_succeeded_after_refresh(&kc_instance.config.realm);
+            }
         }
     }
 
+    if let Err(err) = &raw_claims {
+        log_decode_failure(kc_instance, &header, err).await;
+    }
     raw_claims
 }
 
-fn contains_realm(
-    key: Option<&DecodingKey>,
+/// Decodes the given token, validating only its signature and issuer. See `KeycloakAuthInstance::decode_unverified_claims`.
+pub(crate) async fn decode_unenforced<Extra: DeserializeOwned>(
+    kc_instance: &KeycloakAuthInstance,
     token: &str,
-    realm: String) -> bool {
+) -> Result<StandardClaims<Extra>, AuthError> {
+    // Introspection only reports claims of tokens it still considers active.
+    if kc_instance.introspection.is_some() {
+        return Err(AuthError::InvalidConfiguration {
+            reason: String::from("Tokens can not be inspected by an introspecting instance."),
+        });
+    }
+    // Expiry and not-before time are never checked by `decode_and_validate` itself.
+    let raw_claims = decode_and_validate(
+        kc_instance,
+        RawToken(token),
+        &[],
+        &[kc_instance.issuer().to_owned()],
+        &NoopAuthMetrics {},
+    )
+    .await?;
+    serde_json::from_value(Value::from_iter(raw_claims)).map_err(|err| AuthError::JsonParse {
+        source: Arc::new(err),
+    })
+}
+
+/// Logs why a token could not be decoded, including the key ID (`kid`) it requested and the IDs of all known keys.
+/// Logged at debug level only, as anyone can send tokens failing to decode.
+/// Note: Neither the token nor its signature may be logged here.
+async fn log_decode_failure(
+    kc_instance: &KeycloakAuthInstance,
+    header: &jsonwebtoken::Header,
+    err: &AuthError,
+) {
+    // Spares enumerating the known keys for every rejected token.
+    if !tracing::enabled!(tracing::Level::DEBUG) {
+        return;
+    }
+    let key_related = match err {
+        AuthError::NoDecodingKeys | AuthError::NoMatchingDecodingKey => true,
+        AuthError::Decode { source } => should_check_with_another_key(source),
+        _ => false,
+    };
+    let known_kids = kc_instance.key_ids().await;
+    let kid_known = header
+        .kid
+        .as_ref()
+        .is_some_and(|kid| known_kids.contains(kid));
+    let err = err.to_string();
+    let message = match key_related {
+        true => "Token could not be verified with any of the known decoding keys.",
+        false => "Token was rejected.",
+    };
+    tracing::debug!(
+        kid = ?header.kid,
+        kid_known,
+        ?known_kids,
+        alg = ?header.alg,
+        err,
+        message
+    );
+}
+
+fn contains_realm(token: &str, realm: &str) -> bool {
+    peek_issuer(token).is_some_and(|iss| iss.ends_with(format!("/{}", realm).as_str()))
+}
 
-    if key.is_none() {
-        return false;
+/// Reads the 'alg' parameter of the header of the given token WITHOUT validating the token in any way.
+fn peek_algorithm(token: &str) -> Option<String> {
+    use base64::Engine;
+
+    #[derive(Deserialize)]
+    struct AlgorithmOnly {
+        alg: String,
     }
 
-    let mut validation = jsonwebtoken::Validation::new(Algorithm::HS256);
+    let header = token.split('.').next()?;
+    let header = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(header)
+        .ok()?;
+    serde_json::from_slice::<AlgorithmOnly>(&header)
+        .ok()
+        .map(|it| it.alg)
+}
 
+/// Reads the 'iss' claim of the given token WITHOUT validating the token in any way.
+/// Only use the result to decide how to validate the token.
+pub(crate) fn peek_issuer(token: &str) -> Option<String> {
+    let mut validation = jsonwebtoken::Validation::new(Algorithm::HS256);
+    // We only want to peek at the issuer. The token is validated when actually decoding it.
     validation.insecure_disable_signature_validation();
+    validation.validate_aud = false;
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
 
+    // Note: The key is not used, as signature validation is disabled.
     let token_data =
-        jsonwebtoken::decode::<RawClaims>(token, key.unwrap(), &validation).context(DecodeSnafu {});
-
-    if let Ok(t) = token_data {
-        let iss = t.claims.get("iss");
+        jsonwebtoken::decode::<RawClaims>(token, &DecodingKey::from_secret(&[]), &validation)
+            .ok()?;
+    match token_data.claims.get("iss") {
+        Some(Value::String(iss)) => Some(iss.to_owned()),
+        _ => None,
+    }
+}
 
-        if let Some(iss_val) = iss {
-            match iss_val {
-                Value::String(iss) => {
-                    let has_realm = iss.as_str().ends_with(format!("\\{}", realm).as_str());
+/// Outcome of a successful validation, see `parse_raw_claims`.
+#[derive(Clone)]
+pub(crate) struct ValidatedToken<R: Role, Extra: DeserializeOwned + Clone> {
+    /// The raw claims to be persisted, as configured through `KeycloakAuthLayer::persist_raw_claims`.
+    pub(crate) raw_claims: Option<RawClaims>,
+    pub(crate) token: KeycloakToken<R, Extra>,
+}
 
-                    if has_realm {
-                        return true;
-                    }
-                },
-                _ => {},
+pub(crate) async fn parse_raw_claims<R, Extra>(
+    mut raw_claims: RawClaims,
+    layer: &KeycloakAuthLayer<R, Extra>,
+    kc_instance: &KeycloakAuthInstance,
+) -> Result<ValidatedToken<R, Extra>, AuthError>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    let raw_claims_clone = match (layer.persist_raw_claims, &layer.persist_raw_claim_keys) {
+        (true, _) => Some(raw_claims.clone()),
+        (false, keys) if keys.is_empty() => None,
+        (false, keys) => Some(
+            keys.iter()
+                .filter_map(|key| raw_claims.get_key_value(key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        ),
+    };
+    let tenant_id = match &layer.tenant_claim {
+        Some(claim) => match raw_claims.get(claim) {
+            Some(Value::String(tenant_id)) => Some(tenant_id.clone()),
+            _ => {
+                return Err(AuthError::MissingClaim {
+                    claim: claim.clone(),
+                })
             }
+        },
+        None => None,
+    };
+    if let (Some(clients), Some(Value::Object(resource_access))) =
+        (&layer.role_clients, raw_claims.get_mut("resource_access"))
+    {
+        resource_access.retain(|client, _| clients.contains(client));
+    }
+    let value = serde_json::Value::from_iter(raw_claims);
+    let claimed_roles = kc_instance
+        .config
+        .role_claims
+        .iter()
+        .filter(
+            |role_claim| match (&role_claim.client, &layer.role_clients) {
+                (Some(client), Some(clients)) => clients.contains(client),
+                _ => true,
+            },
+        )
+        .map(|role_claim| (role_claim.client.clone(), role_claim.read(&value)))
+        .collect::<Vec<_>>();
+
+    let standard_claims = parse_standard_claims(&value, layer.extra_fallback)?;
+    let mut keycloak_token = KeycloakToken::<R, Extra>::parse(
+        standard_claims,
+        layer.role_matching,
+        layer.unknown_roles,
+    )?;
+    keycloak_token.tenant_id = tenant_id;
+    for (client, roles) in claimed_roles {
+        keycloak_token.add_roles(client, roles, layer.unknown_roles);
+    }
+    // Parsed on its own, so that the claim is left untouched (e.g. for `Extra`) and only ever parsed if requested.
+    if layer.uma_permissions {
+        if let Some(authorization) = value.get("authorization") {
+            let authorization =
+                Authorization::deserialize(authorization).map_err(|err| AuthError::JsonParse {
+                    source: Arc::new(err),
+                })?;
+            keycloak_token.add_uma_permissions(authorization, layer.unknown_roles);
+        }
+    }
+    keycloak_token.add_scope_roles(&layer.roles_from_scopes);
+    if let Some(role_expansion) = &layer.role_expansion {
+        keycloak_token.expand_roles(role_expansion);
+    }
+    let now = kc_instance.config.clock.now();
+    assert_current(&keycloak_token, layer, now, kc_instance.config.leeway)?;
+    if kc_instance.config.validate_issued_at {
+        let skew = kc_instance
+            .config
+            .issued_at_skew
+            .unwrap_or(kc_instance.config.leeway);
+        if let Some(issued_at) = keycloak_token.issued_at.filter(|iat| *iat > now + skew) {
+            return Err(AuthError::InvalidToken {
+                reason: format!("The token was issued in the future, at {issued_at}"),
+            });
+        }
+    }
+    if let Some(expected_token_type) = &layer.expected_token_type {
+        if &keycloak_token.token_type != expected_token_type {
+            return Err(AuthError::UnexpectedTokenType {
+                token_type: keycloak_token.token_type,
+            });
+        }
+    }
+    if !layer.expected_audiences.is_empty() {
+        let present = keycloak_token.audiences();
+        if !layer
+            .audience_matching
+            .matches(&layer.expected_audiences, present)
+        {
+            return Err(AuthError::UnexpectedAudience {
+                required: layer.expected_audiences.clone(),
+                present: present.to_vec(),
+            });
         }
     }
-    false
+    if let Some(minimum_acr) = &layer.minimum_acr {
+        if !keycloak_token.meets_acr(minimum_acr) {
+            return Err(AuthError::InsufficientAuthenticationLevel {
+                required: minimum_acr.clone(),
+                present: keycloak_token.authentication_context,
+            });
+        }
+    }
+    if let Some(client_id) = &layer.client_id {
+        if !keycloak_token.has_audience(client_id) && keycloak_token.authorized_party != *client_id
+        {
+            return Err(AuthError::NotIssuedForClient {
+                client_id: client_id.clone(),
+            });
+        }
+    }
+    if let Some(email_verified) = layer.require_verified_email {
+        if email_verified(&keycloak_token.extra) != Some(true) {
+            return Err(AuthError::EmailNotVerified);
+        }
+    }
+    if !layer.allowed_authorized_parties.is_empty()
+        && !layer
+            .allowed_authorized_parties
+            .contains(&keycloak_token.authorized_party)
+    {
+        return Err(AuthError::UnexpectedAuthorizedParty {
+            authorized_party: keycloak_token.authorized_party,
+        });
+    }
+    let role_check = match layer.reject_tokens_without_roles && !keycloak_token.has_any_roles() {
+        true => Err(AuthError::NoRoles),
+        false => match layer.required_roles_mode {
+            RequiredRolesMode::All => keycloak_token.expect_roles(&layer.required_roles),
+            RequiredRolesMode::Any => keycloak_token.expect_any_role(&layer.required_roles),
+        },
+    }
+    .and_then(|()| {
+        layer
+            .required_scoped_roles
+            .iter()
+            .try_for_each(|required| keycloak_token.expect_scoped_role(required))
+    });
+    if let Err(err) = role_check {
+        layer.metrics.role_check_failed(&err);
+        return Err(err);
+    }
+    keycloak_token.expect_scopes(&layer.required_scopes)?;
+    if let Some(missing) = layer
+        .required_groups
+        .iter()
+        .find(|group| !keycloak_token.in_group_matching(group, layer.group_matching))
+    {
+        return Err(AuthError::MissingExpectedGroup {
+            group: missing.to_owned(),
+        });
+    }
+    (layer.validate_extra)(&keycloak_token.extra)?;
+    // Performed last, as this may require a lookup in a remote store.
+    assert_not_revoked(&keycloak_token, layer).await?;
+    Ok(ValidatedToken {
+        raw_claims: raw_claims_clone,
+        token: keycloak_token,
+    })
 }
 
-pub(crate) async fn parse_raw_claims<R, Extra>(
-    raw_claims: RawClaims,
-    persist_raw_claims: bool,
-    required_roles: &[R],
-) -> Result<
-    (
-        Option<HashMap<String, serde_json::Value>>,
-        KeycloakToken<R, Extra>,
-    ),
-    AuthError,
->
+/// Checks whether the token is active at `now`, not older than the `max_token_age`
+/// and its user not authenticated longer ago than the `max_auth_age` of the layer.
+/// Repeated on every use of a cached token, as these checks depend on the time of use.
+pub(crate) fn assert_current<R, Extra>(
+    keycloak_token: &KeycloakToken<R, Extra>,
+    layer: &KeycloakAuthLayer<R, Extra>,
+    now: time::OffsetDateTime,
+    leeway: Duration,
+) -> Result<(), AuthError>
 where
     R: Role,
     Extra: DeserializeOwned + Clone,
 {
-    let raw_claims_clone = match persist_raw_claims {
-        true => Some(raw_claims.clone()),
-        false => None,
-    };
-    let value = serde_json::Value::from_iter(raw_claims.into_iter());
+    keycloak_token.assert_active_at(now, leeway)?;
+    if let Some(max_token_age) = layer.max_token_age {
+        let age = keycloak_token
+            .age_at(now)
+            .ok_or_else(|| AuthError::MissingClaim {
+                claim: String::from("iat"),
+            })?;
+        if age > max_token_age + leeway {
+            return Err(AuthError::TokenTooOld);
+        }
+    }
+    if let Some(max_auth_age) = layer.max_auth_age {
+        let auth_time = keycloak_token
+            .auth_time
+            .ok_or_else(|| AuthError::MissingClaim {
+                claim: String::from("auth_time"),
+            })?;
+        if now - auth_time > max_auth_age + leeway {
+            return Err(AuthError::AuthenticationTooOld);
+        }
+    }
+    Ok(())
+}
 
-    let standard_claims = serde_json::from_value(value).map_err(|err| AuthError::JsonParse {
+/// Parses the standard and extra claims. Should only the extra claims be malformed,
+/// they are replaced by the `extra_fallback` (see `KeycloakAuthLayer::default_extra_on_error`) if one is set.
+fn parse_standard_claims<Extra: DeserializeOwned>(
+    value: &Value,
+    extra_fallback: Option<fn() -> Extra>,
+) -> Result<StandardClaims<Extra>, AuthError> {
+    let err = match StandardClaims::<Extra>::deserialize(value) {
+        Ok(standard_claims) => return Ok(standard_claims),
+        Err(err) => err,
+    };
+    if let Some(extra_fallback) = extra_fallback {
+        // If the standard claims alone can be parsed, the extra claims are at fault.
+        if let Ok(standard_claims) = StandardClaims::<NoExtra>::deserialize(value) {
+            tracing::warn!(%err, "Could not parse the extra claims of a token, falling back to their default");
+            return Ok(standard_claims.with_extra(extra_fallback()));
+        }
+    }
+    Err(AuthError::JsonParse {
         source: Arc::new(err),
-    })?;
-    let keycloak_token = KeycloakToken::<R, Extra>::parse(standard_claims)?;
-    keycloak_token.assert_not_expired()?;
-    keycloak_token.expect_roles(required_roles)?;
-    Ok((raw_claims_clone, keycloak_token))
+    })
+}
+
+/// Consults the `jti_blocklist` of the layer. Repeated on every use of a cached token.
+pub(crate) async fn assert_not_revoked<R, Extra>(
+    keycloak_token: &KeycloakToken<R, Extra>,
+    layer: &KeycloakAuthLayer<R, Extra>,
+) -> Result<(), AuthError>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    if let Some(jti_blocklist) = &layer.jti_blocklist {
+        if jti_blocklist.is_revoked(&keycloak_token.jwt_id).await {
+            return Err(AuthError::TokenRevoked);
+        }
+    }
+    Ok(())
 }
 
 #[serde_as]
@@ -185,8 +683,12 @@ where
 pub struct StandardClaims<Extra> {
     /// Expiration time (unix timestamp).
     pub exp: Option<i64>,
-    /// Issued at time (unix timestamp).
-    pub iat: i64,
+    /// Not before time (unix timestamp). The token must not be accepted before this point in time.
+    pub nbf: Option<i64>,
+    /// Issued at time (unix timestamp). Omitted by some token issuers.
+    pub iat: Option<i64>,
+    /// Time the user authenticated at (unix timestamp). Keycloak omits it for tokens not backed by a user login.
+    pub auth_time: Option<i64>,
     /// JWT ID (unique identifier for this token).
     pub jti: String,
     /// Issuer (who created and signed this token). This is the UUID which uniquely identifies this user inside Keycloak.
@@ -200,6 +702,12 @@ pub struct StandardClaims<Extra> {
     pub typ: String,
     /// Authorized party (the party to which this token was issued).
     pub azp: String,
+    /// Space-delimited list of scopes granted to the token, e.g. "openid profile email".
+    pub scope: Option<String>,
+    /// Authentication context class reference, e.g. "1" for a password login in Keycloak.
+    pub acr: Option<String>,
+    /// Authentication methods references (RFC 8176), e.g. ["pwd", "otp"].
+    pub amr: Option<Vec<String>>,
 
     /// Keycloak: Optional realm roles from Keycloak.
     pub realm_access: Option<RealmAccess>,
@@ -211,6 +719,34 @@ pub struct StandardClaims<Extra> {
     pub extra: Extra,
 }
 
+/// Accepts any extra claims, used to parse the standard claims on their own.
+#[derive(Deserialize)]
+struct NoExtra {}
+
+impl StandardClaims<NoExtra> {
+    fn with_extra<Extra>(self, extra: Extra) -> StandardClaims<Extra> {
+        StandardClaims {
+            exp: self.exp,
+            nbf: self.nbf,
+            iat: self.iat,
+            auth_time: self.auth_time,
+            jti: self.jti,
+            iss: self.iss,
+            aud: self.aud,
+            sub: self.sub,
+            typ: self.typ,
+            azp: self.azp,
+            scope: self.scope,
+            acr: self.acr,
+            amr: self.amr,
+            realm_access: self.realm_access,
+            resource_access: self.resource_access,
+            groups: self.groups,
+            extra,
+        }
+    }
+}
+
 /// Access details.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Access {
@@ -224,63 +760,251 @@ pub struct RealmAccess(pub Access);
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResourceAccess(pub HashMap<String, Access>);
 
+/// UMA permissions ('authorization' claim), as issued in requesting party tokens by the Keycloak authorization services.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Authorization {
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+}
+
+/// The scopes granted on a single resource.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Permission {
+    /// ID of the resource.
+    pub rsid: Option<String>,
+    /// Name of the resource, e.g. "document:42".
+    pub rsname: Option<String>,
+    /// The scopes granted on the resource, e.g. ["view", "delete"].
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+impl Access {
+    fn num_distinct_roles(&self) -> usize {
+        self.roles.iter().collect::<HashSet<_>>().len()
+    }
+}
+
 impl NumRoles for RealmAccess {
     fn num_roles(&self) -> usize {
-        self.0.roles.len()
+        self.0.num_distinct_roles()
     }
 }
 
 impl NumRoles for ResourceAccess {
     fn num_roles(&self) -> usize {
-        self.0.values().map(|access| access.roles.len()).sum()
+        self.0.values().map(Access::num_distinct_roles).sum()
+    }
+}
+
+impl NumRoles for Authorization {
+    fn num_roles(&self) -> usize {
+        self.permissions
+            .iter()
+            .map(|permission| permission.scopes.iter().collect::<HashSet<_>>().len())
+            .sum()
     }
 }
 
 impl<R: Role> ExtractRoles<R> for RealmAccess {
     fn extract_roles(self, target: &mut Vec<KeycloakRole<R>>) {
+        self.extract_roles_into(target, None);
+    }
+
+    fn extract_roles_into(
+        self,
+        target: &mut Vec<KeycloakRole<R>>,
+        mut unknown: Option<&mut Vec<KeycloakRole<String>>>,
+    ) {
         for role in self.0.roles {
-            target.push(KeycloakRole::Realm { role: role.into() });
+            push_role(target, unknown.as_deref_mut(), None, role);
         }
     }
 }
 
 impl<R: Role> ExtractRoles<R> for ResourceAccess {
     fn extract_roles(self, target: &mut Vec<KeycloakRole<R>>) {
-        for (res_name, access) in &self.0 {
-            for role in &access.roles {
-                target.push(KeycloakRole::Client {
-                    client: res_name.to_owned(),
-                    role: role.to_owned().into(),
-                });
+        self.extract_roles_into(target, None);
+    }
+
+    fn extract_roles_into(
+        self,
+        target: &mut Vec<KeycloakRole<R>>,
+        mut unknown: Option<&mut Vec<KeycloakRole<String>>>,
+    ) {
+        for (res_name, access) in self.0 {
+            for role in access.roles {
+                push_role(target, unknown.as_deref_mut(), Some(res_name.clone()), role);
+            }
+        }
+    }
+}
+
+/// Extracts a `KeycloakRole::Scope` for each scope of each permission, on the resource named by 'rsname', or by 'rsid' if unnamed.
+impl<R: Role> ExtractRoles<R> for Authorization {
+    fn extract_roles(self, target: &mut Vec<KeycloakRole<R>>) {
+        self.extract_roles_into(target, None);
+    }
+
+    fn extract_roles_into(
+        self,
+        target: &mut Vec<KeycloakRole<R>>,
+        mut unknown: Option<&mut Vec<KeycloakRole<String>>>,
+    ) {
+        for permission in self.permissions {
+            let resource = permission.rsname.or(permission.rsid);
+            for scope in permission.scopes {
+                push_permission(target, unknown.as_deref_mut(), resource.clone(), scope);
+            }
+        }
+    }
+}
+
+/// Determines how the audiences ('aud' claim) of a token must relate to the expected audiences of the layer.
+/// Not applied if no audiences are expected.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AudienceMatching {
+    /// At least one of the expected audiences must be present.
+    #[default]
+    AnyOf,
+
+    /// All of the expected audiences must be present. Further audiences are allowed.
+    AllOf,
+
+    /// The token must carry exactly the expected audiences (in any order), no more and no less.
+    Exact,
+}
+
+impl AudienceMatching {
+    pub fn matches(&self, expected: &[String], present: &[String]) -> bool {
+        match self {
+            AudienceMatching::AnyOf => expected.iter().any(|aud| present.contains(aud)),
+            AudienceMatching::AllOf => expected.iter().all(|aud| present.contains(aud)),
+            AudienceMatching::Exact => {
+                expected.iter().all(|aud| present.contains(aud))
+                    && present.iter().all(|aud| expected.contains(aud))
             }
         }
     }
 }
 
+/// `expires_at` of tokens without an 'exp' claim.
+const NEVER_EXPIRES: time::OffsetDateTime = time::PrimitiveDateTime::MAX.assume_utc();
+
+/// Determines how group memberships are checked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GroupMatching {
+    /// The user must be a member of exactly the given group.
+    #[default]
+    Exact,
+
+    /// The user must be a member of the given group or of any of its (nested) subgroups.
+    /// Note: Keycloak puts only the groups a user was directly assigned to into the token.
+    /// With this, a member of "/engineering/backend" is also considered a member of "/engineering".
+    IncludeSubgroups,
+
+    /// The given group is a pattern the path of a group of the user must match, e.g. "/org/*/admins".
+    /// Patterns support this subset of globs: A segment of `*` matches any one path segment,
+    /// a segment of `**` matches any number of path segments (including none). All other segments must match exactly,
+    /// so `*` within a segment (like "/org/team-*") is taken literally. Matching allocates nothing.
+    Glob,
+}
+
+fn normalize_group(group: &str) -> &str {
+    group.trim_matches('/')
+}
+
+/// Matches the remaining path segments against the remaining pattern segments, see `GroupMatching::Glob`.
+fn glob_matches(
+    mut pattern: std::str::Split<'_, char>,
+    mut path: std::str::Split<'_, char>,
+) -> bool {
+    loop {
+        match pattern.next() {
+            None => return path.next().is_none(),
+            Some("**") => loop {
+                if glob_matches(pattern.clone(), path.clone()) {
+                    return true;
+                }
+                if path.next().is_none() {
+                    return false;
+                }
+            },
+            Some(expected) => match path.next() {
+                Some(segment) if expected == "*" || expected == segment => {}
+                _ => return false,
+            },
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize),
+    serde(bound(serialize = "R: serde::Serialize, Extra: serde::Serialize"))
+)]
 pub struct KeycloakToken<R, Extra = ProfileAndEmail>
 where
     R: Role,
     Extra: DeserializeOwned + Clone,
 {
     /// Expiration time (UTC).
+    #[cfg_attr(
+        feature = "serialize",
+        serde(serialize_with = "time::serde::rfc3339::serialize")
+    )]
     pub expires_at: time::OffsetDateTime,
-    /// Issued at time (UTC).
-    pub issued_at: time::OffsetDateTime,
+    /// Not before time (UTC). The token must not be accepted before this point in time.
+    #[cfg_attr(
+        feature = "serialize",
+        serde(serialize_with = "time::serde::rfc3339::option::serialize")
+    )]
+    pub not_before: Option<time::OffsetDateTime>,
+    /// Issued at time (UTC). `None` if the token carries no 'iat' claim.
+    #[cfg_attr(
+        feature = "serialize",
+        serde(serialize_with = "time::serde::rfc3339::option::serialize")
+    )]
+    pub issued_at: Option<time::OffsetDateTime>,
+    /// Time (UTC) the user actually authenticated at. May lie well before `issued_at`, as refreshed tokens keep it.
+    /// `None` if the token carries no 'auth_time' claim.
+    #[cfg_attr(
+        feature = "serialize",
+        serde(serialize_with = "time::serde::rfc3339::option::serialize")
+    )]
+    pub auth_time: Option<time::OffsetDateTime>,
     /// JWT ID (unique identifier for this token).
     pub jwt_id: String,
     /// Issuer (who created and signed this token).
     pub issuer: String,
-    /// Audience (who or what the token is intended for).
+    /// Audience (who or what the token is intended for). Single string 'aud' claims are normalized into a one-element list.
+    /// `None` if the token carries no 'aud' claim, `Some(vec![])` if it is an empty array. See `audiences` to not distinguish the two.
     pub audience: Option<Vec<String>>,
     /// Subject (whom the token refers to). This is the UUID which uniquely identifies this user inside Keycloak.
     pub subject: String,
     /// Authorized party (the party to which this token was issued).
     pub authorized_party: String,
+    /// Type of token. Keycloak issues access tokens of type "Bearer".
+    pub token_type: String,
+    /// Scopes granted to the token, parsed from the space-delimited 'scope' claim. Empty if the claim is absent.
+    pub scopes: Vec<String>,
+    /// Authentication context class reference ('acr' claim): How strongly the user authenticated.
+    /// Keycloak asserts the level of authentication ("0", "1", "2", ...) configured through its step-up authentication flows.
+    pub authentication_context: Option<String>,
+    /// Authentication methods references ('amr' claim), e.g. ["pwd", "otp"]. Only present if mapped by your Keycloak realm.
+    pub authentication_methods: Option<Vec<String>>,
 
     // Keycloak: Roles of the user.
     pub roles: Vec<KeycloakRole<R>>,
+    /// Roles of the user which could not be converted into `R`, by their raw name.
+    /// Only filled when the `KeycloakAuthLayer` was configured with `UnknownRoles::Collect`.
+    pub unknown_roles: Vec<KeycloakRole<String>>,
+    /// How `roles` are matched when using the `ExpectRoles` methods. Set from the `KeycloakAuthLayer` configuration.
+    pub role_matching: RoleMatching,
     pub groups: Option<Vec<String>>,
+    /// Tenant of the token, read from the claim configured as `KeycloakAuthLayer::tenant_claim`. `None` if not configured.
+    pub tenant_id: Option<String>,
 
     pub extra: Extra,
 }
@@ -290,80 +1014,547 @@ where
     R: Role,
     Extra: DeserializeOwned + Clone,
 {
-    pub(crate) fn parse(raw: StandardClaims<Extra>) -> Result<Self, AuthError> {
+    pub(crate) fn parse(
+        raw: StandardClaims<Extra>,
+        role_matching: RoleMatching,
+        unknown_roles: UnknownRoles,
+    ) -> Result<Self, AuthError> {
+        let mut roles = Vec::new();
+        let mut unknown = match unknown_roles {
+            UnknownRoles::Ignore => None,
+            UnknownRoles::Collect => Some(Vec::new()),
+        };
+        (raw.realm_access, raw.resource_access).extract_roles_into(&mut roles, unknown.as_mut());
         Ok(Self {
-            expires_at: time::OffsetDateTime::from_unix_timestamp(raw.exp.map_or(i64::MAX, |x| x)).map_err(|err| {
-                AuthError::InvalidToken {
+            expires_at: raw
+                .exp
+                .map_or(Ok(NEVER_EXPIRES), time::OffsetDateTime::from_unix_timestamp)
+                .map_err(|err| AuthError::InvalidToken {
                     reason: format!(
                         "Could not parse 'exp' (expires_at) field as unix timestamp: {err}"
                     ),
-                }
-            })?,
-            issued_at: time::OffsetDateTime::from_unix_timestamp(raw.iat).map_err(|err| {
-                AuthError::InvalidToken {
+                })?,
+            not_before: raw
+                .nbf
+                .map(time::OffsetDateTime::from_unix_timestamp)
+                .transpose()
+                .map_err(|err| AuthError::InvalidToken {
+                    reason: format!(
+                        "Could not parse 'nbf' (not_before) field as unix timestamp: {err}"
+                    ),
+                })?,
+            issued_at: raw
+                .iat
+                .map(time::OffsetDateTime::from_unix_timestamp)
+                .transpose()
+                .map_err(|err| AuthError::InvalidToken {
                     reason: format!(
                         "Could not parse 'iat' (issued_at) field as unix timestamp: {err}"
                     ),
-                }
-            })?,
+                })?,
+            auth_time: raw
+                .auth_time
+                .map(time::OffsetDateTime::from_unix_timestamp)
+                .transpose()
+                .map_err(|err| AuthError::InvalidToken {
+                    reason: format!("Could not parse 'auth_time' field as unix timestamp: {err}"),
+                })?,
             jwt_id: raw.jti,
             issuer: raw.iss,
             audience: raw.aud,
             subject: raw.sub,
             authorized_party: raw.azp,
-            roles: {
-                let mut roles = Vec::new();
-                (raw.realm_access, raw.resource_access).extract_roles(&mut roles);
-                roles
-            },
+            token_type: raw.typ,
+            authentication_context: raw.acr,
+            authentication_methods: raw.amr,
+            scopes: raw
+                .scope
+                .map(|scope| scope.split_whitespace().map(str::to_owned).collect())
+                .unwrap_or_default(),
+            roles,
+            unknown_roles: unknown.unwrap_or_default(),
+            role_matching,
             groups: raw.groups,
+            tenant_id: None,
             extra: raw.extra,
         })
     }
 
+    /// Adds the given raw roles of the given client (or the realm if `None`), see `KeycloakConfig::role_claims`.
+    pub(crate) fn add_roles(
+        &mut self,
+        client: Option<String>,
+        roles: Vec<String>,
+        unknown_roles: UnknownRoles,
+    ) {
+        let mut unknown = match unknown_roles {
+            UnknownRoles::Ignore => None,
+            UnknownRoles::Collect => Some(&mut self.unknown_roles),
+        };
+        for role in roles {
+            push_role(
+                &mut self.roles,
+                unknown.as_deref_mut(),
+                client.clone(),
+                role,
+            );
+        }
+    }
+
+    /// Adds the scopes granted by the given UMA permissions as `KeycloakRole::Scope` roles. See `KeycloakAuthLayer::uma_permissions`.
+    pub(crate) fn add_uma_permissions(
+        &mut self,
+        authorization: Authorization,
+        unknown_roles: UnknownRoles,
+    ) {
+        let unknown = match unknown_roles {
+            UnknownRoles::Ignore => None,
+            UnknownRoles::Collect => Some(&mut self.unknown_roles),
+        };
+        authorization.extract_roles_into(&mut self.roles, unknown);
+    }
+
+    /// Adds the given scopes as `KeycloakRole::Scope` roles, if they were granted to the token. See `KeycloakAuthLayer::roles_from_scopes`.
+    pub(crate) fn add_scope_roles(&mut self, scopes: &[String]) {
+        for scope in scopes {
+            if !self.scopes.contains(scope) {
+                continue;
+            }
+            if let Ok(scope) = R::try_from(scope.clone()) {
+                let role = KeycloakRole::Scope {
+                    resource: None,
+                    scope,
+                };
+                if !self.roles.contains(&role) {
+                    self.roles.push(role);
+                }
+            }
+        }
+    }
+
+    /// Adds the roles derived by `expansion` from the current roles, in the scope of the role they were derived from.
+    pub(crate) fn expand_roles(&mut self, expansion: &RoleExpansion<R>) {
+        let mut expanded: Vec<KeycloakRole<R>> = Vec::with_capacity(self.roles.len());
+        for role in &self.roles {
+            // Permissions on a resource do not imply roles.
+            let derived = match role.is_resource_scoped() {
+                true => Vec::new(),
+                false => expansion(role),
+            }
+            .into_iter()
+            .map(|derived| role.in_same_scope(derived));
+            for role in std::iter::once(role.clone()).chain(derived) {
+                if !expanded.contains(&role) {
+                    expanded.push(role);
+                }
+            }
+        }
+        self.roles = expanded;
+    }
+
+    /// Returns true if the token carries no 'exp' claim, in which case `expires_at` is set to the largest representable time.
+    pub fn never_expires(&self) -> bool {
+        self.expires_at == NEVER_EXPIRES
+    }
+
+    /// How long the token remains valid (`expires_at - now`), e.g. to decide whether it should be refreshed proactively.
+    /// `None` if the token already expired, or if it never expires. Use `never_expires` to distinguish these cases.
+    ///
+    /// Measured against the current system time, not the `KeycloakConfig::clock`. Use `remaining_validity_at` otherwise.
+    pub fn remaining_validity(&self) -> Option<time::Duration> {
+        self.remaining_validity_at(time::OffsetDateTime::now_utc())
+    }
+
+    /// Like `remaining_validity`, but measured from the given time instead of the current system time,
+    /// e.g. `KeycloakConfig::clock.now()`.
+    pub fn remaining_validity_at(&self, now: time::OffsetDateTime) -> Option<time::Duration> {
+        if self.never_expires() {
+            return None;
+        }
+        let remaining = self.expires_at - now;
+        remaining.is_positive().then_some(remaining)
+    }
+
+    /// How long ago the token was issued (`now - issued_at`). `None` if the token carries no 'iat' claim.
+    /// Tokens issued in the future, e.g. because of clock skew with your Keycloak server, have an age of zero.
+    ///
+    /// Measured against the current system time, not the `KeycloakConfig::clock`. Use `age_at` otherwise.
+    pub fn age(&self) -> Option<time::Duration> {
+        self.age_at(time::OffsetDateTime::now_utc())
+    }
+
+    /// Like `age`, but measured up to the given time instead of the current system time.
+    pub fn age_at(&self, now: time::OffsetDateTime) -> Option<time::Duration> {
+        self.issued_at
+            .map(|issued_at| (now - issued_at).max(time::Duration::ZERO))
+    }
+
     pub fn is_expired(&self) -> bool {
-        time::OffsetDateTime::now_utc() > self.expires_at
+        self.is_expired_with_leeway(Duration::ZERO)
+    }
+
+    /// Returns true if the token expired. The token is only considered expired once `now > expires_at + leeway`.
+    pub fn is_expired_with_leeway(&self, leeway: Duration) -> bool {
+        self.is_expired_at(time::OffsetDateTime::now_utc(), leeway)
+    }
+
+    /// Like `is_expired`, but compared against the given time instead of the current system time.
+    pub fn is_expired_at(&self, now: time::OffsetDateTime, leeway: Duration) -> bool {
+        now - leeway > self.expires_at
     }
 
     pub fn assert_not_expired(&self) -> Result<(), AuthError> {
-        match self.is_expired() {
+        self.assert_not_expired_with_leeway(Duration::ZERO)
+    }
+
+    /// Like `assert_not_expired`, but tolerating the given leeway (see `is_expired_with_leeway`).
+    pub fn assert_not_expired_with_leeway(&self, leeway: Duration) -> Result<(), AuthError> {
+        self.assert_not_expired_at(time::OffsetDateTime::now_utc(), leeway)
+    }
+
+    /// Like `assert_not_expired`, but compared against the given time instead of the current system time.
+    pub fn assert_not_expired_at(
+        &self,
+        now: time::OffsetDateTime,
+        leeway: Duration,
+    ) -> Result<(), AuthError> {
+        match self.is_expired_at(now, leeway) {
             true => Err(AuthError::TokenExpired),
             false => Ok(()),
         }
     }
-}
 
-impl<R, Extra> ExpectRoles<R> for KeycloakToken<R, Extra>
-where
-    R: Role,
-    Extra: DeserializeOwned + Clone,
-{
-    type Rejection = AuthError;
+    /// Returns true if the token carries a not-before time which was not yet reached.
+    pub fn is_not_yet_valid(&self) -> bool {
+        self.is_not_yet_valid_with_leeway(Duration::ZERO)
+    }
 
-    fn expect_roles<I: Into<R> + Clone>(&self, roles: &[I]) -> Result<(), Self::Rejection> {
-        for expected in roles {
-            let expected: R = expected.clone().into();
-            if !self.roles.iter().any(|role| role.role() == &expected) {
-                return Err(AuthError::MissingExpectedRole {
-                    role: expected.to_string(),
-                });
-            }
+    /// Like `is_not_yet_valid`, but tolerating the given leeway (`now + leeway < not_before`).
+    pub fn is_not_yet_valid_with_leeway(&self, leeway: Duration) -> bool {
+        self.is_not_yet_valid_at(time::OffsetDateTime::now_utc(), leeway)
+    }
+
+    /// Like `is_not_yet_valid`, but compared against the given time instead of the current system time.
+    pub fn is_not_yet_valid_at(&self, now: time::OffsetDateTime, leeway: Duration) -> bool {
+        self.not_before
+            .is_some_and(|not_before| now + leeway < not_before)
+    }
+
+    /// Asserts that the token is currently usable: It must neither be expired nor be used before its not-before time.
+    pub fn assert_active(&self) -> Result<(), AuthError> {
+        self.assert_active_with_leeway(Duration::ZERO)
+    }
+
+    /// Like `assert_active`, but tolerating the given leeway on both the expiration and the not-before time.
+    pub fn assert_active_with_leeway(&self, leeway: Duration) -> Result<(), AuthError> {
+        self.assert_active_at(time::OffsetDateTime::now_utc(), leeway)
+    }
+
+    /// Like `assert_active`, but compared against the given time instead of the current system time.
+    /// The `KeycloakAuthLayer` validates tokens against the `KeycloakConfig::clock`.
+    pub fn assert_active_at(
+        &self,
+        now: time::OffsetDateTime,
+        leeway: Duration,
+    ) -> Result<(), AuthError> {
+        self.assert_not_expired_at(now, leeway)?;
+        match self.is_not_yet_valid_at(now, leeway) {
+            true => Err(AuthError::TokenNotYetValid),
+            false => Ok(()),
         }
-        Ok(())
     }
 
-    fn not_expect_roles<I: Into<R> + Clone>(&self, roles: &[I]) -> Result<(), Self::Rejection> {
-        for expected in roles {
-            let expected: R = expected.clone().into();
-            if let Some(_role) = self.roles.iter().find(|role| role.role() == &expected) {
-                return Err(AuthError::UnexpectedRole);
+    /// Returns true if the user is a member of the given group.
+    /// Groups are compared as paths, ignoring leading and trailing slashes:
+    /// "/engineering/backend" and "engineering/backend" both match a group of "/engineering/backend".
+    /// Memberships in subgroups are not considered, see `in_group_or_subgroup`.
+    pub fn in_group(&self, group: &str) -> bool {
+        self.in_group_matching(group, GroupMatching::Exact)
+    }
+
+    /// Returns true if the user is a member of the given group or of any of its (nested) subgroups,
+    /// e.g. "/engineering" matches a group of "/engineering/backend". Paths are compared as in `in_group`.
+    pub fn in_group_or_subgroup(&self, group: &str) -> bool {
+        self.in_group_matching(group, GroupMatching::IncludeSubgroups)
+    }
+
+    /// Returns true if the user is a member of a group whose path matches the given glob pattern,
+    /// e.g. "/org/*/admins" or "/org/**". See `GroupMatching::Glob` for the supported patterns.
+    pub fn in_group_glob(&self, pattern: &str) -> bool {
+        self.in_group_matching(pattern, GroupMatching::Glob)
+    }
+
+    /// Returns true if the user is a member of the given group, using the given `GroupMatching`.
+    pub fn in_group_matching(&self, group: &str, matching: GroupMatching) -> bool {
+        let expected = normalize_group(group);
+        self.groups.iter().flatten().any(|group| {
+            let group = normalize_group(group);
+            match matching {
+                GroupMatching::Exact => group == expected,
+                GroupMatching::IncludeSubgroups => {
+                    group == expected
+                        || (group.starts_with(expected) && group[expected.len()..].starts_with('/'))
+                }
+                GroupMatching::Glob => glob_matches(expected.split('/'), group.split('/')),
             }
-        }
-        Ok(())
+        })
     }
-}
 
-#[derive(serde::Deserialize, Debug, Clone)]
+    /// The audiences of the token. Empty if the token carries no 'aud' claim or an empty one.
+    pub fn audiences(&self) -> &[String] {
+        self.audience.as_deref().unwrap_or_default()
+    }
+
+    /// Returns true if the token is intended for the given audience. Audiences are compared exactly.
+    pub fn has_audience(&self, audience: &str) -> bool {
+        self.audiences().iter().any(|it| it == audience)
+    }
+
+    /// Returns true if the user authenticated at least at the given level ('acr' claim).
+    /// Numeric levels (as issued by Keycloak) are compared numerically, any other values only match exactly.
+    /// A token without an 'acr' claim meets no level.
+    pub fn meets_acr(&self, minimum: &str) -> bool {
+        match self.authentication_context.as_deref() {
+            None => false,
+            Some(acr) => match (acr.parse::<u32>(), minimum.parse::<u32>()) {
+                (Ok(level), Ok(minimum)) => level >= minimum,
+                _ => acr == minimum,
+            },
+        }
+    }
+
+    /// Returns true if the token was granted the given scope. Scopes are compared exactly.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|it| it == scope)
+    }
+
+    /// Returns an error if any of the given scopes were not granted to the token.
+    pub fn expect_scopes(&self, scopes: &[String]) -> Result<(), AuthError> {
+        match scopes.iter().all(|scope| self.has_scope(scope)) {
+            true => Ok(()),
+            false => Err(AuthError::InsufficientScope {
+                required: scopes.to_vec(),
+                present: self.scopes.clone(),
+            }),
+        }
+    }
+
+    /// Returns true if the token carries the given role.
+    /// Realm and client roles are not distinguished: Only the roles name (see `KeycloakRole::role`) is compared, using the tokens `role_matching`.
+    /// Scopes of UMA permissions are only granted on their resource and never match, see `has_permission`.
+    pub fn has_role<I: Into<R>>(&self, role: I) -> bool {
+        let expected: R = role.into();
+        self.roles.iter().any(|role| {
+            !role.is_resource_scoped() && self.role_matching.matches(role.role(), &expected)
+        })
+    }
+
+    /// Returns true if the token carries a UMA permission for the given scope on the given resource.
+    /// Shorthand for `has_scoped_role` with a `KeycloakRole::Scope`. Requires the layer to extract `uma_permissions`.
+    pub fn has_permission<I: Into<R>>(&self, resource: &str, scope: I) -> bool {
+        self.has_scoped_role(&KeycloakRole::Scope {
+            resource: Some(resource.to_owned()),
+            scope: scope.into(),
+        })
+    }
+
+    /// Returns true if the token carries the given role in the given scope.
+    /// A `KeycloakRole::Realm` only matches realm roles, a `KeycloakRole::Client` only matches roles of that exact client
+    /// and a `KeycloakRole::Scope` only matches scopes treated as roles on that exact resource.
+    pub fn has_scoped_role(&self, expected: &KeycloakRole<R>) -> bool {
+        self.roles.iter().any(|role| match (role, expected) {
+            (KeycloakRole::Realm { role }, KeycloakRole::Realm { role: expected }) => {
+                self.role_matching.matches(role, expected)
+            }
+            (
+                KeycloakRole::Client { client, role },
+                KeycloakRole::Client {
+                    client: expected_client,
+                    role: expected,
+                },
+            ) => client == expected_client && self.role_matching.matches(role, expected),
+            (
+                KeycloakRole::Scope { resource, scope },
+                KeycloakRole::Scope {
+                    resource: expected_resource,
+                    scope: expected,
+                },
+            ) => resource == expected_resource && self.role_matching.matches(scope, expected),
+            _ => false,
+        })
+    }
+
+    /// Returns true if the token carries the given role for the given client.
+    pub fn has_client_role<I: Into<R>>(&self, client: &str, role: I) -> bool {
+        self.has_scoped_role(&KeycloakRole::Client {
+            client: client.to_owned(),
+            role: role.into(),
+        })
+    }
+
+    /// Returns true if the token carries at least one of the given roles. Always false for an empty `roles` slice.
+    pub fn has_any_role<I: Into<R> + Clone>(&self, roles: &[I]) -> bool {
+        roles.iter().any(|role| self.has_role(role.clone()))
+    }
+
+    /// Returns true if the token carries any role at all, realm or client.
+    /// Only roles extracted into `roles` count, see `KeycloakAuthLayer::role_clients` and `KeycloakAuthLayer::unknown_roles`.
+    pub fn has_any_roles(&self) -> bool {
+        !self.roles.is_empty()
+    }
+
+    /// Returns true if the token carries all of the given roles. Always true for an empty `roles` slice.
+    pub fn has_all_roles<I: Into<R> + Clone>(&self, roles: &[I]) -> bool {
+        roles.iter().all(|role| self.has_role(role.clone()))
+    }
+
+    /// Returns the realm roles of the token, omitting all client roles.
+    pub fn realm_roles(&self) -> Vec<&R> {
+        self.roles
+            .iter()
+            .filter_map(|role| match role {
+                KeycloakRole::Realm { role } => Some(role),
+                KeycloakRole::Client { client: _, role: _ }
+                | KeycloakRole::Scope {
+                    resource: _,
+                    scope: _,
+                } => None,
+            })
+            .collect()
+    }
+
+    /// Returns the roles of the given client (as found in the 'resource_access' claim), omitting realm roles and roles of other clients.
+    pub fn client_roles(&self, client: &str) -> Vec<&R> {
+        self.roles
+            .iter()
+            .filter_map(|role| match role {
+                KeycloakRole::Client {
+                    client: role_client,
+                    role,
+                } if role_client == client => Some(role),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl<R, Extra> KeycloakToken<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone + UserProfile,
+{
+    /// The username of the user, as provided by the `Extra` claims.
+    pub fn username(&self) -> Option<&str> {
+        self.extra.username()
+    }
+
+    /// The email address of the user, as provided by the `Extra` claims.
+    pub fn email(&self) -> Option<&str> {
+        self.extra.email()
+    }
+
+    /// The full name of the user, as provided by the `Extra` claims.
+    pub fn full_name(&self) -> Option<&str> {
+        self.extra.full_name()
+    }
+}
+
+/// Minimal description of the user a token was issued for, to be used in place of the full `KeycloakToken`.
+///
+/// Created from any `KeycloakToken` whose `Extra` claims implement `UserProfile`, including the default `ProfileAndEmail`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
+pub struct UserContext {
+    /// The subject of the token (the UUID of the user inside Keycloak).
+    pub id: String,
+    pub username: Option<String>,
+    /// All roles of the token, rendered along with their scope, e.g. "realm:admin" or "client:billing:manage".
+    pub roles: Vec<String>,
+    pub email: Option<String>,
+}
+
+impl<R, Extra> From<&KeycloakToken<R, Extra>> for UserContext
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone + UserProfile,
+{
+    fn from(token: &KeycloakToken<R, Extra>) -> Self {
+        Self {
+            id: token.subject.clone(),
+            username: token.username().map(str::to_owned),
+            roles: token.roles.iter().map(ToString::to_string).collect(),
+            email: token.email().map(str::to_owned),
+        }
+    }
+}
+
+/// Reports a single missing role as `MissingExpectedRole`, several as `MissingExpectedRoles`.
+fn missing_roles(mut roles: Vec<String>) -> AuthError {
+    match roles.len() {
+        1 => AuthError::MissingExpectedRole {
+            role: roles.remove(0),
+        },
+        _ => AuthError::MissingExpectedRoles { roles },
+    }
+}
+
+impl<R, Extra> ExpectRoles<R> for KeycloakToken<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    type Rejection = AuthError;
+
+    fn expect_roles<I: Into<R> + Clone>(&self, roles: &[I]) -> Result<(), Self::Rejection> {
+        let missing = roles
+            .iter()
+            .filter(|expected| !self.has_role((*expected).clone()))
+            .map(|missing| missing.clone().into().to_string())
+            .collect::<Vec<_>>();
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(missing_roles(missing)),
+        }
+    }
+
+    fn expect_any_role<I: Into<R> + Clone>(&self, roles: &[I]) -> Result<(), Self::Rejection> {
+        if roles.is_empty() || self.has_any_role(roles) {
+            return Ok(());
+        }
+        Err(missing_roles(
+            roles
+                .iter()
+                .map(|it| it.clone().into().to_string())
+                .collect(),
+        ))
+    }
+
+    fn expect_scoped_role(&self, role: &KeycloakRole<R>) -> Result<(), Self::Rejection> {
+        match self.has_scoped_role(role) {
+            true => Ok(()),
+            false => Err(AuthError::MissingExpectedRole {
+                role: role.to_string(),
+            }),
+        }
+    }
+
+    fn not_expect_roles<I: Into<R> + Clone>(&self, roles: &[I]) -> Result<(), Self::Rejection> {
+        match self.has_any_role(roles) {
+            true => Err(AuthError::UnexpectedRole),
+            false => Ok(()),
+        }
+    }
+
+    fn not_expect_all_roles<I: Into<R> + Clone>(&self, roles: &[I]) -> Result<(), Self::Rejection> {
+        match !roles.is_empty() && self.has_all_roles(roles) {
+            true => Err(AuthError::UnexpectedRole),
+            false => Ok(()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
 pub struct Profile {
     /// Keycloak: First name.
     pub given_name: Option<String>,
@@ -376,6 +1567,7 @@ pub struct Profile {
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
 pub struct Email {
     /// Keycloak: Email address of the user.
     pub email: Option<String>,
@@ -383,10 +1575,1516 @@ pub struct Email {
     pub email_verified: Option<bool>,
 }
 
-#[derive(serde::Deserialize, Debug, Clone)]
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
 pub struct ProfileAndEmail {
     #[serde(flatten)]
     pub profile: Option<Profile>,
     #[serde(flatten)]
     pub email: Option<Email>,
 }
+
+/// Exposes whether the email address of a user is verified.
+/// Implement this for your custom `Extra` type to pass `EmailVerification::email_verified` as the `KeycloakAuthLayer::require_verified_email`.
+pub trait EmailVerification {
+    /// Keycloak: Whether the users email is verified. `None` if unknown.
+    fn email_verified(&self) -> Option<bool>;
+}
+
+/// Custom validation of the `Extra` claims of a token, e.g. checking the presence of a tenant id.
+///
+/// Pass `ValidateExtra::validate` as the `KeycloakAuthLayer::validate_extra` to run it for every token, after the role checks.
+pub trait ValidateExtra {
+    /// Validate the extra claims. Any error returned rejects the token and is passed on to the caller.
+    fn validate(&self) -> Result<(), AuthError>;
+}
+
+/// Exposes the profile of a user, making the `username`, `email` and `full_name` accessors of `KeycloakToken` available.
+/// Implement this for your custom `Extra` type to map your own claims. Every method returns `None` by default.
+pub trait UserProfile {
+    /// Keycloak: Username of the user.
+    fn username(&self) -> Option<&str> {
+        None
+    }
+
+    /// Keycloak: Email address of the user.
+    fn email(&self) -> Option<&str> {
+        None
+    }
+
+    /// Keycloak: Combined first and last name of the user.
+    fn full_name(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl UserProfile for Profile {
+    fn username(&self) -> Option<&str> {
+        self.preferred_username.as_deref()
+    }
+
+    fn full_name(&self) -> Option<&str> {
+        self.full_name.as_deref()
+    }
+}
+
+impl UserProfile for Email {
+    fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+}
+
+impl UserProfile for ProfileAndEmail {
+    fn username(&self) -> Option<&str> {
+        self.profile.as_ref().and_then(UserProfile::username)
+    }
+
+    fn email(&self) -> Option<&str> {
+        self.email.as_ref().and_then(UserProfile::email)
+    }
+
+    fn full_name(&self) -> Option<&str> {
+        self.profile.as_ref().and_then(UserProfile::full_name)
+    }
+}
+
+impl EmailVerification for Email {
+    fn email_verified(&self) -> Option<bool> {
+        self.email_verified
+    }
+}
+
+impl EmailVerification for ProfileAndEmail {
+    fn email_verified(&self) -> Option<bool> {
+        self.email.as_ref().and_then(|email| email.email_verified)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use serde_json::json;
+    use url::Url;
+
+    use crate::{
+        error::AuthError,
+        instance::{KeycloakAuthInstance, KeycloakConfig},
+        layer::KeycloakAuthLayer,
+        role::{ExpectRoles, KeycloakRole, Role, RoleClaim, RoleMatching, UnknownRoles},
+        PassthroughMode,
+    };
+
+    use serde::{de::DeserializeOwned, Deserialize};
+
+    use super::{
+        contains_realm, AudienceMatching, EmailVerification, GroupMatching, KeycloakToken,
+        UserContext, UserProfile, ValidateExtra,
+    };
+
+    pub(crate) const SECRET: &[u8] = b"secret";
+
+    fn now() -> i64 {
+        time::OffsetDateTime::now_utc().unix_timestamp()
+    }
+
+    /// Claims of a valid access token issued by "https://localhost:8443/realms/MyRealm" for the audience "account".
+    pub(crate) fn claims() -> serde_json::Value {
+        json!({
+            "exp": now() + 300,
+            "iat": now(),
+            "jti": "1b2d7b2c-6b1c-4d0b-9e0a-3d1d3f4b5a6c",
+            "iss": "https://localhost:8443/realms/MyRealm",
+            "aud": "account",
+            "sub": "f8f2e2e4-6a10-4b23-8b2d-3c5a2a1d9e7f",
+            "typ": "Bearer",
+            "azp": "my-client",
+            "realm_access": { "roles": ["administrator"] },
+        })
+    }
+
+    /// Signs the given claims with the `SECRET`.
+    pub(crate) fn encode(claims: &serde_json::Value) -> String {
+        jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            claims,
+            &EncodingKey::from_secret(SECRET),
+        )
+        .unwrap()
+    }
+
+    fn layer(leeway: Duration) -> KeycloakAuthLayer<String> {
+        let instance = KeycloakAuthInstance::from_secret(
+            KeycloakConfig::builder()
+                .server(Url::parse("https://localhost:8443/").unwrap())
+                .realm(String::from("MyRealm"))
+                .leeway(leeway)
+                .build(),
+            SECRET,
+            Algorithm::HS256,
+        );
+        KeycloakAuthLayer::<String>::builder()
+            .instance(instance)
+            .expected_audiences(vec![String::from("account")])
+            .build()
+    }
+
+    fn decode_with<R: Role, Extra: DeserializeOwned + Clone>(
+        layer: &KeycloakAuthLayer<R, Extra>,
+        token: &str,
+    ) -> Result<KeycloakToken<R, Extra>, AuthError> {
+        futures::executor::block_on(layer.validate_raw_token(token)).map(|(_, token)| token)
+    }
+
+    fn decode(token: &str) -> Result<KeycloakToken<String>, AuthError> {
+        decode_with(&layer(Duration::ZERO), token)
+    }
+
+    fn decode_with_leeway(
+        token: &str,
+        leeway: Duration,
+    ) -> Result<KeycloakToken<String>, AuthError> {
+        decode_with(&layer(leeway), token)
+    }
+
+    #[test]
+    fn tries_every_configured_secret() {
+        let layer = KeycloakAuthLayer::<String>::builder()
+            .instance(KeycloakAuthInstance::from_secrets(
+                KeycloakConfig::builder()
+                    .server(Url::parse("https://localhost:8443/").unwrap())
+                    .realm(String::from("MyRealm"))
+                    .build(),
+                [&b"current"[..], b"previous", SECRET],
+                Algorithm::HS256,
+            ))
+            .expected_audiences(vec![String::from("account")])
+            .build();
+        let encode_with = |secret: &[u8]| {
+            jsonwebtoken::encode(
+                &Header::new(Algorithm::HS256),
+                &claims(),
+                &EncodingKey::from_secret(secret),
+            )
+            .unwrap()
+        };
+
+        for secret in [&b"current"[..], b"previous", SECRET] {
+            assert!(decode_with(&layer, &encode_with(secret)).is_ok());
+        }
+        assert!(matches!(
+            decode_with(&layer, &encode_with(b"unknown")),
+            Err(AuthError::NoMatchingDecodingKey)
+        ));
+    }
+
+    #[test]
+    fn accepts_token_without_nbf() {
+        let token = decode(&encode(&claims())).unwrap();
+        assert_eq!(token.not_before, None);
+        assert!(token.assert_active().is_ok());
+    }
+
+    #[test]
+    fn accepts_token_with_past_nbf() {
+        let mut claims = claims();
+        claims["nbf"] = json!(now() - 10);
+        let token = decode(&encode(&claims)).unwrap();
+        assert!(token.not_before.is_some());
+        assert!(token.assert_active().is_ok());
+    }
+
+    #[test]
+    fn rejects_token_with_future_nbf() {
+        let mut claims = claims();
+        claims["nbf"] = json!(now() + 300);
+        assert!(matches!(
+            decode(&encode(&claims)),
+            Err(AuthError::TokenNotYetValid)
+        ));
+    }
+
+    #[test]
+    fn assert_active_rejects_future_not_before() {
+        let mut token = decode(&encode(&claims())).unwrap();
+        token.not_before = Some(time::OffsetDateTime::now_utc() + time::Duration::minutes(5));
+        assert!(matches!(
+            token.assert_active(),
+            Err(AuthError::TokenNotYetValid)
+        ));
+    }
+
+    #[test]
+    fn peeks_at_the_realm_of_the_issuer() {
+        // The signature and audience are irrelevant when peeking. Only the issuer is looked at.
+        let token = encode(&claims());
+        assert!(contains_realm(&token, "MyRealm"));
+        assert!(!contains_realm(&token, "Realm"));
+        assert!(!contains_realm(&token, "OtherRealm"));
+    }
+
+    #[test]
+    fn rejects_expired_token_without_leeway() {
+        let mut claims = claims();
+        claims["exp"] = json!(now() - 5);
+        assert!(matches!(
+            decode(&encode(&claims)),
+            Err(AuthError::TokenExpired)
+        ));
+    }
+
+    #[test]
+    fn accepts_recently_expired_token_within_leeway() {
+        let mut claims = claims();
+        claims["exp"] = json!(now() - 5);
+        let token = decode_with_leeway(&encode(&claims), Duration::from_secs(30)).unwrap();
+        assert!(token.is_expired());
+        assert!(!token.is_expired_with_leeway(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn role_matching_is_exact_by_default() {
+        let token = decode(&encode(&claims())).unwrap();
+        assert!(token.expect_roles(&["administrator"]).is_ok());
+        assert!(token.expect_roles(&["Administrator"]).is_err());
+        assert!(token.not_expect_roles(&["Administrator"]).is_ok());
+    }
+
+    #[test]
+    fn role_matching_can_ignore_case() {
+        let mut token = decode(&encode(&claims())).unwrap();
+        token.role_matching = RoleMatching::CaseInsensitive;
+        assert!(token.expect_roles(&["Administrator"]).is_ok());
+        assert!(token.not_expect_roles(&["ADMINISTRATOR"]).is_err());
+    }
+
+    #[test]
+    fn expect_roles_reports_all_missing_roles() {
+        let token = decode(&encode(&claims())).unwrap();
+        match token.expect_roles(&["editor", "administrator", "viewer"]) {
+            Err(AuthError::MissingExpectedRoles { roles }) => {
+                assert_eq!(roles, ["editor", "viewer"]);
+            }
+            other => panic!("Unexpected result: {other:?}"),
+        }
+        match token.expect_roles(&["editor", "administrator"]) {
+            Err(AuthError::MissingExpectedRole { role }) => {
+                assert_eq!(role, "editor");
+            }
+            other => panic!("Unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn expect_any_role_requires_one_match() {
+        let token = decode(&encode(&claims())).unwrap();
+        assert!(token.expect_any_role(&["editor", "administrator"]).is_ok());
+        assert!(token.expect_any_role::<&str>(&[]).is_ok());
+        match token.expect_any_role(&["editor", "viewer"]) {
+            Err(AuthError::MissingExpectedRoles { roles }) => {
+                assert_eq!(roles, ["editor", "viewer"]);
+            }
+            other => panic!("Unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn has_role_helpers() {
+        let mut claims = claims();
+        claims["resource_access"] = json!({ "billing": { "roles": ["manage"] } });
+        let token = decode(&encode(&claims)).unwrap();
+        assert!(token.has_role("administrator"));
+        assert!(token.has_role("manage"));
+        assert!(!token.has_role("editor"));
+        assert!(token.has_any_role(&["editor", "manage"]));
+        assert!(!token.has_any_role::<&str>(&[]));
+        assert!(token.has_all_roles(&["administrator", "manage"]));
+        assert!(!token.has_all_roles(&["administrator", "editor"]));
+        assert!(token.has_all_roles::<&str>(&[]));
+    }
+
+    #[test]
+    fn client_roles_do_not_collide_with_realm_roles() {
+        let mut claims = claims();
+        claims["resource_access"] = json!({ "billing": { "roles": ["manage"] } });
+        let token = decode(&encode(&claims)).unwrap();
+        assert!(token.has_client_role("billing", "manage"));
+        assert!(!token.has_client_role("shipping", "manage"));
+        assert!(!token.has_client_role("billing", "administrator"));
+        assert!(token.expect_client_role("billing", "manage").is_ok());
+        assert!(token
+            .expect_scoped_role(&KeycloakRole::Realm {
+                role: String::from("manage")
+            })
+            .is_err());
+
+        let mut layer = layer(Duration::ZERO);
+        layer.required_scoped_roles = vec![KeycloakRole::Client {
+            client: String::from("billing"),
+            role: String::from("administrator"),
+        }];
+        match decode_with(&layer, &encode(&claims)) {
+            Err(AuthError::MissingExpectedRole { role }) => {
+                assert_eq!(role, "client:billing:administrator")
+            }
+            other => panic!("Unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn validates_authorized_party_against_allowlist() {
+        let token = encode(&claims());
+
+        let mut layer = layer(Duration::ZERO);
+        assert!(decode_with(&layer, &token).is_ok());
+
+        layer.allowed_authorized_parties = vec![String::from("my-client")];
+        assert!(decode_with(&layer, &token).is_ok());
+
+        layer.allowed_authorized_parties = vec![String::from("other-client")];
+        assert!(matches!(
+            decode_with(&layer, &token),
+            Err(AuthError::UnexpectedAuthorizedParty { authorized_party }) if authorized_party == "my-client"
+        ));
+    }
+
+    #[test]
+    fn rejects_unverified_email_when_required() {
+        let mut layer = layer(Duration::ZERO);
+        layer.require_verified_email = Some(EmailVerification::email_verified);
+
+        let mut claims = claims();
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::EmailNotVerified)
+        ));
+
+        claims["email_verified"] = json!(false);
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::EmailNotVerified)
+        ));
+
+        claims["email_verified"] = json!(true);
+        assert!(decode_with(&layer, &encode(&claims)).is_ok());
+    }
+
+    #[derive(Debug, Clone, Default, Deserialize)]
+    struct Tenant {
+        tenant_id: Option<String>,
+    }
+
+    impl ValidateExtra for Tenant {
+        fn validate(&self) -> Result<(), AuthError> {
+            match self.tenant_id {
+                Some(_) => Ok(()),
+                None => Err(AuthError::InvalidToken {
+                    reason: "Missing tenant_id".to_owned(),
+                }),
+            }
+        }
+    }
+
+    #[test]
+    fn runs_extra_validation_when_configured() {
+        let layer = KeycloakAuthLayer::<String, Tenant>::builder()
+            .instance(layer(Duration::ZERO).instance)
+            .passthrough_mode(PassthroughMode::Block)
+            .expected_audiences(vec![String::from("account")])
+            .validate_extra(ValidateExtra::validate)
+            .build();
+
+        let mut claims = claims();
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::InvalidToken { reason }) if reason == "Missing tenant_id"
+        ));
+
+        claims["tenant_id"] = json!("acme");
+        let token = decode_with(&layer, &encode(&claims)).unwrap();
+        assert_eq!(token.extra.tenant_id.as_deref(), Some("acme"));
+    }
+
+    #[test]
+    fn falls_back_to_default_extra_if_configured() {
+        let strict = KeycloakAuthLayer::<String, Tenant>::builder()
+            .instance(layer(Duration::ZERO).instance)
+            .passthrough_mode(PassthroughMode::Block)
+            .expected_audiences(vec![String::from("account")])
+            .build();
+        let lenient = strict.clone().default_extra_on_error();
+
+        let mut claims = claims();
+        claims["tenant_id"] = json!(42);
+        assert!(matches!(
+            decode_with(&strict, &encode(&claims)),
+            Err(AuthError::JsonParse { source: _ })
+        ));
+        let token = decode_with(&lenient, &encode(&claims)).unwrap();
+        assert_eq!(token.extra.tenant_id, None);
+        assert_eq!(token.realm_roles(), ["administrator"]);
+
+        // Malformed standard claims are never tolerated.
+        claims["tenant_id"] = json!("acme");
+        claims["sub"] = json!(42);
+        assert!(matches!(
+            decode_with(&lenient, &encode(&claims)),
+            Err(AuthError::JsonParse { source: _ })
+        ));
+    }
+
+    #[test]
+    fn parses_scopes() {
+        let mut claims = claims();
+        let token = decode(&encode(&claims)).unwrap();
+        assert!(token.scopes.is_empty());
+        assert!(!token.has_scope("openid"));
+
+        claims["scope"] = json!("");
+        assert!(decode(&encode(&claims)).unwrap().scopes.is_empty());
+
+        claims["scope"] = json!(" openid  profile email ");
+        let token = decode(&encode(&claims)).unwrap();
+        assert_eq!(token.scopes, vec!["openid", "profile", "email"]);
+        assert!(token.has_scope("profile"));
+        assert!(!token.has_scope("prof"));
+    }
+
+    #[test]
+    fn rejects_tokens_without_roles_if_configured() {
+        let mut without_roles = claims();
+        without_roles
+            .as_object_mut()
+            .unwrap()
+            .remove("realm_access");
+        let without_roles = encode(&without_roles);
+
+        let token = decode(&without_roles).unwrap();
+        assert!(!token.has_any_roles());
+        assert!(decode(&encode(&claims())).unwrap().has_any_roles());
+
+        let mut layer = layer(Duration::ZERO);
+        layer.reject_tokens_without_roles = true;
+        assert!(matches!(
+            decode_with(&layer, &without_roles),
+            Err(AuthError::NoRoles)
+        ));
+        assert!(decode_with(&layer, &encode(&claims())).is_ok());
+    }
+
+    #[test]
+    fn rejects_tokens_missing_required_scopes() {
+        let instance = layer(Duration::ZERO).instance;
+        let layer = KeycloakAuthLayer::<String>::builder()
+            .instance(instance)
+            .expected_audiences(vec![String::from("account")])
+            .required_scopes(vec![String::from("openid"), String::from("email")])
+            .build();
+
+        let mut claims = claims();
+        claims["scope"] = json!("openid profile");
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::InsufficientScope { required, present })
+                if required == ["openid", "email"] && present == ["openid", "profile"]
+        ));
+
+        claims["scope"] = json!("openid profile email");
+        assert!(decode_with(&layer, &encode(&claims)).is_ok());
+    }
+
+    #[test]
+    fn persists_only_listed_raw_claims() {
+        let mut claims = claims();
+        claims["tenant_id"] = json!("acme");
+        let token = encode(&claims);
+
+        let instance = layer(Duration::ZERO).instance;
+        let build = |persist_raw_claims: bool, keys: Vec<String>| {
+            KeycloakAuthLayer::<String>::builder()
+                .instance(instance.clone())
+                .expected_audiences(vec![String::from("account")])
+                .persist_raw_claims(persist_raw_claims)
+                .persist_raw_claim_keys(keys)
+                .build()
+        };
+        let raw_claims = |layer: &KeycloakAuthLayer<String>| {
+            futures::executor::block_on(layer.validate_raw_token(&token))
+                .unwrap()
+                .0
+        };
+
+        assert_eq!(raw_claims(&build(false, vec![])), None);
+
+        let all = raw_claims(&build(true, vec![String::from("tenant_id")])).unwrap();
+        assert!(all.contains_key("sub") && all.contains_key("tenant_id"));
+
+        let filtered = raw_claims(&build(
+            false,
+            vec![String::from("tenant_id"), String::from("missing")],
+        ))
+        .unwrap();
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered["tenant_id"], json!("acme"));
+    }
+
+    #[test]
+    fn rejects_only_when_all_forbidden_roles_are_present() {
+        let mut claims = claims();
+        claims["realm_access"] = json!({ "roles": ["requester", "approver"] });
+        let token = decode(&encode(&claims)).unwrap();
+
+        assert!(token
+            .not_expect_all_roles(&["requester", "auditor"])
+            .is_ok());
+        assert!(token.not_expect_all_roles::<&str>(&[]).is_ok());
+        assert!(matches!(
+            token.not_expect_all_roles(&["requester", "approver"]),
+            Err(AuthError::UnexpectedRole)
+        ));
+
+        assert!(matches!(
+            token.not_expect_any_role(&["requester", "auditor"]),
+            Err(AuthError::UnexpectedRole)
+        ));
+        assert!(token.not_expect_any_role(&["auditor"]).is_ok());
+    }
+
+    #[test]
+    fn validates_the_issuer() {
+        let mut claims = claims();
+        claims["iss"] = json!("https://localhost:8443/realms/OtherRealm");
+        assert!(decode(&encode(&claims)).is_ok());
+
+        let of_realm = KeycloakAuthLayer::<String>::builder()
+            .instance(layer(Duration::ZERO).instance)
+            .expected_audiences(vec![String::from("account")])
+            .validate_issuer_of_realm(true)
+            .build();
+        assert!(decode_with(&of_realm, &encode(&self::claims())).is_ok());
+        assert!(matches!(
+            decode_with(&of_realm, &encode(&claims)),
+            Err(AuthError::InvalidIssuer)
+        ));
+
+        let layer = KeycloakAuthLayer::<String>::builder()
+            .instance(layer(Duration::ZERO).instance)
+            .expected_audiences(vec![String::from("account")])
+            .expected_issuers(vec![
+                String::from("https://keycloak.example.com/realms/MyRealm"),
+                String::from("https://localhost:8443/realms/OtherRealm"),
+            ])
+            .build();
+        assert!(decode_with(&layer, &encode(&claims)).is_ok());
+
+        // The issuer derived from the instance is no longer accepted when the expected issuers are set explicitly.
+        assert!(matches!(
+            decode_with(&layer, &encode(&self::claims())),
+            Err(AuthError::InvalidIssuer)
+        ));
+    }
+
+    #[test]
+    fn validates_the_token_type_when_configured() {
+        let mut claims = claims();
+        claims["typ"] = json!("Refresh");
+        assert_eq!(decode(&encode(&claims)).unwrap().token_type, "Refresh");
+
+        let layer = KeycloakAuthLayer::<String>::builder()
+            .instance(layer(Duration::ZERO).instance)
+            .expected_audiences(vec![String::from("account")])
+            .expected_token_type(String::from("Bearer"))
+            .build();
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::UnexpectedTokenType { token_type }) if token_type == "Refresh"
+        ));
+
+        claims["typ"] = json!("Bearer");
+        assert!(decode_with(&layer, &encode(&claims)).is_ok());
+    }
+
+    #[test]
+    fn checks_group_membership() {
+        let mut claims = claims();
+        assert!(!decode(&encode(&claims)).unwrap().in_group("engineering"));
+
+        claims["groups"] = json!(["/engineering/backend", "/sales"]);
+        let token = decode(&encode(&claims)).unwrap();
+        assert!(token.in_group("/engineering/backend"));
+        assert!(token.in_group("engineering/backend/"));
+        assert!(token.in_group("sales"));
+        assert!(!token.in_group("/engineering"));
+        assert!(!token.in_group("backend"));
+
+        assert!(token.in_group_or_subgroup("/engineering"));
+        assert!(token.in_group_or_subgroup("/engineering/backend"));
+        assert!(!token.in_group_or_subgroup("/engineer"));
+        assert!(!token.in_group_or_subgroup("/engineering/frontend"));
+    }
+
+    #[test]
+    fn matches_groups_against_glob_patterns() {
+        let mut claims = claims();
+        claims["groups"] = json!(["/org/acme/admins", "/org/globex/teams/backend"]);
+        let token = decode(&encode(&claims)).unwrap();
+        assert!(token.in_group_glob("/org/*/admins"));
+        assert!(token.in_group_glob("org/acme/*/"));
+        assert!(!token.in_group_glob("/org/*"));
+        assert!(!token.in_group_glob("/*/admins"));
+        assert!(!token.in_group_glob("/org/acm*/admins"));
+
+        assert!(token.in_group_glob("/org/**"));
+        assert!(token.in_group_glob("/org/**/backend"));
+        assert!(token.in_group_glob("/**/admins"));
+        assert!(token.in_group_glob("/org/acme/admins/**"));
+        assert!(token.in_group_glob("**"));
+        assert!(!token.in_group_glob("/org/**/frontend"));
+        assert!(!token.in_group_glob("/**/teams"));
+
+        let mut layer = layer(Duration::ZERO);
+        layer.required_groups = vec![String::from("/org/*/admins")];
+        layer.group_matching = GroupMatching::Glob;
+        assert!(decode_with(&layer, &encode(&claims)).is_ok());
+        layer.required_groups = vec![String::from("/org/*/owners")];
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::MissingExpectedGroup { group }) if group == "/org/*/owners"
+        ));
+    }
+
+    #[test]
+    fn rejects_tokens_missing_required_groups() {
+        let instance = layer(Duration::ZERO).instance;
+        let layer = |group_matching: GroupMatching| {
+            KeycloakAuthLayer::<String>::builder()
+                .instance(instance.clone())
+                .expected_audiences(vec![String::from("account")])
+                .required_groups(vec![String::from("/engineering")])
+                .group_matching(group_matching)
+                .build()
+        };
+        let mut claims = claims();
+        claims["groups"] = json!(["/engineering/backend"]);
+
+        assert!(matches!(
+            decode_with(&layer(GroupMatching::Exact), &encode(&claims)),
+            Err(AuthError::MissingExpectedGroup { group }) if group == "/engineering"
+        ));
+        assert!(decode_with(&layer(GroupMatching::IncludeSubgroups), &encode(&claims)).is_ok());
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum StrictRole {
+        Administrator,
+        Auditor,
+    }
+
+    impl Role for StrictRole {}
+
+    impl std::fmt::Display for StrictRole {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            std::fmt::Debug::fmt(self, f)
+        }
+    }
+
+    impl TryFrom<String> for StrictRole {
+        type Error = String;
+
+        fn try_from(value: String) -> Result<Self, Self::Error> {
+            match value.as_str() {
+                "administrator" => Ok(StrictRole::Administrator),
+                "auditor" => Ok(StrictRole::Auditor),
+                _ => Err(value),
+            }
+        }
+    }
+
+    #[test]
+    fn handles_roles_unknown_to_strict_role_types() {
+        let mut claims = claims();
+        claims["realm_access"] = json!({ "roles": ["administrator", "offline_access"] });
+        claims["resource_access"] =
+            json!({ "account": { "roles": ["auditor", "manage-account"] } });
+        let layer = |unknown_roles: UnknownRoles| {
+            KeycloakAuthLayer::<StrictRole>::builder()
+                .instance(layer(Duration::ZERO).instance)
+                .expected_audiences(vec![String::from("account")])
+                .unknown_roles(unknown_roles)
+                .build()
+        };
+
+        let token = decode_with(&layer(UnknownRoles::Ignore), &encode(&claims)).unwrap();
+        assert_eq!(
+            token.roles,
+            vec![
+                KeycloakRole::Realm {
+                    role: StrictRole::Administrator
+                },
+                KeycloakRole::Client {
+                    client: String::from("account"),
+                    role: StrictRole::Auditor
+                },
+            ]
+        );
+        assert!(token.unknown_roles.is_empty());
+
+        let token = decode_with(&layer(UnknownRoles::Collect), &encode(&claims)).unwrap();
+        assert_eq!(token.roles.len(), 2);
+        assert_eq!(
+            token.unknown_roles,
+            vec![
+                KeycloakRole::Realm {
+                    role: String::from("offline_access")
+                },
+                KeycloakRole::Client {
+                    client: String::from("account"),
+                    role: String::from("manage-account")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_tokens_with_disallowed_algorithms() {
+        let mut layer = layer(Duration::ZERO);
+        layer.instance = Arc::new(
+            KeycloakAuthInstance::from_jwks(
+                KeycloakConfig::builder()
+                    .server(Url::parse("https://localhost:8443/").unwrap())
+                    .realm(String::from("MyRealm"))
+                    .allowed_algorithms(vec![Algorithm::HS256])
+                    .build(),
+                r#"{"keys": [{ "kty": "oct", "k": "c2VjcmV0" }]}"#,
+            )
+            .unwrap(),
+        );
+        assert!(decode_with(&layer, &encode(&claims())).is_ok());
+
+        let hs512 = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS512),
+            &claims(),
+            &EncodingKey::from_secret(SECRET),
+        )
+        .unwrap();
+        assert!(matches!(
+            decode_with(&layer, &hs512),
+            Err(AuthError::DisallowedAlgorithm { algorithm }) if algorithm == "HS512"
+        ));
+    }
+
+    #[test]
+    fn rejects_tokens_not_issued_for_own_client() {
+        let mut layer = layer(Duration::ZERO);
+        layer.client_id = Some(String::from("my-service"));
+
+        // Neither audience nor authorized party.
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims())),
+            Err(AuthError::NotIssuedForClient { client_id }) if client_id == "my-service"
+        ));
+
+        let mut for_service = claims();
+        for_service["aud"] = json!(["account", "my-service"]);
+        assert!(decode_with(&layer, &encode(&for_service)).is_ok());
+
+        let mut to_service = claims();
+        to_service["azp"] = json!("my-service");
+        assert!(decode_with(&layer, &encode(&to_service)).is_ok());
+    }
+
+    #[test]
+    fn applies_customized_validation() {
+        let layer = |customize: fn(&mut jsonwebtoken::Validation)| {
+            KeycloakAuthLayer::<String>::builder()
+                .instance(KeycloakAuthInstance::from_secret(
+                    KeycloakConfig::builder()
+                        .server(Url::parse("https://localhost:8443/").unwrap())
+                        .realm(String::from("MyRealm"))
+                        .customize_validation(Arc::new(customize))
+                        .build(),
+                    SECRET,
+                    Algorithm::HS256,
+                ))
+                .expected_audiences(vec![String::from("account")])
+                .build()
+        };
+        let token = encode(&claims());
+
+        // Extending the defaults.
+        let expected_subject = layer(|validation| {
+            validation.sub = Some(String::from("f8f2e2e4-6a10-4b23-8b2d-3c5a2a1d9e7f"))
+        });
+        assert!(decode_with(&expected_subject, &token).is_ok());
+        let other_subject = layer(|validation| validation.sub = Some(String::from("someone-else")));
+        assert!(matches!(
+            decode_with(&other_subject, &token),
+            Err(AuthError::Decode { source: _ })
+        ));
+
+        // Overriding the defaults.
+        let other_issuer = layer(|validation| {
+            validation.set_issuer(&["https://localhost:8443/realms/OtherRealm"])
+        });
+        assert!(matches!(
+            decode_with(&other_issuer, &token),
+            Err(AuthError::InvalidIssuer)
+        ));
+    }
+
+    #[test]
+    fn decodes_claims_without_enforcing_them() {
+        let instance = layer(Duration::ZERO).instance;
+        let inspect = |token: &str| {
+            futures::executor::block_on(
+                instance.decode_unverified_claims::<serde_json::Value>(token),
+            )
+        };
+
+        let mut claims = claims();
+        claims["exp"] = json!(now() - 3600);
+        claims["aud"] = json!("other");
+        claims.as_object_mut().unwrap().remove("realm_access");
+        let standard_claims = inspect(&encode(&claims)).unwrap();
+        assert_eq!(standard_claims.exp, claims["exp"].as_i64());
+        assert_eq!(standard_claims.aud, Some(vec![String::from("other")]));
+
+        // The signature is still verified.
+        let forged = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"forged"),
+        )
+        .unwrap();
+        assert!(matches!(
+            inspect(&forged),
+            Err(AuthError::NoMatchingDecodingKey)
+        ));
+    }
+
+    #[test]
+    fn rejects_tokens_without_signature_algorithm() {
+        use base64::Engine;
+
+        let encode_part = |value: serde_json::Value| {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(value.to_string())
+        };
+        let signed = encode(&claims());
+        let signature = signed.rsplit('.').next().unwrap();
+        for alg in ["none", "None", "HS257"] {
+            let header = encode_part(json!({ "alg": alg, "typ": "JWT" }));
+            let payload = encode_part(claims());
+            for token in [
+                format!("{header}.{payload}."),
+                format!("{header}.{payload}.{signature}"),
+            ] {
+                assert!(matches!(
+                    decode(&token),
+                    Err(AuthError::DisallowedAlgorithm { algorithm }) if algorithm == alg
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn reports_remaining_validity() {
+        let mut token = decode(&encode(&claims())).unwrap();
+        let remaining = token.remaining_validity().unwrap();
+        assert!(
+            remaining > time::Duration::seconds(290) && remaining <= time::Duration::seconds(300)
+        );
+        assert!(!token.never_expires());
+
+        let expires_at = token.expires_at;
+        assert_eq!(
+            token.remaining_validity_at(expires_at - time::Duration::seconds(60)),
+            Some(time::Duration::seconds(60))
+        );
+        assert_eq!(token.remaining_validity_at(expires_at), None);
+
+        token.expires_at = time::OffsetDateTime::now_utc() - time::Duration::seconds(1);
+        assert_eq!(token.remaining_validity(), None);
+        assert!(!token.never_expires());
+
+        token.expires_at = super::NEVER_EXPIRES;
+        assert_eq!(token.remaining_validity(), None);
+        assert!(token.never_expires());
+        assert!(!token.is_expired());
+    }
+
+    #[test]
+    fn requires_minimum_authentication_level() {
+        let mut claims = claims();
+        let token = decode(&encode(&claims)).unwrap();
+        assert_eq!(token.authentication_context, None);
+        assert!(!token.meets_acr("0"));
+
+        claims["acr"] = json!("1");
+        claims["amr"] = json!(["pwd"]);
+        let token = decode(&encode(&claims)).unwrap();
+        assert_eq!(
+            token.authentication_methods,
+            Some(vec![String::from("pwd")])
+        );
+        assert!(token.meets_acr("0") && token.meets_acr("1"));
+        assert!(!token.meets_acr("2") && !token.meets_acr("gold"));
+
+        let mut layer = layer(Duration::ZERO);
+        layer.minimum_acr = Some(String::from("2"));
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::InsufficientAuthenticationLevel { required, present })
+                if required == "2" && present.as_deref() == Some("1")
+        ));
+        claims["acr"] = json!("2");
+        assert!(decode_with(&layer, &encode(&claims)).is_ok());
+    }
+
+    #[test]
+    fn rejects_tokens_exceeding_max_age() {
+        let mut token = decode(&encode(&claims())).unwrap();
+        let issued_at = token.issued_at.unwrap();
+        assert_eq!(
+            token.age_at(issued_at + time::Duration::seconds(60)),
+            Some(time::Duration::seconds(60))
+        );
+        // Issued in the future, because of clock skew.
+        assert_eq!(
+            token.age_at(issued_at - time::Duration::seconds(5)),
+            Some(time::Duration::ZERO)
+        );
+        token.issued_at = None;
+        assert_eq!(token.age(), None);
+
+        let mut layer = layer(Duration::ZERO);
+        layer.max_token_age = Some(Duration::from_secs(3600));
+        assert!(decode_with(&layer, &encode(&claims())).is_ok());
+
+        let mut old = claims();
+        old["iat"] = json!(now() - 7200);
+        assert!(matches!(
+            decode_with(&layer, &encode(&old)),
+            Err(AuthError::TokenTooOld)
+        ));
+
+        old.as_object_mut().unwrap().remove("iat");
+        assert!(matches!(
+            decode_with(&layer, &encode(&old)),
+            Err(AuthError::MissingClaim { claim }) if claim == "iat"
+        ));
+    }
+
+    #[test]
+    fn rejects_users_authenticated_too_long_ago() {
+        let mut layer = layer(Duration::ZERO);
+        let mut claims = claims();
+        claims["auth_time"] = json!(now() - 600);
+        let token = decode_with(&layer, &encode(&claims)).unwrap();
+        assert_eq!(
+            token.auth_time.map(|auth_time| auth_time.unix_timestamp()),
+            claims["auth_time"].as_i64()
+        );
+
+        layer.max_auth_age = Some(Duration::from_secs(900));
+        assert!(decode_with(&layer, &encode(&claims)).is_ok());
+
+        layer.max_auth_age = Some(Duration::from_secs(300));
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::AuthenticationTooOld)
+        ));
+
+        claims.as_object_mut().unwrap().remove("auth_time");
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::MissingClaim { claim }) if claim == "auth_time"
+        ));
+    }
+
+    #[test]
+    fn rejects_tokens_issued_in_the_future_if_configured() {
+        let layer = |issued_at_skew: Option<Duration>| {
+            let mut config = KeycloakConfig::builder()
+                .server(Url::parse("https://localhost:8443/").unwrap())
+                .realm(String::from("MyRealm"))
+                .leeway(Duration::from_secs(30))
+                .validate_issued_at(true)
+                .build();
+            config.issued_at_skew = issued_at_skew;
+            KeycloakAuthLayer::<String>::builder()
+                .instance(KeycloakAuthInstance::from_secret(
+                    config,
+                    SECRET,
+                    Algorithm::HS256,
+                ))
+                .expected_audiences(vec![String::from("account")])
+                .build()
+        };
+        let issued_in = |secs: i64| {
+            let mut claims = claims();
+            claims["iat"] = json!(now() + secs);
+            encode(&claims)
+        };
+
+        // Skewed by up to the leeway by default.
+        assert!(decode_with(&layer(None), &issued_in(20)).is_ok());
+        assert!(matches!(
+            decode_with(&layer(None), &issued_in(60)),
+            Err(AuthError::InvalidToken { reason: _ })
+        ));
+        assert!(decode_with(&layer(Some(Duration::from_secs(120))), &issued_in(60)).is_ok());
+        // Year 3000.
+        assert!(matches!(
+            decode_with(
+                &layer(Some(Duration::from_secs(120))),
+                &issued_in(32_503_680_000 - now())
+            ),
+            Err(AuthError::InvalidToken { reason: _ })
+        ));
+        // Not validated by default.
+        assert!(decode(&issued_in(60)).is_ok());
+    }
+
+    #[test]
+    fn rejects_oversized_tokens() {
+        let layer = |max_token_length: usize| {
+            let config = KeycloakConfig::builder()
+                .server(Url::parse("https://localhost:8443/").unwrap())
+                .realm(String::from("MyRealm"))
+                .max_token_length(max_token_length)
+                .build();
+            KeycloakAuthLayer::<String>::builder()
+                .instance(KeycloakAuthInstance::from_secret(
+                    config,
+                    SECRET,
+                    Algorithm::HS256,
+                ))
+                .expected_audiences(vec![String::from("account")])
+                .build()
+        };
+        let token = encode(&claims());
+
+        assert!(decode_with(&layer(token.len()), &token).is_ok());
+        assert!(matches!(
+            decode_with(&layer(token.len() - 1), &token),
+            Err(AuthError::MalformedAuthorizationHeader { reason: _ })
+        ));
+
+        // 8 KiB by default.
+        let mut claims = claims();
+        claims["padding"] = json!("a".repeat(8 * 1024));
+        assert!(matches!(
+            decode(&encode(&claims)),
+            Err(AuthError::MalformedAuthorizationHeader { reason: _ })
+        ));
+    }
+
+    #[test]
+    fn filters_roles_by_scope() {
+        let mut claims = claims();
+        claims["resource_access"] = json!({
+            "billing": { "roles": ["manage", "view"] },
+            "account": { "roles": ["manage-account"] },
+        });
+        let token = decode(&encode(&claims)).unwrap();
+        assert_eq!(token.realm_roles(), vec!["administrator"]);
+        let mut billing_roles = token.client_roles("billing");
+        billing_roles.sort();
+        assert_eq!(billing_roles, vec!["manage", "view"]);
+        assert_eq!(token.client_roles("account"), vec!["manage-account"]);
+        assert!(token.client_roles("unknown").is_empty());
+    }
+
+    #[test]
+    fn accepts_token_without_iat() {
+        let token = decode(&encode(&claims())).unwrap();
+        assert!(token.issued_at.is_some());
+
+        let mut claims = claims();
+        claims.as_object_mut().unwrap().remove("iat");
+        let token = decode(&encode(&claims)).unwrap();
+        assert_eq!(token.issued_at, None);
+    }
+
+    #[test]
+    fn applies_audience_matching() {
+        let mut claims = claims();
+        claims["aud"] = json!(["account", "billing"]);
+        let token = encode(&claims);
+        let decode = |expected: &[&str], matching: AudienceMatching| {
+            let mut layer = layer(Duration::ZERO);
+            layer.expected_audiences = expected.iter().map(|it| it.to_string()).collect();
+            layer.audience_matching = matching;
+            decode_with(&layer, &token)
+        };
+
+        assert!(decode(&["account", "other"], AudienceMatching::AnyOf).is_ok());
+        assert!(decode(&["billing", "account"], AudienceMatching::AllOf).is_ok());
+        assert!(decode(&["billing", "account"], AudienceMatching::Exact).is_ok());
+        assert!(decode(&["account"], AudienceMatching::AllOf).is_ok());
+        match decode(&["account"], AudienceMatching::Exact) {
+            Err(AuthError::UnexpectedAudience { required, present }) => {
+                assert_eq!(required, vec!["account"]);
+                assert_eq!(present, vec!["account", "billing"]);
+            }
+            other => panic!("Unexpected result: {other:?}"),
+        }
+        assert!(matches!(
+            decode(&["account", "other"], AudienceMatching::AllOf),
+            Err(AuthError::UnexpectedAudience {
+                required: _,
+                present: _
+            })
+        ));
+    }
+
+    #[test]
+    fn exposes_user_profile() {
+        let mut claims = claims();
+        claims["preferred_username"] = json!("jdoe");
+        claims["full_name"] = json!("John Doe");
+        claims["email"] = json!("john.doe@example.com");
+        let token = decode(&encode(&claims)).unwrap();
+        assert_eq!(token.username(), Some("jdoe"));
+        assert_eq!(token.full_name(), Some("John Doe"));
+        assert_eq!(token.email(), Some("john.doe@example.com"));
+
+        let token = decode(&encode(&self::claims())).unwrap();
+        assert_eq!(token.username(), None);
+        assert_eq!(token.email(), None);
+    }
+
+    #[test]
+    fn exposes_user_profile_of_custom_extra() {
+        #[derive(Debug, Clone, Deserialize)]
+        struct Login {
+            login: String,
+        }
+
+        impl UserProfile for Login {
+            fn username(&self) -> Option<&str> {
+                Some(&self.login)
+            }
+        }
+
+        let mut claims = claims();
+        claims["login"] = json!("jdoe");
+        let layer = KeycloakAuthLayer::<String, Login>::builder()
+            .instance(layer(Duration::ZERO).instance)
+            .expected_audiences(vec![String::from("account")])
+            .build();
+        let token = decode_with(&layer, &encode(&claims)).unwrap();
+        assert_eq!(token.username(), Some("jdoe"));
+        assert_eq!(token.full_name(), None);
+    }
+
+    #[test]
+    fn converts_into_user_context() {
+        let mut claims = claims();
+        claims["preferred_username"] = json!("jdoe");
+        claims["email"] = json!("john.doe@example.com");
+        claims["resource_access"] = json!({ "billing": { "roles": ["manage"] } });
+        let token = decode(&encode(&claims)).unwrap();
+        assert_eq!(
+            UserContext::from(&token),
+            UserContext {
+                id: String::from("f8f2e2e4-6a10-4b23-8b2d-3c5a2a1d9e7f"),
+                username: Some(String::from("jdoe")),
+                roles: vec![
+                    String::from("realm:administrator"),
+                    String::from("client:billing:manage")
+                ],
+                email: Some(String::from("john.doe@example.com")),
+            }
+        );
+    }
+
+    #[test]
+    fn expands_roles_before_role_checks() {
+        let mut claims = claims();
+        claims["realm_access"] = json!({ "roles": ["administrator", "editor"] });
+        claims["resource_access"] = json!({ "billing": { "roles": ["manage"] } });
+        let mut layer = layer(Duration::ZERO);
+        layer.required_roles = vec![String::from("viewer")];
+        layer.role_expansion = Some(Arc::new(|role: &KeycloakRole<String>| {
+            match role.role().as_str() {
+                "administrator" => vec![String::from("editor"), String::from("viewer")],
+                "editor" => vec![String::from("viewer")],
+                "manage" => vec![String::from("read")],
+                _ => vec![],
+            }
+        }));
+
+        let token = decode_with(&layer, &encode(&claims)).unwrap();
+        assert_eq!(token.realm_roles(), ["administrator", "editor", "viewer"]);
+        assert_eq!(token.client_roles("billing"), ["manage", "read"]);
+        assert!(!token.has_scoped_role(&KeycloakRole::Realm {
+            role: String::from("read")
+        }));
+
+        layer.role_expansion = None;
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::MissingExpectedRole { role: _ })
+        ));
+    }
+
+    #[test]
+    fn treats_configured_scopes_as_roles() {
+        let mut claims = claims();
+        claims["realm_access"] = json!({ "roles": [] });
+        claims["scope"] = json!("openid invoices.read");
+        let mut layer = layer(Duration::ZERO);
+        layer.required_roles = vec![String::from("invoices.read")];
+        layer.roles_from_scopes = vec![
+            String::from("invoices.read"),
+            String::from("invoices.write"),
+        ];
+
+        let token = decode_with(&layer, &encode(&claims)).unwrap();
+        assert_eq!(
+            token.roles,
+            [KeycloakRole::Scope {
+                resource: None,
+                scope: String::from("invoices.read")
+            }]
+        );
+        assert_eq!(token.roles[0].to_string(), "scope:invoices.read");
+        assert!(token.has_role("invoices.read"));
+        assert!(!token.has_role("invoices.write"));
+        assert!(token.realm_roles().is_empty());
+
+        // Roles satisfy the requirement just the same.
+        claims["realm_access"] = json!({ "roles": ["invoices.read"] });
+        claims["scope"] = json!("openid");
+        assert!(decode_with(&layer, &encode(&claims)).is_ok());
+
+        layer.roles_from_scopes = vec![];
+        claims["realm_access"] = json!({ "roles": [] });
+        claims["scope"] = json!("openid invoices.read");
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::MissingExpectedRole { role: _ })
+        ));
+    }
+
+    #[test]
+    fn extracts_uma_permissions_if_configured() {
+        let mut claims = claims();
+        claims["authorization"] = json!({
+            "permissions": [
+                { "rsid": "5b3c", "rsname": "document:42", "scopes": ["view", "delete"] },
+                { "rsid": "9f1a", "scopes": ["view"] },
+            ]
+        });
+        let mut layer = layer(Duration::ZERO);
+        layer.uma_permissions = true;
+        layer.persist_raw_claims = true;
+        layer.required_scoped_roles = vec![KeycloakRole::Scope {
+            resource: Some(String::from("document:42")),
+            scope: String::from("delete"),
+        }];
+
+        let token = decode_with(&layer, &encode(&claims)).unwrap();
+        assert!(token.has_permission("document:42", "view"));
+        assert!(token.has_permission("9f1a", "view"));
+        assert!(!token.has_permission("9f1a", "delete"));
+        assert_eq!(token.realm_roles(), ["administrator"]);
+        assert!(token
+            .roles
+            .iter()
+            .any(|role| role.to_string() == "scope:document:42:delete"));
+        let (raw_claims, _) =
+            futures::executor::block_on(layer.validate_raw_token(&encode(&claims))).unwrap();
+        assert_eq!(
+            raw_claims.unwrap()["authorization"],
+            claims["authorization"]
+        );
+
+        claims["authorization"]["permissions"][0]["scopes"] = json!(["view"]);
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::MissingExpectedRole { role: _ })
+        ));
+
+        // Ignored unless configured, even if malformed.
+        layer.uma_permissions = false;
+        layer.required_scoped_roles = vec![];
+        claims["authorization"] = json!("malformed");
+        let token = decode_with(&layer, &encode(&claims)).unwrap();
+        assert_eq!(token.realm_roles(), ["administrator"]);
+        assert!(!token.has_permission("document:42", "view"));
+        let (raw_claims, _) =
+            futures::executor::block_on(layer.validate_raw_token(&encode(&claims))).unwrap();
+        assert_eq!(raw_claims.unwrap()["authorization"], "malformed");
+    }
+
+    #[test]
+    fn uma_permissions_do_not_grant_roles() {
+        let mut claims = claims();
+        claims["authorization"] = json!({
+            "permissions": [{ "rsid": "5b3c", "rsname": "document:42", "scopes": ["delete"] }]
+        });
+        let mut layer = layer(Duration::ZERO);
+        layer.uma_permissions = true;
+        layer.role_expansion = Some(Arc::new(|role: &KeycloakRole<String>| {
+            match role.role().as_str() {
+                "delete" => vec![String::from("administrator-of-documents")],
+                _ => vec![],
+            }
+        }));
+
+        let token = decode_with(&layer, &encode(&claims)).unwrap();
+        assert!(token.has_permission("document:42", "delete"));
+        assert!(!token.has_role("delete"));
+        assert!(token.expect_roles(&["delete"]).is_err());
+        assert!(!token.has_role("administrator-of-documents"));
+        assert_eq!(token.roles.len(), 2);
+
+        layer.required_roles = vec![String::from("delete")];
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::MissingExpectedRole { role: _ })
+        ));
+    }
+
+    #[test]
+    fn reads_roles_from_configured_claims() {
+        let mut config = KeycloakConfig::builder()
+            .server(Url::parse("https://localhost:8443/").unwrap())
+            .realm(String::from("MyRealm"))
+            .build();
+        config.role_claims = vec![
+            RoleClaim::realm("/roles"),
+            RoleClaim::client("/app/permissions", "billing"),
+            RoleClaim::realm("/absent"),
+        ];
+        let mut layer = KeycloakAuthLayer::<String>::builder()
+            .instance(KeycloakAuthInstance::from_secret(
+                config,
+                SECRET,
+                Algorithm::HS256,
+            ))
+            .expected_audiences(vec![String::from("account")])
+            .required_roles(vec![String::from("auditor")])
+            .build();
+
+        let mut claims = claims();
+        claims["roles"] = json!(["auditor", "administrator", 42]);
+        claims["app"] = json!({ "permissions": "manage" });
+        let token = decode_with(&layer, &encode(&claims)).unwrap();
+        assert_eq!(token.realm_roles(), ["administrator", "auditor"]);
+        assert_eq!(token.client_roles("billing"), ["manage"]);
+
+        // Claims of unexpected type hold no roles.
+        claims["roles"] = json!({ "auditor": true });
+        claims["app"] = json!(["manage"]);
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::MissingExpectedRole { role: _ })
+        ));
+        layer.required_roles = vec![];
+        let token = decode_with(&layer, &encode(&claims)).unwrap();
+        assert_eq!(token.realm_roles(), ["administrator"]);
+        assert!(token.client_roles("billing").is_empty());
+    }
+
+    #[test]
+    fn normalizes_audiences() {
+        let mut layer = layer(Duration::ZERO);
+        layer.expected_audiences = Vec::new();
+        let decode_aud = |aud: Option<serde_json::Value>| {
+            let mut claims = claims();
+            match aud {
+                Some(aud) => claims["aud"] = aud,
+                None => {
+                    claims.as_object_mut().unwrap().remove("aud");
+                }
+            }
+            decode_with(&layer, &encode(&claims)).unwrap()
+        };
+
+        let single = decode_aud(Some(json!("account")));
+        assert_eq!(single.audience, Some(vec![String::from("account")]));
+        assert_eq!(single.audiences(), ["account"]);
+        assert!(single.has_audience("account"));
+
+        let array = decode_aud(Some(json!(["account", "billing"])));
+        assert_eq!(array.audiences(), ["account", "billing"]);
+        assert!(array.has_audience("billing"));
+        assert!(!array.has_audience("reporting"));
+
+        let empty = decode_aud(Some(json!([])));
+        assert_eq!(empty.audience, Some(Vec::new()));
+        assert!(empty.audiences().is_empty());
+
+        let missing = decode_aud(None);
+        assert_eq!(missing.audience, None);
+        assert!(missing.audiences().is_empty());
+        assert!(!missing.has_audience("account"));
+    }
+
+    #[test]
+    fn extracts_roles_of_configured_clients_only() {
+        let mut claims = claims();
+        claims["realm_access"] = json!({ "roles": ["administrator"] });
+        claims["resource_access"] = json!({
+            "billing": { "roles": ["manage"] },
+            "account": { "roles": ["manage-account", "view-profile"] },
+            "reporting": { "roles": ["view"] },
+        });
+        let mut layer = layer(Duration::ZERO);
+        layer.persist_raw_claims = true;
+        layer.role_clients = Some(vec![String::from("billing"), String::from("reporting")]);
+
+        let (raw_claims, token) =
+            futures::executor::block_on(layer.validate_raw_token(&encode(&claims))).unwrap();
+        assert_eq!(
+            raw_claims.unwrap()["resource_access"],
+            claims["resource_access"]
+        );
+        assert_eq!(token.roles.len(), 3);
+        assert_eq!(token.realm_roles(), ["administrator"]);
+        assert_eq!(token.client_roles("billing"), ["manage"]);
+        assert_eq!(token.client_roles("reporting"), ["view"]);
+        assert!(token.client_roles("account").is_empty());
+
+        layer.required_scoped_roles = vec![KeycloakRole::Client {
+            client: String::from("account"),
+            role: String::from("view-profile"),
+        }];
+        assert!(matches!(
+            decode_with(&layer, &encode(&claims)),
+            Err(AuthError::MissingExpectedRole { role: _ })
+        ));
+
+        layer.role_clients = Some(Vec::new());
+        layer.required_scoped_roles = Vec::new();
+        assert_eq!(
+            decode_with(&layer, &encode(&claims)).unwrap().roles.len(),
+            1
+        );
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn serializes_token() {
+        let mut token = decode(&encode(&claims())).unwrap();
+        token.issued_at = Some(time::OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap());
+        token.expires_at = time::OffsetDateTime::from_unix_timestamp(1_700_000_300).unwrap();
+
+        let serialized = serde_json::to_value(&token).unwrap();
+        assert_eq!(serialized["issued_at"], json!("2023-11-14T22:13:20Z"));
+        assert_eq!(serialized["expires_at"], json!("2023-11-14T22:18:20Z"));
+        assert_eq!(serialized["not_before"], json!(null));
+        assert_eq!(
+            serialized["subject"],
+            json!("f8f2e2e4-6a10-4b23-8b2d-3c5a2a1d9e7f")
+        );
+        assert_eq!(
+            serialized["roles"],
+            json!([{ "type": "Realm", "role": "administrator" }])
+        );
+    }
+}
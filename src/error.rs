@@ -1,7 +1,10 @@
+// The code derived for `AuthError` and its categorization still refer to its deprecated variants.
+#![allow(deprecated)]
+
 use std::{borrow::Cow, sync::Arc};
 
 use axum::{
-    http::StatusCode,
+    http::{HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -10,8 +13,50 @@ use snafu::Snafu;
 
 use crate::oidc_discovery;
 
+/// Builds the response rejecting a request, see `KeycloakAuthLayer::error_response`.
+/// Only its headers and body are used. The status code is always the one of the `AuthError`.
+pub type ErrorResponse = Arc<dyn Fn(&AuthError) -> Response + Send + Sync>;
+
+/// Coarse category of an `AuthError`, see `AuthError::kind`.
+///
+/// Unlike `AuthError`, this enum is not expected to grow. New errors are assigned one of these categories,
+/// so matching on the kind of an error does not break when variants are added to `AuthError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuthErrorKind {
+    /// The request is not authenticated. No token was sent, or it is expired, revoked or otherwise invalid.
+    Unauthenticated,
+    /// The token is valid, but lacks a role, group, scope or other property required for this request.
+    Forbidden,
+    /// The request could not be authenticated because of a problem on our side, e.g. a failed OIDC discovery.
+    Server,
+    /// The request itself is malformed, e.g. carries an 'Authorization' header using an unaccepted scheme or an empty token.
+    Malformed,
+}
+
+impl AuthErrorKind {
+    /// The gRPC status code (see the `grpc-status` trailer) corresponding to errors of this kind,
+    /// e.g. to reject calls with a `tonic::Status::new(tonic::Code::from(kind.grpc_code()), message)`.
+    /// With the `tonic` feature enabled, `AuthError` converts into such a `tonic::Status`.
+    /// Consistent with `AuthError::status_code`: 16 (UNAUTHENTICATED) for unauthenticated and malformed requests,
+    /// 7 (PERMISSION_DENIED) for forbidden ones and 13 (INTERNAL) for server errors.
+    pub fn grpc_code(&self) -> i32 {
+        match self {
+            AuthErrorKind::Unauthenticated | AuthErrorKind::Malformed => 16,
+            AuthErrorKind::Forbidden => 7,
+            AuthErrorKind::Server => 13,
+        }
+    }
+}
+
+/// Errors of this crate. New variants may be added in minor releases.
+/// Match on `AuthError::kind` instead if you are only interested in the category of an error.
+///
+/// Implements `std::error::Error` (and is `Send + Sync + 'static`), exposing the underlying jsonwebtoken, serde or reqwest error
+/// through `source()`, so that `anyhow` or `eyre` report the full chain. Sources shared through an `Arc`,
+/// keeping this error `Clone`, display and chain like the wrapped error, but downcast to `Arc<E>`.
 #[derive(Debug, Clone, Snafu)]
 #[snafu(visibility(pub(crate)))]
+#[non_exhaustive]
 pub enum AuthError {
     /// OIDC discovery never happened.
     #[snafu(display("Never discovered a OIDC configuration."))]
@@ -24,6 +69,14 @@ pub enum AuthError {
         source: oidc_discovery::RequestError,
     },
 
+    /// The initial OIDC discovery did not succeed in time.
+    #[snafu(display("The initial OIDC discovery did not succeed within {timeout:?}."))]
+    DiscoveryTimeout { timeout: std::time::Duration },
+
+    /// The configuration of a `KeycloakAuthInstance` was invalid, e.g. lacking a realm or containing a malformed server URL.
+    #[snafu(display("Invalid Keycloak configuration: {reason}"))]
+    InvalidConfiguration { reason: String },
+
     /// JWK set discovery never happened.
     #[snafu(display("Never discovered a JWK set."))]
     NoJwkSetDiscovery,
@@ -38,21 +91,70 @@ pub enum AuthError {
         source: oidc_discovery::RequestError,
     },
 
+    /// The public key an AWS ALB signed its 'x-amzn-oidc-data' with could not be fetched, see `alb::AlbValidator`.
+    #[snafu(display("Could not fetch the public key '{kid}' of the load balancer."))]
+    AlbKeyDiscovery {
+        kid: String,
+        source: oidc_discovery::RequestError,
+    },
+
+    /// The token could not be introspected.
+    #[snafu(display("Could not introspect the token."))]
+    Introspection {
+        source: oidc_discovery::RequestError,
+    },
+
+    /// The token endpoint could not be reached, or its response could not be parsed.
+    #[snafu(display("Could not request a token from the token endpoint."))]
+    TokenRequest {
+        source: oidc_discovery::RequestError,
+    },
+
+    /// The token endpoint rejected the grant, e.g. because the refresh token expired, was revoked or belongs to another client.
+    #[snafu(display("The grant was rejected by the token endpoint: {}", description.as_deref().unwrap_or("No description given.")))]
+    InvalidGrant { description: Option<String> },
+
+    /// The token endpoint responded with an error other than 'invalid_grant', e.g. because of invalid client credentials.
+    #[snafu(display("The token endpoint responded with the error '{error}': {}", description.as_deref().unwrap_or("No description given.")))]
+    TokenEndpoint {
+        error: String,
+        description: Option<String>,
+    },
+
+    /// The introspection endpoint reported the token to be inactive, e.g. because it was revoked.
+    #[snafu(display("The token is not active."))]
+    InactiveToken,
+
     /// The 'Authorization' header was not present on a request.
     #[snafu(display("The 'Authorization' header was not present on a request."))]
     MissingAuthorizationHeader,
 
-    /// The 'Authorization' header was present on a request but its value could not be parsed.
-    /// This can occur if the header value did not solely contain visible ASCII characters.
+    /// The 'Authorization' header was present on a request but no token could be taken from it.
+    /// This can occur if the header value did not solely contain visible ASCII characters,
+    /// used a scheme other than the accepted ones (e.g. "Basic") or contained no token after the scheme.
+    #[snafu(display("The 'Authorization' header was present on a request but it was malformed. Reason: {reason}"))]
+    MalformedAuthorizationHeader { reason: String },
+
+    /// No longer returned. Malformed 'Authorization' headers are reported as `MalformedAuthorizationHeader`.
+    #[deprecated(note = "Reported as `AuthError::MalformedAuthorizationHeader` instead.")]
     #[snafu(display("The 'Authorization' header was present on a request but its value could not be parsed. Reason: {reason}"))]
     InvalidAuthorizationHeader { reason: String },
 
-    /// The 'Authorization' header was present  and could be parsed, but it did not contain the expected "Bearer {token}" format.
+    /// No longer returned. Malformed 'Authorization' headers are reported as `MalformedAuthorizationHeader`.
+    #[deprecated(note = "Reported as `AuthError::MalformedAuthorizationHeader` instead.")]
     #[snafu(display(
         "The 'Authorization' header did not contain the expected 'Bearer ...token' format."
     ))]
     MissingBearerToken,
 
+    /// The header expected to contain the token was not present on a request.
+    #[snafu(display("The '{header}' header was not present on a request."))]
+    MissingTokenHeader { header: String },
+
+    /// The header expected to contain the token was present on a request but no token could be taken from it.
+    #[snafu(display("The '{header}' header was present on a request but its value could not be used. Reason: {reason}"))]
+    InvalidTokenHeader { header: String, reason: String },
+
     /// No query parameters were found on the request.
     #[snafu(display("No query parameters were found on the request."))]
     MissingQueryParams,
@@ -67,6 +169,20 @@ pub enum AuthError {
     #[snafu(display("Query parameters were found on the request, and the expected token parameter was found, but it had no value assigned (\"?token=\")."))]
     EmptyTokenQueryParam,
 
+    /// The cookie expected to contain the token was not present on the request.
+    #[snafu(display("The cookie expected to contain the token was not present on the request."))]
+    MissingTokenCookie,
+
+    /// The cookie expected to contain the token was present, but it had no value assigned ("token=").
+    #[snafu(display(
+        "The cookie expected to contain the token was present, but it had no value assigned."
+    ))]
+    EmptyTokenCookie,
+
+    /// None of the configured token extractors found a token on the request.
+    #[snafu(display("No token was found on the request. Reasons: {}", reasons.join(" ")))]
+    NoToken { reasons: Vec<String> },
+
     /// The DecodingKey, required for decoding tokens, could not be created.
     #[snafu(display(
         "The DecodingKey, required for decoding tokens, could not be created. Source: {source}"
@@ -81,6 +197,15 @@ pub enum AuthError {
     #[snafu(display("There were no decoding keys available."))]
     NoDecodingKeys,
 
+    /// None of the statically configured decoding keys was able to verify the token.
+    #[snafu(display("None of the known decoding keys matched the token."))]
+    NoMatchingDecodingKey,
+
+    /// The token header announced an algorithm which is not allowed, see `KeycloakConfig::allowed_algorithms`,
+    /// or no signature algorithm at all, e.g. "none". `algorithm` is the 'alg' parameter of the header, e.g. "HS512".
+    #[snafu(display("The token uses a disallowed algorithm: {algorithm}"))]
+    DisallowedAlgorithm { algorithm: String },
+
     /// The JWT could not be decoded.
     #[snafu(display("The JWT could not be decoded. Source: {source}"))]
     Decode { source: jsonwebtoken::errors::Error },
@@ -93,6 +218,79 @@ pub enum AuthError {
     #[snafu(display("The tokens lifetime is expired."))]
     TokenExpired,
 
+    /// The tokens not-before time was not yet reached.
+    #[snafu(display("The token is not yet valid."))]
+    TokenNotYetValid,
+
+    /// The user did not authenticate strongly enough (the tokens 'acr' claim), see `KeycloakAuthLayer::minimum_acr`.
+    /// Reported with the `insufficient_user_authentication` error code of RFC 9470, asking the client to step up authentication.
+    #[snafu(display(
+        "The authentication level of the token is insufficient. Required: {required}. Present: {}",
+        present.as_deref().unwrap_or("none")
+    ))]
+    InsufficientAuthenticationLevel {
+        required: String,
+        present: Option<String>,
+    },
+
+    /// The token was issued longer ago than allowed, see `KeycloakAuthLayer::max_token_age`. Rejected regardless of its expiry.
+    #[snafu(display("The token is too old."))]
+    TokenTooOld,
+
+    /// The user authenticated longer ago than allowed, see `KeycloakAuthLayer::max_auth_age`. The user must log in again.
+    #[snafu(display("The user authenticated too long ago."))]
+    AuthenticationTooOld,
+
+    /// The token was issued to a client (its 'azp' claim) not allowed to access this service.
+    #[snafu(display("The token was issued to an unexpected client: {authorized_party}"))]
+    UnexpectedAuthorizedParty { authorized_party: String },
+
+    /// The token was issued neither for (its 'aud' claim) nor to (its 'azp' claim) this service, see `KeycloakAuthLayer::client_id`.
+    #[snafu(display("The token was not issued for the client {client_id}"))]
+    NotIssuedForClient { client_id: String },
+
+    /// The token is not of the expected type (its 'typ' claim), e.g. a refresh token presented instead of an access token.
+    #[snafu(display("The token is of an unexpected type: {token_type}"))]
+    UnexpectedTokenType { token_type: String },
+
+    /// The audiences of the token do not match the expected audiences, see `KeycloakAuthLayer::audience_matching`.
+    /// Note: Responses will only show the audiences in a debug build!
+    #[snafu(display(
+        "The token audiences do not match. Required: {}. Present: {}",
+        required.join(", "),
+        present.join(", ")
+    ))]
+    UnexpectedAudience {
+        required: Vec<String>,
+        present: Vec<String>,
+    },
+
+    /// The ID token does not carry the nonce of the authentication request it was issued for. It may have been replayed.
+    #[snafu(display("The ID token does not carry the expected nonce."))]
+    UnexpectedNonce,
+
+    /// The token was revoked, as reported by the `jti_blocklist` of the layer.
+    #[snafu(display("The token was revoked."))]
+    TokenRevoked,
+
+    /// The token was not issued by any of the expected issuers.
+    #[snafu(display("The token was issued by an unexpected issuer."))]
+    InvalidIssuer,
+
+    /// The token was issued by a realm none of the configured instances is responsible for.
+    /// The `issuer` is taken from the unverified token and never included in responses.
+    #[snafu(display("The token was issued by an unknown realm."))]
+    UnknownRealm { issuer: String },
+
+    /// A claim required by the layer (e.g. its `tenant_claim`) was missing or not a string.
+    #[snafu(display("The token is missing the required claim '{claim}'."))]
+    MissingClaim { claim: String },
+
+    /// A `TenantId` was extracted from a request not authenticated with a tenant.
+    /// Either authentication failed (in `PassthroughMode::Pass`) or the layer has no `tenant_claim` configured.
+    #[snafu(display("No tenant is known for this request."))]
+    MissingTenantId,
+
     /// For a not further known reason, the token was deemed invalid
     #[snafu(display(
         "For a not further known reason, the token was deemed invalid: Reason: {reason}"
@@ -103,88 +301,942 @@ pub enum AuthError {
     #[snafu(display("An expected role (omitted for security reasons) was missing."))]
     MissingExpectedRole { role: String },
 
+    /// Several expected roles were missing, see `ExpectRoles::expect_roles`. A single missing role is reported as `MissingExpectedRole`.
+    /// `ExpectRoles::expect_any_role` lists all of the roles of which none was present.
+    /// Note: The `IntoResponse` implementation will only show the missing roles in a debug build!
+    #[snafu(display("Expected roles (omitted for security reasons) were missing."))]
+    MissingExpectedRoles { roles: Vec<String> },
+
+    /// Note: The `IntoResponse` implementation will only show the provided group in a debug build!
+    #[snafu(display("An expected group (omitted for security reasons) was missing."))]
+    MissingExpectedGroup { group: String },
+
+    /// Not all scopes required by the layer were granted to the token.
+    /// Distinct from the role errors, as scopes are what the client was granted, not what the user is allowed to do.
+    #[snafu(display(
+        "The token lacks required scopes. Required: {}. Present: {}",
+        required.join(", "),
+        present.join(", ")
+    ))]
+    InsufficientScope {
+        required: Vec<String>,
+        present: Vec<String>,
+    },
+
+    /// No longer returned. Missing scopes are reported as `InsufficientScope`, listing all of them.
+    #[deprecated(note = "Reported as `AuthError::InsufficientScope` instead.")]
+    #[snafu(display("An expected scope was missing: {scope}"))]
+    MissingExpectedScope { scope: String },
+
+    /// The email address of the user is not verified, but a verified email address is required.
+    #[snafu(display("The users email address is not verified."))]
+    EmailNotVerified,
+
     /// An unexpected role was present.
     #[snafu(display("An unexpected role was present."))]
     UnexpectedRole,
+
+    /// The `authorizer` of the layer denied the request. Responded to with exactly the given `status` and JSON `body`.
+    /// Its `kind` follows the status: `Unauthenticated` for a 401, `Server` for 5xx and `Forbidden` for any other status.
+    #[snafu(display("The request was denied by the authorizer."))]
+    AccessDenied {
+        status: StatusCode,
+        body: serde_json::Value,
+    },
+
+    /// The token carries no roles at all, see `KeycloakAuthLayer::reject_tokens_without_roles`.
+    #[snafu(display("The token carries no roles. Check the role mappers of the client scopes of the client it was issued to."))]
+    NoRoles,
 }
 
-impl IntoResponse for AuthError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            err @ AuthError::NoOidcDiscovery => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Cow::Owned(err.to_string()),
-            ),
-            err @ AuthError::OidcDiscovery { source: _ } => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Cow::Owned(err.to_string()),
-            ),
-            err @ AuthError::NoJwkSetDiscovery => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Cow::Owned(err.to_string()),
-            ),
-            err @ AuthError::JwkEndpoint { source: _ } => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Cow::Owned(err.to_string()),
-            ),
-            err @ AuthError::JwkSetDiscovery { source: _ } => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Cow::Owned(err.to_string()),
-            ),
-            err @ AuthError::MissingAuthorizationHeader => {
-                (StatusCode::UNAUTHORIZED, Cow::Owned(err.to_string()))
+impl AuthError {
+    /// The category of this error. Consistent with the `status_code`: `Unauthenticated` and `Malformed` errors
+    /// result in a `401 Unauthorized`, `Forbidden` ones in a `403 Forbidden` and `Server` ones in a `500 Internal Server Error`.
+    pub fn kind(&self) -> AuthErrorKind {
+        match self {
+            AuthError::NoOidcDiscovery
+            | AuthError::OidcDiscovery { source: _ }
+            | AuthError::DiscoveryTimeout { timeout: _ }
+            | AuthError::InvalidConfiguration { reason: _ }
+            | AuthError::NoJwkSetDiscovery
+            | AuthError::JwkEndpoint { source: _ }
+            | AuthError::JwkSetDiscovery { source: _ }
+            | AuthError::AlbKeyDiscovery { kid: _, source: _ }
+            | AuthError::Introspection { source: _ }
+            | AuthError::TokenRequest { source: _ }
+            | AuthError::TokenEndpoint {
+                error: _,
+                description: _,
             }
-            err @ AuthError::InvalidAuthorizationHeader { reason: _ } => {
-                (StatusCode::UNAUTHORIZED, Cow::Owned(err.to_string()))
+            | AuthError::CreateDecodingKey { source: _ }
+            | AuthError::JsonParse { source: _ } => AuthErrorKind::Server,
+            AuthError::MalformedAuthorizationHeader { reason: _ }
+            | AuthError::InvalidAuthorizationHeader { reason: _ }
+            | AuthError::MissingBearerToken
+            | AuthError::InvalidTokenHeader {
+                header: _,
+                reason: _,
             }
-            err @ AuthError::MissingBearerToken => {
-                (StatusCode::UNAUTHORIZED, Cow::Owned(err.to_string()))
+            | AuthError::EmptyTokenQueryParam
+            | AuthError::EmptyTokenCookie => AuthErrorKind::Malformed,
+            AuthError::MissingAuthorizationHeader
+            | AuthError::MissingTokenHeader { header: _ }
+            | AuthError::MissingQueryParams
+            | AuthError::MissingTokenQueryParam
+            | AuthError::MissingTokenCookie
+            | AuthError::NoToken { reasons: _ }
+            | AuthError::InactiveToken
+            | AuthError::InvalidGrant { description: _ }
+            | AuthError::DecodeHeader { source: _ }
+            | AuthError::NoDecodingKeys
+            | AuthError::NoMatchingDecodingKey
+            | AuthError::DisallowedAlgorithm { algorithm: _ }
+            | AuthError::Decode { source: _ }
+            | AuthError::TokenExpired
+            | AuthError::TokenNotYetValid
+            | AuthError::TokenTooOld
+            | AuthError::AuthenticationTooOld
+            | AuthError::InsufficientAuthenticationLevel {
+                required: _,
+                present: _,
             }
-            err @ AuthError::MissingQueryParams => {
-                (StatusCode::UNAUTHORIZED, Cow::Owned(err.to_string()))
+            | AuthError::TokenRevoked
+            | AuthError::UnexpectedAudience {
+                required: _,
+                present: _,
             }
-            err @ AuthError::MissingTokenQueryParam => {
-                (StatusCode::UNAUTHORIZED, Cow::Owned(err.to_string()))
+            | AuthError::UnexpectedAuthorizedParty {
+                authorized_party: _,
             }
-            err @ AuthError::EmptyTokenQueryParam => {
-                (StatusCode::UNAUTHORIZED, Cow::Owned(err.to_string()))
+            | AuthError::NotIssuedForClient { client_id: _ }
+            | AuthError::UnexpectedTokenType { token_type: _ }
+            | AuthError::UnexpectedNonce
+            | AuthError::InvalidIssuer
+            | AuthError::UnknownRealm { issuer: _ }
+            | AuthError::MissingClaim { claim: _ }
+            | AuthError::MissingTenantId
+            | AuthError::InvalidToken { reason: _ } => AuthErrorKind::Unauthenticated,
+            AuthError::MissingExpectedRole { role: _ }
+            | AuthError::MissingExpectedRoles { roles: _ }
+            | AuthError::MissingExpectedGroup { group: _ }
+            | AuthError::InsufficientScope {
+                required: _,
+                present: _,
             }
-            err @ AuthError::CreateDecodingKey { source: _ } => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Cow::Owned(err.to_string()),
-            ),
-            err @ AuthError::DecodeHeader { source: _ } => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Cow::Owned(err.to_string()),
-            ),
-            err @ AuthError::NoDecodingKeys => {
-                (StatusCode::UNAUTHORIZED, Cow::Owned(err.to_string()))
+            | AuthError::MissingExpectedScope { scope: _ }
+            | AuthError::EmailNotVerified
+            | AuthError::UnexpectedRole
+            | AuthError::NoRoles => AuthErrorKind::Forbidden,
+            AuthError::AccessDenied { status, body: _ } => match *status {
+                StatusCode::UNAUTHORIZED => AuthErrorKind::Unauthenticated,
+                status if status.is_server_error() => AuthErrorKind::Server,
+                _ => AuthErrorKind::Forbidden,
+            },
+        }
+    }
+
+    /// The RFC 6750 error code describing this error in a `WWW-Authenticate: Bearer` header.
+    ///
+    /// `None` if the request did not contain a token at all, in which case clients must only be told that a token is required,
+    /// or if the error is not caused by the request.
+    pub fn bearer_error_code(&self) -> Option<&'static str> {
+        match self {
+            AuthError::NoOidcDiscovery
+            | AuthError::OidcDiscovery { source: _ }
+            | AuthError::DiscoveryTimeout { timeout: _ }
+            | AuthError::InvalidConfiguration { reason: _ }
+            | AuthError::NoJwkSetDiscovery
+            | AuthError::JwkEndpoint { source: _ }
+            | AuthError::JwkSetDiscovery { source: _ }
+            | AuthError::AlbKeyDiscovery { kid: _, source: _ }
+            | AuthError::Introspection { source: _ }
+            | AuthError::TokenRequest { source: _ }
+            | AuthError::TokenEndpoint {
+                error: _,
+                description: _,
             }
-            err @ AuthError::Decode { source: _ } => {
-                (StatusCode::UNAUTHORIZED, Cow::Owned(err.to_string()))
+            | AuthError::CreateDecodingKey { source: _ }
+            | AuthError::JsonParse { source: _ } => None,
+            // The authorizer decides what is reported.
+            AuthError::AccessDenied { status: _, body: _ } => None,
+            AuthError::MissingAuthorizationHeader
+            | AuthError::MissingTokenHeader { header: _ }
+            | AuthError::MissingQueryParams
+            | AuthError::MissingTokenQueryParam
+            | AuthError::MissingTokenCookie
+            | AuthError::MissingTenantId
+            | AuthError::NoToken { reasons: _ } => None,
+            AuthError::MalformedAuthorizationHeader { reason: _ }
+            | AuthError::InvalidAuthorizationHeader { reason: _ }
+            | AuthError::MissingBearerToken
+            | AuthError::InvalidTokenHeader {
+                header: _,
+                reason: _,
             }
-            err @ AuthError::JsonParse { source: _ } => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Cow::Owned(err.to_string()),
-            ),
-            err @ AuthError::TokenExpired => {
-                (StatusCode::UNAUTHORIZED, Cow::Owned(err.to_string()))
+            | AuthError::EmptyTokenQueryParam
+            | AuthError::EmptyTokenCookie => Some("invalid_request"),
+            AuthError::InactiveToken
+            | AuthError::InvalidGrant { description: _ }
+            | AuthError::DecodeHeader { source: _ }
+            | AuthError::NoDecodingKeys
+            | AuthError::NoMatchingDecodingKey
+            | AuthError::DisallowedAlgorithm { algorithm: _ }
+            | AuthError::Decode { source: _ }
+            | AuthError::TokenExpired
+            | AuthError::TokenNotYetValid
+            | AuthError::TokenTooOld
+            | AuthError::AuthenticationTooOld
+            | AuthError::TokenRevoked
+            | AuthError::UnexpectedAudience {
+                required: _,
+                present: _,
             }
-            err @ AuthError::InvalidToken { reason: _ } => {
-                (StatusCode::UNAUTHORIZED, Cow::Owned(err.to_string()))
+            | AuthError::UnexpectedAuthorizedParty {
+                authorized_party: _,
             }
-            AuthError::MissingExpectedRole { role } => (
-                StatusCode::FORBIDDEN,
-                match cfg!(debug_assertions) {
-                    true => Cow::Owned(format!("Missing expected role: {role}")),
-                    false => Cow::Borrowed("Missing expected role"),
-                },
-            ),
-            err @ AuthError::UnexpectedRole => (StatusCode::FORBIDDEN, Cow::Owned(err.to_string())),
+            | AuthError::NotIssuedForClient { client_id: _ }
+            | AuthError::UnexpectedTokenType { token_type: _ }
+            | AuthError::UnexpectedNonce
+            | AuthError::InvalidIssuer
+            | AuthError::UnknownRealm { issuer: _ }
+            | AuthError::MissingClaim { claim: _ }
+            | AuthError::InvalidToken { reason: _ } => Some("invalid_token"),
+            AuthError::MissingExpectedRole { role: _ }
+            | AuthError::MissingExpectedRoles { roles: _ }
+            | AuthError::MissingExpectedGroup { group: _ }
+            | AuthError::InsufficientScope {
+                required: _,
+                present: _,
+            }
+            | AuthError::MissingExpectedScope { scope: _ }
+            | AuthError::EmailNotVerified
+            | AuthError::UnexpectedRole
+            | AuthError::NoRoles => Some("insufficient_scope"),
+            AuthError::InsufficientAuthenticationLevel {
+                required: _,
+                present: _,
+            } => Some("insufficient_user_authentication"),
+        }
+    }
+
+    /// Like `into_response`, but includes the given `realm` in the `WWW-Authenticate` header.
+    /// Used by the `KeycloakAuthService` to reject requests, passing the realm of its instance.
+    pub fn into_response_for_realm(self, realm: &str) -> Response {
+        self.response(Some(realm), None)
+    }
+
+    /// Like `into_response_for_realm`, but lets the given `error_response` build the headers and body of the response.
+    /// Its status code is replaced by the `status_code` of this error, and the `WWW-Authenticate` header is still set.
+    pub(crate) fn into_custom_response_for_realm(
+        self,
+        realm: &str,
+        error_response: &ErrorResponse,
+    ) -> Response {
+        self.response(Some(realm), Some(error_response))
+    }
+
+    fn response(self, realm: Option<&str>, error_response: Option<&ErrorResponse>) -> Response {
+        let error_code = self.bearer_error_code();
+        let param = match &self {
+            // RFC 6750, section 3: The scopes necessary to access the resource.
+            AuthError::InsufficientScope {
+                required,
+                present: _,
+            } => Some(("scope", required.join(" "))),
+            // RFC 9470, section 3: The authentication context class required.
+            AuthError::InsufficientAuthenticationLevel {
+                required,
+                present: _,
+            } => Some(("acr_values", required.clone())),
+            _ => None,
         };
+        let www_authenticate = www_authenticate(realm, error_code, &self.response_message(), param);
+        let mut response = match error_response {
+            Some(error_response) => {
+                let mut response = error_response(&self);
+                // Never let a custom response turn a rejection into a success.
+                *response.status_mut() = self.status_code();
+                response
+            }
+            None => self.message_response(),
+        };
+        let status = response.status();
+        // Only `insufficient_scope` is also reported along with a 403.
+        if status == StatusCode::UNAUTHORIZED
+            || (status == StatusCode::FORBIDDEN && error_code == Some("insufficient_scope"))
+        {
+            if let Ok(value) = HeaderValue::from_str(&www_authenticate) {
+                response
+                    .headers_mut()
+                    .insert(http::header::WWW_AUTHENTICATE, value);
+            }
+        }
+        response
+    }
+
+    /// The HTTP status code responses for this error are sent with.
+    ///
+    /// - `401 Unauthorized`: The request is not authenticated. No token was sent, or it is malformed, expired or otherwise invalid.
+    /// - `403 Forbidden`: The token is valid, but lacks a role, group, scope or other property required for this request.
+    /// - `500 Internal Server Error`: The token could not be checked because of a problem on our side, e.g. a failed OIDC discovery.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::NoOidcDiscovery
+            | AuthError::OidcDiscovery { source: _ }
+            | AuthError::DiscoveryTimeout { timeout: _ }
+            | AuthError::InvalidConfiguration { reason: _ }
+            | AuthError::NoJwkSetDiscovery
+            | AuthError::JwkEndpoint { source: _ }
+            | AuthError::JwkSetDiscovery { source: _ }
+            | AuthError::AlbKeyDiscovery { kid: _, source: _ }
+            | AuthError::Introspection { source: _ }
+            | AuthError::TokenRequest { source: _ }
+            | AuthError::TokenEndpoint {
+                error: _,
+                description: _,
+            }
+            | AuthError::CreateDecodingKey { source: _ }
+            | AuthError::JsonParse { source: _ } => StatusCode::INTERNAL_SERVER_ERROR,
+            AuthError::MissingAuthorizationHeader
+            | AuthError::MalformedAuthorizationHeader { reason: _ }
+            | AuthError::InvalidAuthorizationHeader { reason: _ }
+            | AuthError::MissingBearerToken
+            | AuthError::MissingTokenHeader { header: _ }
+            | AuthError::InvalidTokenHeader {
+                header: _,
+                reason: _,
+            }
+            | AuthError::MissingQueryParams
+            | AuthError::MissingTokenQueryParam
+            | AuthError::EmptyTokenQueryParam
+            | AuthError::MissingTokenCookie
+            | AuthError::EmptyTokenCookie
+            | AuthError::NoToken { reasons: _ }
+            | AuthError::InactiveToken
+            | AuthError::InvalidGrant { description: _ }
+            | AuthError::DecodeHeader { source: _ }
+            | AuthError::NoDecodingKeys
+            | AuthError::NoMatchingDecodingKey
+            | AuthError::DisallowedAlgorithm { algorithm: _ }
+            | AuthError::Decode { source: _ }
+            | AuthError::TokenExpired
+            | AuthError::TokenNotYetValid
+            | AuthError::TokenTooOld
+            | AuthError::AuthenticationTooOld
+            | AuthError::InsufficientAuthenticationLevel {
+                required: _,
+                present: _,
+            }
+            | AuthError::TokenRevoked
+            | AuthError::UnexpectedAudience {
+                required: _,
+                present: _,
+            }
+            | AuthError::UnexpectedAuthorizedParty {
+                authorized_party: _,
+            }
+            | AuthError::NotIssuedForClient { client_id: _ }
+            | AuthError::UnexpectedTokenType { token_type: _ }
+            | AuthError::UnexpectedNonce
+            | AuthError::InvalidIssuer
+            | AuthError::UnknownRealm { issuer: _ }
+            | AuthError::MissingClaim { claim: _ }
+            | AuthError::MissingTenantId
+            | AuthError::InvalidToken { reason: _ } => StatusCode::UNAUTHORIZED,
+            AuthError::MissingExpectedRole { role: _ }
+            | AuthError::MissingExpectedRoles { roles: _ }
+            | AuthError::MissingExpectedGroup { group: _ }
+            | AuthError::InsufficientScope {
+                required: _,
+                present: _,
+            }
+            | AuthError::MissingExpectedScope { scope: _ }
+            | AuthError::EmailNotVerified
+            | AuthError::UnexpectedRole
+            | AuthError::NoRoles => StatusCode::FORBIDDEN,
+            AuthError::AccessDenied { status, body: _ } => *status,
+        }
+    }
+
+    fn message_response(self) -> Response {
+        let status = self.status_code();
+        if let AuthError::AccessDenied { status, body } = self {
+            return (status, Json(body)).into_response();
+        }
         let body = Json(json!({
-            "error": error_message,
+            "error": self.response_message(),
         }));
         (status, body).into_response()
     }
+
+    /// Describes this error in responses. The expected roles, groups, audiences and client
+    /// as well as the algorithm announced by the token are only named in a debug build.
+    pub(crate) fn response_message(&self) -> Cow<'_, str> {
+        match self {
+            AuthError::MissingExpectedRole { role } => match cfg!(debug_assertions) {
+                true => Cow::Owned(format!("Missing expected role: {role}")),
+                false => Cow::Borrowed("Missing expected role"),
+            },
+            AuthError::MissingExpectedRoles { roles } => match cfg!(debug_assertions) {
+                true => Cow::Owned(format!("Missing expected roles: {}", roles.join(", "))),
+                false => Cow::Borrowed("Missing expected roles"),
+            },
+            AuthError::MissingExpectedGroup { group } => match cfg!(debug_assertions) {
+                true => Cow::Owned(format!("Missing expected group: {group}")),
+                false => Cow::Borrowed("Missing expected group"),
+            },
+            AuthError::UnexpectedAudience {
+                required: _,
+                present: _,
+            } => match cfg!(debug_assertions) {
+                true => Cow::Owned(self.to_string()),
+                false => Cow::Borrowed("The token audiences do not match."),
+            },
+            AuthError::DisallowedAlgorithm { algorithm: _ } => match cfg!(debug_assertions) {
+                true => Cow::Owned(self.to_string()),
+                false => Cow::Borrowed("The token uses a disallowed algorithm."),
+            },
+            AuthError::NotIssuedForClient { client_id: _ } => match cfg!(debug_assertions) {
+                true => Cow::Owned(self.to_string()),
+                false => Cow::Borrowed("The token was not issued for this client."),
+            },
+            err => Cow::Owned(err.to_string()),
+        }
+    }
+}
+
+/// Builds the value of a RFC 6750 `WWW-Authenticate: Bearer` header.
+fn www_authenticate(
+    realm: Option<&str>,
+    error_code: Option<&'static str>,
+    error_description: &str,
+    param: Option<(&'static str, String)>,
+) -> String {
+    let mut params = Vec::new();
+    if let Some(realm) = realm {
+        params.push(format!("realm=\"{}\"", quote(realm)));
+    }
+    if let Some(error_code) = error_code {
+        params.push(format!("error=\"{error_code}\""));
+        params.push(format!(
+            "error_description=\"{}\"",
+            quote(error_description)
+        ));
+    }
+    if let Some((name, value)) = param {
+        params.push(format!("{name}=\"{}\"", quote(&value)));
+    }
+    match params.is_empty() {
+        true => String::from("Bearer"),
+        false => format!("Bearer {}", params.join(", ")),
+    }
+}
+
+/// Makes the given string usable inside a quoted-string header parameter.
+/// Characters not allowed in header values are replaced, quotes and backslashes are escaped.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            ' '..='~' => quoted.push(c),
+            _ => quoted.push(' '),
+        }
+    }
+    quoted
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        self.response(None, None)
+    }
+}
+
+#[cfg(feature = "tonic")]
+impl From<AuthError> for tonic::Status {
+    fn from(err: AuthError) -> Self {
+        tonic::Status::new(
+            tonic::Code::from(err.kind().grpc_code()),
+            err.response_message(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::response::IntoResponse;
+
+    use super::{AuthError, AuthErrorKind};
+
+    fn www_authenticate(response: &axum::response::Response) -> Option<&str> {
+        response
+            .headers()
+            .get(http::header::WWW_AUTHENTICATE)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    #[test]
+    fn omits_error_code_without_token() {
+        let response = AuthError::MissingAuthorizationHeader.into_response_for_realm("MyRealm");
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            www_authenticate(&response),
+            Some("Bearer realm=\"MyRealm\"")
+        );
+    }
+
+    #[test]
+    fn reports_invalid_tokens() {
+        let response = AuthError::TokenExpired.into_response_for_realm("MyRealm");
+        assert_eq!(
+            www_authenticate(&response),
+            Some("Bearer realm=\"MyRealm\", error=\"invalid_token\", error_description=\"The tokens lifetime is expired.\"")
+        );
+
+        let response = AuthError::InvalidToken {
+            reason: String::from("a \"quoted\" reason"),
+        }
+        .into_response();
+        assert!(www_authenticate(&response)
+            .unwrap()
+            .contains("a \\\"quoted\\\" reason"));
+
+        // The issuer is read from an unverified token.
+        let response = AuthError::UnknownRealm {
+            issuer: String::from("https://evil.example.com/realms/Evil"),
+        }
+        .into_response_for_realm("MyRealm");
+        assert!(!www_authenticate(&response).unwrap().contains("evil"));
+    }
+
+    #[test]
+    fn reports_insufficient_scope_on_forbidden() {
+        let response = AuthError::UnexpectedRole.into_response_for_realm("MyRealm");
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+        assert!(www_authenticate(&response)
+            .unwrap()
+            .starts_with("Bearer realm=\"MyRealm\", error=\"insufficient_scope\""));
+    }
+
+    #[test]
+    fn reports_required_scopes() {
+        let response = AuthError::InsufficientScope {
+            required: vec![String::from("openid"), String::from("email")],
+            present: vec![String::from("openid")],
+        }
+        .into_response_for_realm("MyRealm");
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+        assert_eq!(
+            www_authenticate(&response),
+            Some("Bearer realm=\"MyRealm\", error=\"insufficient_scope\", error_description=\"The token lacks required scopes. Required: openid, email. Present: openid\", scope=\"openid email\"")
+        );
+    }
+
+    #[test]
+    fn asks_for_step_up_authentication() {
+        let response = AuthError::InsufficientAuthenticationLevel {
+            required: String::from("2"),
+            present: Some(String::from("1")),
+        }
+        .into_response_for_realm("MyRealm");
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            www_authenticate(&response),
+            Some("Bearer realm=\"MyRealm\", error=\"insufficient_user_authentication\", error_description=\"The authentication level of the token is insufficient. Required: 2. Present: 1\", acr_values=\"2\"")
+        );
+    }
+
+    #[test]
+    fn omits_header_on_server_errors() {
+        let response = AuthError::NoOidcDiscovery.into_response_for_realm("MyRealm");
+        assert_eq!(response.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(www_authenticate(&response), None);
+    }
+
+    #[test]
+    fn exposes_underlying_errors_as_sources() {
+        use std::error::Error;
+
+        fn chain(err: &(dyn Error + 'static)) -> Vec<String> {
+            std::iter::successors(Some(err), |err| (*err).source())
+                .map(ToString::to_string)
+                .collect()
+        }
+        fn assert_boxable<E: Error + Send + Sync + 'static>(_: &E) {}
+
+        let json_error = serde_json::from_str::<u8>("x").unwrap_err();
+        let err = AuthError::JsonParse {
+            source: std::sync::Arc::new(json_error),
+        };
+        assert_boxable(&err);
+        let source = err.source().unwrap();
+        assert!(source
+            .downcast_ref::<std::sync::Arc<serde_json::Error>>()
+            .is_some());
+        assert_eq!(
+            chain(&err),
+            [
+                err.to_string(),
+                serde_json::from_str::<u8>("x").unwrap_err().to_string()
+            ]
+        );
+
+        let err = AuthError::Decode {
+            source: jsonwebtoken::errors::ErrorKind::InvalidSignature.into(),
+        };
+        assert!(err
+            .source()
+            .unwrap()
+            .downcast_ref::<jsonwebtoken::errors::Error>()
+            .is_some());
+
+        // Discovery errors only describe the failed step, the cause is found further down the chain.
+        let err = AuthError::OidcDiscovery {
+            source: crate::oidc_discovery::RequestError::Send {
+                source: std::sync::Arc::new(
+                    reqwest::Client::new().get("not a url").build().unwrap_err(),
+                ),
+            },
+        };
+        assert_eq!(
+            chain(&err),
+            [
+                "Could not discover OIDC configuration.",
+                "RequestError: Could not send request",
+                "builder error",
+                "relative URL without a base"
+            ]
+        );
+    }
+
+    #[test]
+    fn distinguishes_unauthenticated_from_forbidden() {
+        let decode_error = || jsonwebtoken::errors::ErrorKind::InvalidSignature.into();
+        for unauthenticated in [
+            AuthError::MissingAuthorizationHeader,
+            AuthError::MalformedAuthorizationHeader {
+                reason: String::from("The 'Basic' scheme is not accepted."),
+            },
+            AuthError::NoToken { reasons: vec![] },
+            AuthError::DecodeHeader {
+                source: decode_error(),
+            },
+            AuthError::Decode {
+                source: decode_error(),
+            },
+            AuthError::NoDecodingKeys,
+            AuthError::NoMatchingDecodingKey,
+            AuthError::TokenExpired,
+            AuthError::TokenNotYetValid,
+            AuthError::TokenTooOld,
+            AuthError::AuthenticationTooOld,
+            AuthError::InvalidIssuer,
+            AuthError::InactiveToken,
+        ] {
+            assert_eq!(
+                unauthenticated.clone().into_response().status(),
+                http::StatusCode::UNAUTHORIZED,
+                "{unauthenticated:?}"
+            );
+        }
+        for forbidden in [
+            AuthError::MissingExpectedRole {
+                role: String::from("administrator"),
+            },
+            AuthError::MissingExpectedRoles {
+                roles: vec![String::from("administrator"), String::from("editor")],
+            },
+            AuthError::MissingExpectedGroup {
+                group: String::from("/engineering"),
+            },
+            AuthError::InsufficientScope {
+                required: vec![String::from("email")],
+                present: vec![String::from("openid")],
+            },
+            AuthError::EmailNotVerified,
+            AuthError::UnexpectedRole,
+            AuthError::NoRoles,
+        ] {
+            assert_eq!(
+                forbidden.clone().into_response().status(),
+                http::StatusCode::FORBIDDEN,
+                "{forbidden:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn distinguishes_missing_from_malformed_authorization_header() {
+        let missing = AuthError::MissingAuthorizationHeader.into_response_for_realm("MyRealm");
+        let malformed = AuthError::MalformedAuthorizationHeader {
+            reason: String::from("The 'Basic' scheme is not accepted."),
+        }
+        .into_response_for_realm("MyRealm");
+        assert_eq!(missing.status(), http::StatusCode::UNAUTHORIZED);
+        assert_eq!(malformed.status(), http::StatusCode::UNAUTHORIZED);
+        assert_eq!(www_authenticate(&missing), Some("Bearer realm=\"MyRealm\""));
+        assert_eq!(
+            www_authenticate(&malformed),
+            Some("Bearer realm=\"MyRealm\", error=\"invalid_request\", error_description=\"The 'Authorization' header was present on a request but it was malformed. Reason: The 'Basic' scheme is not accepted.\"")
+        );
+    }
+
+    #[test]
+    fn categorizes_every_error() {
+        use std::{sync::Arc, time::Duration};
+
+        use crate::oidc_discovery::RequestError;
+
+        let decode_error = || jsonwebtoken::errors::ErrorKind::InvalidSignature.into();
+        let request_error = || RequestError::Send {
+            source: Arc::new(reqwest::Client::new().get("not a url").build().unwrap_err()),
+        };
+        let text = String::new;
+        let cases = [
+            (AuthError::NoOidcDiscovery, AuthErrorKind::Server),
+            (
+                AuthError::OidcDiscovery {
+                    source: request_error(),
+                },
+                AuthErrorKind::Server,
+            ),
+            (
+                AuthError::DiscoveryTimeout {
+                    timeout: Duration::from_secs(1),
+                },
+                AuthErrorKind::Server,
+            ),
+            (
+                AuthError::InvalidConfiguration { reason: text() },
+                AuthErrorKind::Server,
+            ),
+            (AuthError::NoJwkSetDiscovery, AuthErrorKind::Server),
+            (
+                AuthError::JwkEndpoint {
+                    source: url::ParseError::EmptyHost,
+                },
+                AuthErrorKind::Server,
+            ),
+            (
+                AuthError::JwkSetDiscovery {
+                    source: request_error(),
+                },
+                AuthErrorKind::Server,
+            ),
+            (
+                AuthError::AlbKeyDiscovery {
+                    kid: text(),
+                    source: request_error(),
+                },
+                AuthErrorKind::Server,
+            ),
+            (
+                AuthError::Introspection {
+                    source: request_error(),
+                },
+                AuthErrorKind::Server,
+            ),
+            (
+                AuthError::TokenRequest {
+                    source: request_error(),
+                },
+                AuthErrorKind::Server,
+            ),
+            (
+                AuthError::InvalidGrant { description: None },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::TokenEndpoint {
+                    error: text(),
+                    description: None,
+                },
+                AuthErrorKind::Server,
+            ),
+            (AuthError::InactiveToken, AuthErrorKind::Unauthenticated),
+            (
+                AuthError::MissingAuthorizationHeader,
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::MalformedAuthorizationHeader { reason: text() },
+                AuthErrorKind::Malformed,
+            ),
+            (
+                AuthError::InvalidAuthorizationHeader { reason: text() },
+                AuthErrorKind::Malformed,
+            ),
+            (AuthError::MissingBearerToken, AuthErrorKind::Malformed),
+            (
+                AuthError::MissingTokenHeader { header: text() },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::InvalidTokenHeader {
+                    header: text(),
+                    reason: text(),
+                },
+                AuthErrorKind::Malformed,
+            ),
+            (
+                AuthError::MissingQueryParams,
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::MissingTokenQueryParam,
+                AuthErrorKind::Unauthenticated,
+            ),
+            (AuthError::EmptyTokenQueryParam, AuthErrorKind::Malformed),
+            (
+                AuthError::MissingTokenCookie,
+                AuthErrorKind::Unauthenticated,
+            ),
+            (AuthError::EmptyTokenCookie, AuthErrorKind::Malformed),
+            (
+                AuthError::NoToken { reasons: vec![] },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::CreateDecodingKey {
+                    source: decode_error(),
+                },
+                AuthErrorKind::Server,
+            ),
+            (
+                AuthError::DecodeHeader {
+                    source: decode_error(),
+                },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (AuthError::NoDecodingKeys, AuthErrorKind::Unauthenticated),
+            (
+                AuthError::NoMatchingDecodingKey,
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::DisallowedAlgorithm { algorithm: text() },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::Decode {
+                    source: decode_error(),
+                },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::JsonParse {
+                    source: Arc::new(serde_json::from_str::<u8>("x").unwrap_err()),
+                },
+                AuthErrorKind::Server,
+            ),
+            (AuthError::TokenExpired, AuthErrorKind::Unauthenticated),
+            (AuthError::TokenNotYetValid, AuthErrorKind::Unauthenticated),
+            (AuthError::TokenTooOld, AuthErrorKind::Unauthenticated),
+            (
+                AuthError::AuthenticationTooOld,
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::InsufficientAuthenticationLevel {
+                    required: text(),
+                    present: None,
+                },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::UnexpectedAuthorizedParty {
+                    authorized_party: text(),
+                },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::NotIssuedForClient { client_id: text() },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::UnexpectedTokenType { token_type: text() },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::UnexpectedAudience {
+                    required: vec![],
+                    present: vec![],
+                },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (AuthError::UnexpectedNonce, AuthErrorKind::Unauthenticated),
+            (AuthError::TokenRevoked, AuthErrorKind::Unauthenticated),
+            (AuthError::InvalidIssuer, AuthErrorKind::Unauthenticated),
+            (
+                AuthError::UnknownRealm { issuer: text() },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::MissingClaim { claim: text() },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (AuthError::MissingTenantId, AuthErrorKind::Unauthenticated),
+            (
+                AuthError::InvalidToken { reason: text() },
+                AuthErrorKind::Unauthenticated,
+            ),
+            (
+                AuthError::MissingExpectedRole { role: text() },
+                AuthErrorKind::Forbidden,
+            ),
+            (
+                AuthError::MissingExpectedRoles {
+                    roles: vec![text(), text()],
+                },
+                AuthErrorKind::Forbidden,
+            ),
+            (
+                AuthError::MissingExpectedGroup { group: text() },
+                AuthErrorKind::Forbidden,
+            ),
+            (
+                AuthError::InsufficientScope {
+                    required: vec![],
+                    present: vec![],
+                },
+                AuthErrorKind::Forbidden,
+            ),
+            (
+                AuthError::MissingExpectedScope { scope: text() },
+                AuthErrorKind::Forbidden,
+            ),
+            (AuthError::EmailNotVerified, AuthErrorKind::Forbidden),
+            (AuthError::UnexpectedRole, AuthErrorKind::Forbidden),
+            (AuthError::NoRoles, AuthErrorKind::Forbidden),
+            (
+                AuthError::AccessDenied {
+                    status: http::StatusCode::FORBIDDEN,
+                    body: serde_json::Value::Null,
+                },
+                AuthErrorKind::Forbidden,
+            ),
+            (
+                AuthError::AccessDenied {
+                    status: http::StatusCode::UNAUTHORIZED,
+                    body: serde_json::Value::Null,
+                },
+                AuthErrorKind::Unauthenticated,
+            ),
+        ];
+        for (err, kind) in cases {
+            assert_eq!(err.kind(), kind, "{err:?}");
+            let (status, grpc_code) = match kind {
+                AuthErrorKind::Unauthenticated | AuthErrorKind::Malformed => {
+                    (http::StatusCode::UNAUTHORIZED, 16)
+                }
+                AuthErrorKind::Forbidden => (http::StatusCode::FORBIDDEN, 7),
+                AuthErrorKind::Server => (http::StatusCode::INTERNAL_SERVER_ERROR, 13),
+            };
+            assert_eq!(err.status_code(), status, "{err:?}");
+            assert_eq!(kind.grpc_code(), grpc_code, "{err:?}");
+        }
+    }
 }
@@ -10,9 +10,13 @@ use crate::error::AuthError;
 pub type ExtractedToken<'a> = Cow<'a, str>;
 
 /// Allows for customized strategies on how to retrieve the auth token from an axum request.
-/// This crate implements two default strategies:
+/// This crate implements six default strategies:
 ///   - `AuthHeaderTokenExtractor`: Extracts the token from the `http::header::AUTHORIZATION` header.
+///   - `AuthSchemeTokenExtractor`: Like `AuthHeaderTokenExtractor`, but accepting other schemes than "Bearer".
+///   - `HeaderTokenExtractor`: Extracts the token from an arbitrary header (for example named "X-Forwarded-Access-Token").
 ///   - `QueryParamTokenExtractor`: Extracts the token from a query parameter (for example named "token").
+///   - `CookieTokenExtractor`: Extracts the token from a cookie (for example named "access_token").
+///   - `WebSocketProtocolTokenExtractor`: Extracts the token from the `Sec-WebSocket-Protocol` header of WebSocket handshakes.
 ///
 /// Note: The current return type and caller impl does not allow to return multiple tokens from a request.
 /// We may implement this feature in the future. This could allow the QueryParamTokenExtractor to extract all tokens found.
@@ -21,22 +25,155 @@ pub trait TokenExtractor: Send + Sync {
 }
 
 /// Searches the auth token in the authorization header. (Authorization: `Bearer <token>`)
+///
+/// The "Bearer" scheme is matched case-insensitively. Use an `AuthSchemeTokenExtractor` to accept other schemes.
+/// Whitespace surrounding the token is ignored.
+/// Requests carrying multiple `Authorization` headers (e.g. one added by a proxy) are only accepted if all headers are identical.
+/// Differing headers make it ambiguous who is authenticating and are rejected as malformed.
 #[derive(Debug, Clone, Default)]
 pub struct AuthHeaderTokenExtractor {}
 
+impl AuthHeaderTokenExtractor {
+    /// Extracts the token from the given headers, e.g. of a request with a body other than the axum one.
+    pub(crate) fn extract_from_headers<'a>(
+        &self,
+        headers: &'a http::HeaderMap,
+    ) -> Result<ExtractedToken<'a>, AuthError> {
+        extract_authorization(headers, &["Bearer"])
+    }
+}
+
 impl TokenExtractor for AuthHeaderTokenExtractor {
     fn extract<'a>(&self, request: &'a Request) -> Result<ExtractedToken<'a>, AuthError> {
-        request
+        self.extract_from_headers(request.headers())
+    }
+}
+
+/// Searches the auth token in the authorization header, accepting any of the configured `schemes`,
+/// e.g. `Authorization: JWT <token>`. Otherwise behaves like the `AuthHeaderTokenExtractor`.
+///
+/// The schemes are matched case-insensitively. Defaults to only accepting "Bearer".
+#[derive(Debug, Clone)]
+pub struct AuthSchemeTokenExtractor {
+    pub schemes: Vec<String>,
+}
+
+impl AuthSchemeTokenExtractor {
+    /// Accepts tokens presented with any of the given schemes, for example `["Bearer", "JWT"]`.
+    pub fn accepting_schemes<S: Into<String>>(schemes: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            schemes: schemes.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl Default for AuthSchemeTokenExtractor {
+    fn default() -> Self {
+        Self::accepting_schemes(["Bearer"])
+    }
+}
+
+impl TokenExtractor for AuthSchemeTokenExtractor {
+    fn extract<'a>(&self, request: &'a Request) -> Result<ExtractedToken<'a>, AuthError> {
+        extract_authorization(request.headers(), &self.schemes)
+    }
+}
+
+fn extract_authorization<'a, S: AsRef<str>>(
+    headers: &'a http::HeaderMap,
+    schemes: &[S],
+) -> Result<ExtractedToken<'a>, AuthError> {
+    let mut values = headers.get_all(http::header::AUTHORIZATION).iter();
+    let value = values.next().ok_or(AuthError::MissingAuthorizationHeader)?;
+    if values.any(|other| other != value) {
+        return Err(AuthError::MalformedAuthorizationHeader {
+            reason: String::from("Multiple differing 'Authorization' headers were sent."),
+        });
+    }
+    let value = value
+        .to_str()
+        .map_err(|err| AuthError::MalformedAuthorizationHeader {
+            reason: err.to_string(),
+        })?
+        .trim();
+
+    // Never name the scheme in errors. Without a scheme, it is the token itself.
+    let (scheme, token) = value
+        .split_once(|c: char| c.is_ascii_whitespace())
+        .unwrap_or((value, ""));
+    if !schemes
+        .iter()
+        .any(|accepted| accepted.as_ref().eq_ignore_ascii_case(scheme))
+    {
+        return Err(AuthError::MalformedAuthorizationHeader {
+            reason: String::from("Unsupported or missing authorization scheme."),
+        });
+    }
+
+    match token.trim() {
+        "" => Err(AuthError::MalformedAuthorizationHeader {
+            reason: String::from("The authorization scheme was not followed by a token."),
+        }),
+        token => Ok(Cow::Borrowed(token)),
+    }
+}
+
+/// Searches the auth token in an arbitrary header, eg. returns `<token>` when looking at a request with header `X-Forwarded-Access-Token: <token>`.
+/// This is useful when running behind API gateways which inject the token into a custom header.
+///
+/// The `prefix` (default: "Bearer ") is stripped from the header value if present. The remainder is used as the token.
+/// To try multiple candidate headers, configure one extractor per header in the layers `token_extractors`.
+#[derive(Debug, Clone)]
+pub struct HeaderTokenExtractor {
+    pub header: http::HeaderName,
+    pub prefix: Option<String>,
+}
+
+impl HeaderTokenExtractor {
+    pub fn extracting_header(header: http::HeaderName) -> Self {
+        Self {
+            header,
+            prefix: Some(String::from("Bearer ")),
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn without_prefix(mut self) -> Self {
+        self.prefix = None;
+        self
+    }
+}
+
+impl TokenExtractor for HeaderTokenExtractor {
+    fn extract<'a>(&self, request: &'a Request) -> Result<ExtractedToken<'a>, AuthError> {
+        let value = request
             .headers()
-            .get(http::header::AUTHORIZATION)
-            .ok_or(AuthError::MissingAuthorizationHeader)?
+            .get(&self.header)
+            .ok_or_else(|| AuthError::MissingTokenHeader {
+                header: self.header.to_string(),
+            })?
             .to_str()
-            .map_err(|err| AuthError::InvalidAuthorizationHeader {
+            .map_err(|err| AuthError::InvalidTokenHeader {
+                header: self.header.to_string(),
                 reason: err.to_string(),
-            })?
-            .strip_prefix("Bearer ")
-            .ok_or(AuthError::MissingBearerToken)
-            .map(Cow::Borrowed)
+            })?;
+
+        let token = match &self.prefix {
+            Some(prefix) => value.strip_prefix(prefix.as_str()).unwrap_or(value),
+            None => value,
+        };
+
+        match token.is_empty() {
+            true => Err(AuthError::InvalidTokenHeader {
+                header: self.header.to_string(),
+                reason: String::from("The header contained no token."),
+            }),
+            false => Ok(Cow::Borrowed(token)),
+        }
     }
 }
 
@@ -82,19 +219,415 @@ impl TokenExtractor for QueryParamTokenExtractor {
     }
 }
 
+/// Searches the auth token in the cookies of a request, eg. returns `<token>` when looking at a request with header `Cookie: access_token=<token>`.
+/// The name of the cookie is configurable. Default is: "access_token".
+///
+/// All `Cookie` headers of the request are considered. Should the cookie be set multiple times, the first occurrence is used.
+/// This allows browser-based apps to store the token in an HttpOnly cookie.
+#[derive(Debug, Clone)]
+pub struct CookieTokenExtractor {
+    pub name: String,
+}
+
+impl CookieTokenExtractor {
+    pub fn extracting_cookie(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+}
+
+impl Default for CookieTokenExtractor {
+    fn default() -> Self {
+        Self::extracting_cookie("access_token")
+    }
+}
+
+impl TokenExtractor for CookieTokenExtractor {
+    fn extract<'a>(&self, request: &'a Request) -> Result<ExtractedToken<'a>, AuthError> {
+        let value = request
+            .headers()
+            .get_all(http::header::COOKIE)
+            .iter()
+            // Cookie headers not solely containing visible ASCII characters can not be inspected.
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == self.name)
+            .map(|(_, value)| value.trim().trim_matches('"'))
+            .ok_or(AuthError::MissingTokenCookie)?;
+
+        match value.is_empty() {
+            true => Err(AuthError::EmptyTokenCookie),
+            false => Ok(Cow::Borrowed(value)),
+        }
+    }
+}
+
+/// Searches the auth token in the subprotocols requested by a WebSocket handshake,
+/// eg. returns `<token>` when looking at a request with header `Sec-WebSocket-Protocol: access_token, <token>`.
+/// The token is the subprotocol following the `marker` protocol. Default marker is: "access_token".
+///
+/// Browsers can not set an `Authorization` header when opening a WebSocket, but can request subprotocols:
+/// `new WebSocket(url, ["access_token", token])`. Browsers close the connection if the server does not select one of them,
+/// so let your handler select the marker through axum's `WebSocketUpgrade::protocols(["access_token"])`.
+/// Never select the token itself, as it would be echoed back in the response.
+#[derive(Debug, Clone)]
+pub struct WebSocketProtocolTokenExtractor {
+    pub marker: String,
+}
+
+impl WebSocketProtocolTokenExtractor {
+    pub fn with_marker(marker: impl Into<String>) -> Self {
+        Self {
+            marker: marker.into(),
+        }
+    }
+}
+
+impl Default for WebSocketProtocolTokenExtractor {
+    fn default() -> Self {
+        Self::with_marker("access_token")
+    }
+}
+
+impl TokenExtractor for WebSocketProtocolTokenExtractor {
+    fn extract<'a>(&self, request: &'a Request) -> Result<ExtractedToken<'a>, AuthError> {
+        let header = http::header::SEC_WEBSOCKET_PROTOCOL;
+        let mut values = request.headers().get_all(&header).iter().peekable();
+        if values.peek().is_none() {
+            return Err(AuthError::MissingTokenHeader {
+                header: header.to_string(),
+            });
+        }
+        let mut protocols = values
+            // Header values not solely containing visible ASCII characters can not be inspected.
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim);
+
+        // Reasons must never contain the token, as they are part of the error response.
+        let reason = match protocols.find(|protocol| *protocol == self.marker) {
+            None => format!("The '{}' protocol was not requested.", self.marker),
+            Some(_) => match protocols.next() {
+                Some(token) if !token.is_empty() => return Ok(Cow::Borrowed(token)),
+                _ => format!(
+                    "The '{}' protocol was not followed by a token.",
+                    self.marker
+                ),
+            },
+        };
+        Err(AuthError::InvalidTokenHeader {
+            header: header.to_string(),
+            reason,
+        })
+    }
+}
+
 pub(crate) fn extract_jwt<'a>(
     request: &'a Request<axum::body::Body>,
     extractors: &NonEmpty<Arc<dyn TokenExtractor>>,
 ) -> Result<ExtractedToken<'a>, AuthError> {
-    // NOTE: This initial binding will never be used,
-    // as the upcoming for-loop will be executed at least once,
-    // thank to the NonEmpty vec!
-    let result = Ok(ExtractedToken::Borrowed(""));
+    let mut errors = Vec::new();
     for extractor in extractors {
-        let result = extractor.extract(request);
-        if let Ok(jwt) = result {
-            return Ok(jwt);
+        match extractor.extract(request) {
+            Ok(jwt) => return Ok(jwt),
+            Err(err) => errors.push(err),
+        }
+    }
+    // NOTE: There is at least one error, thanks to the NonEmpty vec of extractors!
+    match errors.len() {
+        1 => Err(errors.remove(0)),
+        _ => Err(AuthError::NoToken {
+            reasons: errors.iter().map(ToString::to_string).collect(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::{body::Body, extract::Request};
+    use nonempty::NonEmpty;
+
+    use crate::error::AuthError;
+
+    use super::{
+        extract_jwt, AuthHeaderTokenExtractor, AuthSchemeTokenExtractor, CookieTokenExtractor,
+        HeaderTokenExtractor, TokenExtractor, WebSocketProtocolTokenExtractor,
+    };
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut builder = Request::builder().uri("https://localhost/");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn extracts_token_from_websocket_protocols() {
+        let extractor = WebSocketProtocolTokenExtractor::default();
+        let single = request(&[("sec-websocket-protocol", "chat, access_token, abc.def.ghi")]);
+        assert_eq!(extractor.extract(&single).unwrap(), "abc.def.ghi");
+
+        let multiple = request(&[
+            ("sec-websocket-protocol", "access_token"),
+            ("sec-websocket-protocol", "abc.def.ghi"),
+        ]);
+        assert_eq!(extractor.extract(&multiple).unwrap(), "abc.def.ghi");
+
+        for (headers, expected) in [
+            (vec![], "MissingTokenHeader"),
+            (
+                vec![("sec-websocket-protocol", "chat, abc.def.ghi")],
+                "InvalidTokenHeader",
+            ),
+            (
+                vec![("sec-websocket-protocol", "chat, access_token")],
+                "InvalidTokenHeader",
+            ),
+        ] {
+            let request = request(&headers);
+            let err = extractor.extract(&request).unwrap_err();
+            assert!(format!("{err:?}").starts_with(expected), "{err:?}");
+            assert!(!err.to_string().contains("abc.def.ghi"));
+        }
+    }
+
+    #[test]
+    fn extracts_token_from_cookie() {
+        let request = request(&[("cookie", "theme=dark; access_token=abc.def.ghi; lang=en")]);
+        let token = CookieTokenExtractor::default().extract(&request).unwrap();
+        assert_eq!(token, "abc.def.ghi");
+    }
+
+    #[test]
+    fn extracts_token_from_any_cookie_header() {
+        let request = request(&[("cookie", "theme=dark"), ("cookie", "jwt=abc.def.ghi")]);
+        let token = CookieTokenExtractor::extracting_cookie("jwt")
+            .extract(&request)
+            .unwrap();
+        assert_eq!(token, "abc.def.ghi");
+    }
+
+    #[test]
+    fn rejects_missing_or_empty_cookie() {
+        let extractor = CookieTokenExtractor::default();
+        assert!(matches!(
+            extractor.extract(&request(&[])),
+            Err(AuthError::MissingTokenCookie)
+        ));
+        assert!(matches!(
+            extractor.extract(&request(&[("cookie", "theme=dark")])),
+            Err(AuthError::MissingTokenCookie)
+        ));
+        assert!(matches!(
+            extractor.extract(&request(&[("cookie", "access_token=")])),
+            Err(AuthError::EmptyTokenCookie)
+        ));
+    }
+
+    #[test]
+    fn uses_extractors_in_order() {
+        let extractors = NonEmpty::<Arc<dyn TokenExtractor>> {
+            head: Arc::new(AuthHeaderTokenExtractor::default()),
+            tail: vec![Arc::new(CookieTokenExtractor::default())],
+        };
+
+        let both = request(&[
+            ("authorization", "Bearer from-header"),
+            ("cookie", "access_token=from-cookie"),
+        ]);
+        assert_eq!(extract_jwt(&both, &extractors).unwrap(), "from-header");
+
+        let cookie_only = request(&[("cookie", "access_token=from-cookie")]);
+        assert_eq!(
+            extract_jwt(&cookie_only, &extractors).unwrap(),
+            "from-cookie"
+        );
+
+        match extract_jwt(&request(&[]), &extractors) {
+            Err(AuthError::NoToken { reasons }) => assert_eq!(reasons.len(), 2),
+            other => panic!("Unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn extracts_token_from_custom_header() {
+        let extractor = HeaderTokenExtractor::extracting_header(http::HeaderName::from_static(
+            "x-forwarded-access-token",
+        ));
+        let with_prefix = request(&[("x-forwarded-access-token", "Bearer abc.def.ghi")]);
+        assert_eq!(extractor.extract(&with_prefix).unwrap(), "abc.def.ghi");
+        let without_prefix = request(&[("x-forwarded-access-token", "abc.def.ghi")]);
+        assert_eq!(extractor.extract(&without_prefix).unwrap(), "abc.def.ghi");
+
+        let extractor = extractor.with_prefix("Token ");
+        let custom_prefix = request(&[("x-forwarded-access-token", "Token abc.def.ghi")]);
+        assert_eq!(extractor.extract(&custom_prefix).unwrap(), "abc.def.ghi");
+    }
+
+    #[test]
+    fn rejects_missing_or_empty_custom_header() {
+        let extractor = HeaderTokenExtractor::extracting_header(http::HeaderName::from_static(
+            "x-forwarded-access-token",
+        ));
+        assert!(matches!(
+            extractor.extract(&request(&[])),
+            Err(AuthError::MissingTokenHeader { header: _ })
+        ));
+        assert!(matches!(
+            extractor.extract(&request(&[("x-forwarded-access-token", "Bearer ")])),
+            Err(AuthError::InvalidTokenHeader {
+                header: _,
+                reason: _
+            })
+        ));
+    }
+
+    #[test]
+    fn matches_authorization_schemes_case_insensitively() {
+        let extractor = AuthHeaderTokenExtractor::default();
+        for value in [
+            "Bearer abc.def.ghi",
+            "bearer abc.def.ghi",
+            "BEARER   abc.def.ghi  ",
+        ] {
+            let request = request(&[("authorization", value)]);
+            assert_eq!(extractor.extract(&request).unwrap(), "abc.def.ghi");
         }
+        assert!(matches!(
+            extractor.extract(&request(&[("authorization", "JWT abc.def.ghi")])),
+            Err(AuthError::MalformedAuthorizationHeader { reason: _ })
+        ));
+
+        let extractor = AuthSchemeTokenExtractor::accepting_schemes(["Bearer", "JWT"]);
+        let request = request(&[("authorization", "jwt abc.def.ghi")]);
+        assert_eq!(extractor.extract(&request).unwrap(), "abc.def.ghi");
+    }
+
+    #[test]
+    fn accepts_multiple_authorization_headers_only_if_identical() {
+        let extractor = AuthHeaderTokenExtractor::default();
+        assert!(matches!(
+            extractor.extract(&request(&[])),
+            Err(AuthError::MissingAuthorizationHeader)
+        ));
+
+        let single = request(&[("authorization", "Bearer abc.def.ghi")]);
+        assert_eq!(extractor.extract(&single).unwrap(), "abc.def.ghi");
+
+        let identical = request(&[
+            ("authorization", "Bearer abc.def.ghi"),
+            ("authorization", "Bearer abc.def.ghi"),
+        ]);
+        assert_eq!(extractor.extract(&identical).unwrap(), "abc.def.ghi");
+
+        let conflicting = request(&[
+            ("authorization", "Bearer abc.def.ghi"),
+            ("authorization", "Bearer jkl.mno.pqr"),
+        ]);
+        assert!(matches!(
+            extractor.extract(&conflicting),
+            Err(AuthError::MalformedAuthorizationHeader { reason }) if reason.contains("differing")
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_authorization_header() {
+        assert!(matches!(
+            AuthHeaderTokenExtractor::default().extract(&request(&[])),
+            Err(AuthError::MissingAuthorizationHeader)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_authorization_header() {
+        let extractor = AuthHeaderTokenExtractor::default();
+        let malformed = |request: &Request| match extractor.extract(request) {
+            Err(AuthError::MalformedAuthorizationHeader { reason }) => reason,
+            other => panic!("Unexpected result: {other:?}"),
+        };
+
+        // Wrong scheme.
+        for value in ["Basic dXNlcjpwYXNz", "Bearerabc.def.ghi", "abc.def.ghi"] {
+            let reason = malformed(&request(&[("authorization", value)]));
+            assert!(
+                reason.contains("Unsupported or missing authorization scheme"),
+                "{value}: {reason}"
+            );
+            assert!(!reason.contains("abc.def.ghi"), "{value}: {reason}");
+            assert!(!reason.contains("dXNlcjpwYXNz"), "{value}: {reason}");
+        }
+
+        // No token after the scheme.
+        for value in ["Bearer", "Bearer ", "bearer    "] {
+            let reason = malformed(&request(&[("authorization", value)]));
+            assert!(
+                reason.contains("not followed by a token"),
+                "{value}: {reason}"
+            );
+        }
+
+        // Not solely visible ASCII characters.
+        let mut request = request(&[]);
+        request.headers_mut().insert(
+            http::header::AUTHORIZATION,
+            http::HeaderValue::from_bytes(b"Bearer abc\xff").unwrap(),
+        );
+        malformed(&request);
+    }
+
+    #[tokio::test]
+    async fn protects_websocket_upgrades() {
+        use axum::{extract::WebSocketUpgrade, routing::get, Router};
+
+        use crate::{
+            layer::KeycloakAuthLayer,
+            testing::test::{secret_builder, serve},
+        };
+
+        let builder = secret_builder();
+        let router = Router::new()
+            .route(
+                "/ws",
+                get(|ws: WebSocketUpgrade| async {
+                    ws.protocols(["access_token"]).on_upgrade(|_| async {})
+                }),
+            )
+            .layer(
+                KeycloakAuthLayer::<String>::builder()
+                    .instance(builder.instance().unwrap())
+                    .expected_audiences(vec![String::from("account")])
+                    .token_extractors(NonEmpty {
+                        head: Arc::new(AuthHeaderTokenExtractor::default())
+                            as Arc<dyn TokenExtractor>,
+                        tail: vec![Arc::new(WebSocketProtocolTokenExtractor::default())],
+                    })
+                    .build(),
+            );
+        let url = format!("{}/ws", serve(router).await);
+
+        let handshake = |protocols: String| {
+            reqwest::Client::new()
+                .get(&url)
+                .header("connection", "upgrade")
+                .header("upgrade", "websocket")
+                .header("sec-websocket-version", "13")
+                .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+                .header("sec-websocket-protocol", protocols)
+                .send()
+        };
+
+        let token = builder.build().unwrap();
+        let response = handshake(format!("access_token, {token}")).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(
+            response.headers().get("sec-websocket-protocol").unwrap(),
+            "access_token"
+        );
+
+        let response = handshake(String::from("access_token")).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
     }
-    result
 }
@@ -0,0 +1,208 @@
+//! Protects tonic (gRPC) servers, validating the token sent in the `authorization` metadata of each call.
+//!
+//! Tonic interceptors are synchronous and could not fetch keys on demand.
+//! The `KeycloakGrpcAuthLayer` is therefore a tower layer, added to a tonic server through `Server::layer`.
+//! It validates tokens just like the `KeycloakAuthLayer` it is created from, rejecting calls with a `tonic::Status`
+//! (see `AuthErrorKind::grpc_code`) and inserting the `KeycloakToken` into the extensions of accepted ones.
+//! Handlers read it through `tonic::Request::extensions`.
+
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    decode::ProfileAndEmail, extract::AuthHeaderTokenExtractor, layer::KeycloakAuthLayer,
+    role::Role, KeycloakAuthStatus, PassthroughMode,
+};
+
+/// Tower layer validating the token of gRPC calls, see the module documentation.
+///
+/// The token is always read from the `authorization` metadata ("Bearer <token>").
+/// The `token_extractors` and `error_response` of the wrapped layer only apply to axum routers and are not used.
+/// Calls are rejected or passed on according to its `passthrough_mode`. Its `authorizer` is consulted as well.
+#[derive(Clone)]
+pub struct KeycloakGrpcAuthLayer<R, Extra = ProfileAndEmail>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    layer: KeycloakAuthLayer<R, Extra>,
+}
+
+impl<R, Extra> KeycloakGrpcAuthLayer<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    pub fn new(layer: KeycloakAuthLayer<R, Extra>) -> Self {
+        Self { layer }
+    }
+}
+
+impl<S, R, Extra> tower::Layer<S> for KeycloakGrpcAuthLayer<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    type Service = KeycloakGrpcAuthService<S, R, Extra>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        KeycloakGrpcAuthService {
+            inner,
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct KeycloakGrpcAuthService<S, R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    inner: S,
+    layer: KeycloakAuthLayer<R, Extra>,
+}
+
+impl<S, B, R, Extra> tower::Service<http::Request<B>> for KeycloakGrpcAuthService<S, R, Extra>
+where
+    S: tower::Service<http::Request<B>, Response = http::Response<tonic::body::BoxBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+    R: Role + 'static,
+    Extra: DeserializeOwned + Clone + Sync + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match (self.layer.is_ready(), self.inner.poll_ready(cx)) {
+            (true, Poll::Ready(t)) => Poll::Ready(t),
+            (false, _) => Poll::Pending,
+            (_, Poll::Pending) => Poll::Pending,
+        }
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let clone = self.inner.clone();
+        let layer = self.layer.clone();
+
+        // Take the service that was ready!
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+
+            let result =
+                match AuthHeaderTokenExtractor::default().extract_from_headers(&parts.headers) {
+                    Ok(raw_token) => layer.validate(&raw_token).await,
+                    Err(err) => Err(err),
+                };
+            let result = match (result, &layer.authorizer) {
+                (Ok(validated), Some(authorizer)) => authorizer
+                    .authorize(&validated.token, &parts)
+                    .await
+                    .map(|()| validated),
+                (result, _) => result,
+            };
+
+            match (result, layer.passthrough_mode) {
+                (Ok(validated), passthrough_mode) => {
+                    if let Some(raw_claims) = validated.raw_claims {
+                        parts.extensions.insert(raw_claims);
+                    }
+                    match passthrough_mode {
+                        PassthroughMode::Block => {
+                            parts.extensions.insert(validated.token);
+                        }
+                        PassthroughMode::Pass => {
+                            parts
+                                .extensions
+                                .insert(KeycloakAuthStatus::<R, Extra>::Success(validated.token));
+                        }
+                    }
+                }
+                (Err(err), PassthroughMode::Block) => {
+                    return Ok(tonic::Status::from(err).into_http());
+                }
+                (Err(err), PassthroughMode::Pass) => {
+                    parts
+                        .extensions
+                        .insert(KeycloakAuthStatus::<R, Extra>::Failure(Arc::new(err)));
+                }
+            }
+            inner.call(http::Request::from_parts(parts, body)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use tower::{Layer, ServiceExt};
+
+    use crate::{
+        decode::KeycloakToken,
+        layer::KeycloakAuthLayer,
+        testing::{test::secret_builder, TestTokenBuilder},
+    };
+
+    use super::KeycloakGrpcAuthLayer;
+
+    async fn call(
+        builder: &TestTokenBuilder,
+        authorization: Option<String>,
+    ) -> http::Response<tonic::body::BoxBody> {
+        let layer = KeycloakAuthLayer::<String>::builder()
+            .instance(builder.instance().unwrap())
+            .expected_audiences(vec![String::from("account")])
+            .required_roles(vec![String::from("administrator")])
+            .build();
+        let service = KeycloakGrpcAuthLayer::new(layer).layer(tower::service_fn(
+            |request: http::Request<()>| async move {
+                let token = request.extensions().get::<KeycloakToken<String>>().unwrap();
+                let mut response = http::Response::new(tonic::body::empty_body());
+                response
+                    .headers_mut()
+                    .insert("x-subject", token.subject.parse().unwrap());
+                Ok::<_, Infallible>(response)
+            },
+        ));
+        let mut request = http::Request::new(());
+        if let Some(authorization) = authorization {
+            request
+                .headers_mut()
+                .insert(http::header::AUTHORIZATION, authorization.parse().unwrap());
+        }
+        service.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn validates_tokens_of_calls() {
+        let builder = secret_builder().subject("f9b0c3b4-6d0e-4c2a-9a63-3b4c1d2e5f60");
+
+        let admin = builder.clone().realm_role("administrator").build().unwrap();
+        let response = call(&builder, Some(format!("Bearer {admin}"))).await;
+        assert_eq!(
+            response.headers()["x-subject"],
+            "f9b0c3b4-6d0e-4c2a-9a63-3b4c1d2e5f60"
+        );
+
+        let response = call(&builder, None).await;
+        assert_eq!(response.headers()["grpc-status"], "16");
+
+        let user = builder.clone().realm_role("user").build().unwrap();
+        let response = call(&builder, Some(format!("Bearer {user}"))).await;
+        assert_eq!(response.headers()["grpc-status"], "7");
+        assert!(response.headers().get("x-subject").is_none());
+    }
+}
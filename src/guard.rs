@@ -0,0 +1,211 @@
+use std::{
+    marker::PhantomData,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{body::Body, response::IntoResponse};
+use futures::future::BoxFuture;
+use http::Request;
+use serde::de::DeserializeOwned;
+use tower::Layer;
+
+use crate::{
+    decode::{KeycloakToken, ProfileAndEmail},
+    error::AuthError,
+    role::{ExpectRoles, RequiredRolesMode, Role},
+    KeycloakAuthStatus,
+};
+
+/// Requires roles on the `KeycloakToken` of a request, for just the routes this layer is attached to.
+///
+/// Use this when routes protected by one `KeycloakAuthLayer` require different roles.
+/// The token must already be validated by a `KeycloakAuthLayer` applied further out (see the crate docs for an example).
+/// Requests lacking the roles are rejected with `AuthError::MissingExpectedRole` (403), or `AuthError::MissingExpectedRoles` listing every missing role if several are missing.
+/// Requests the `KeycloakAuthLayer` could not authenticate (in `PassthroughMode::Pass`) are rejected with their original error.
+pub struct RequireRolesLayer<R, Extra = ProfileAndEmail>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    roles: Arc<[R]>,
+    mode: RequiredRolesMode,
+    phantom: PhantomData<fn() -> Extra>,
+}
+
+impl<R, Extra> RequireRolesLayer<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    /// Requires all of the given roles to be present.
+    pub fn all<I: Into<R>>(roles: impl IntoIterator<Item = I>) -> Self {
+        Self::new(roles, RequiredRolesMode::All)
+    }
+
+    /// Requires at least one of the given roles to be present.
+    pub fn any<I: Into<R>>(roles: impl IntoIterator<Item = I>) -> Self {
+        Self::new(roles, RequiredRolesMode::Any)
+    }
+
+    /// Requires the given roles to be present, as determined by `mode`.
+    pub fn new<I: Into<R>>(roles: impl IntoIterator<Item = I>, mode: RequiredRolesMode) -> Self {
+        Self {
+            roles: roles.into_iter().map(Into::into).collect(),
+            mode,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<R, Extra> Clone for RequireRolesLayer<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            roles: self.roles.clone(),
+            mode: self.mode,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<S, R, Extra> Layer<S> for RequireRolesLayer<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    type Service = RequireRolesService<S, R, Extra>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireRolesService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequireRolesService<S, R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    inner: S,
+    layer: RequireRolesLayer<R, Extra>,
+}
+
+impl<S, R, Extra> RequireRolesService<S, R, Extra>
+where
+    R: Role + 'static,
+    Extra: DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    fn check(&self, request: &Request<Body>) -> Result<(), AuthError> {
+        let token = match request.extensions().get::<KeycloakToken<R, Extra>>() {
+            Some(token) => token,
+            None => match request.extensions().get::<KeycloakAuthStatus<R, Extra>>() {
+                Some(KeycloakAuthStatus::Success(token)) => token,
+                Some(KeycloakAuthStatus::Failure(err)) => return Err(err.as_ref().clone()),
+                None => {
+                    return Err(AuthError::NoToken {
+                        reasons: vec![String::from(
+                            "The request was not authenticated by a KeycloakAuthLayer.",
+                        )],
+                    })
+                }
+            },
+        };
+        match self.layer.mode {
+            RequiredRolesMode::All => token.expect_roles(&self.layer.roles[..]),
+            RequiredRolesMode::Any => token.expect_any_role(&self.layer.roles[..]),
+        }
+    }
+}
+
+impl<S, R, Extra> tower::Service<Request<Body>> for RequireRolesService<S, R, Extra>
+where
+    S: tower::Service<Request<Body>, Response = axum::response::Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    R: Role + 'static,
+    Extra: DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if let Err(err) = self.check(&request) {
+            return Box::pin(async move { Ok(err.into_response()) });
+        }
+        // Take the service that was ready!
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move { inner.call(request).await })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{routing::get, Router};
+    use http::StatusCode;
+
+    use crate::{
+        layer::KeycloakAuthLayer,
+        testing::test::{secret_builder, serve},
+        PassthroughMode,
+    };
+
+    use super::RequireRolesLayer;
+
+    #[tokio::test]
+    async fn enforces_roles_per_route() {
+        let builder = secret_builder().realm_role("auditor");
+        let router = Router::new()
+            .route(
+                "/admin",
+                get(|| async { "admin" })
+                    .layer(RequireRolesLayer::<String>::all(["administrator"])),
+            )
+            .route(
+                "/audit",
+                get(|| async { "audit" }).layer(RequireRolesLayer::<String>::any([
+                    "administrator",
+                    "auditor",
+                ])),
+            )
+            .route("/public", get(|| async { "public" }))
+            .layer(
+                KeycloakAuthLayer::<String>::builder()
+                    .instance(builder.instance().unwrap())
+                    .passthrough_mode(PassthroughMode::Pass)
+                    .expected_audiences(vec![String::from("account")])
+                    .build(),
+            );
+        let url = serve(router).await;
+
+        let token = builder.build().unwrap();
+        let status = |path: &str, token: Option<&str>| {
+            let url = format!("{url}{path}");
+            let token = token.map(str::to_owned);
+            async move {
+                let mut request = reqwest::Client::new().get(url);
+                if let Some(token) = token {
+                    request = request.bearer_auth(token);
+                }
+                request.send().await.unwrap().status()
+            }
+        };
+
+        assert_eq!(status("/admin", Some(&token)).await, StatusCode::FORBIDDEN);
+        assert_eq!(status("/audit", Some(&token)).await, StatusCode::OK);
+        assert_eq!(status("/public", Some(&token)).await, StatusCode::OK);
+        assert_eq!(status("/audit", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status("/public", None).await, StatusCode::OK);
+    }
+}
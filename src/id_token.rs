@@ -0,0 +1,310 @@
+use std::sync::Arc;
+
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_with::{serde_as, OneOrMany};
+
+use crate::{
+    decode::{decode_and_validate, ProfileAndEmail, RawClaims, RawToken, UserProfile},
+    error::AuthError,
+    instance::KeycloakAuthInstance,
+    metrics::NoopAuthMetrics,
+};
+
+/// The 'typ' claim of ID tokens issued by Keycloak.
+const ID_TOKEN_TYPE: &str = "ID";
+
+/// A validated OpenID Connect ID token, see `KeycloakAuthInstance::validate_id_token`.
+///
+/// ID tokens describe the authentication of a user towards the client they were issued to.
+/// They must never be accepted as access tokens, which is why they are not validated through the `KeycloakAuthLayer`.
+#[derive(Debug, Clone)]
+pub struct IdToken<Extra = ProfileAndEmail> {
+    /// Expiration time (UTC).
+    pub expires_at: time::OffsetDateTime,
+    /// Issued at time (UTC).
+    pub issued_at: time::OffsetDateTime,
+    /// Not before time (UTC). `None` if the token carries no 'nbf' claim.
+    pub not_before: Option<time::OffsetDateTime>,
+    /// Time (UTC) the user actually authenticated at. May lie well before `issued_at` if an existing session was reused.
+    pub auth_time: Option<time::OffsetDateTime>,
+    /// Issuer (who created and signed this token).
+    pub issuer: String,
+    /// Audience (the client the token was issued to, possibly among others).
+    pub audience: Vec<String>,
+    /// Subject (the UUID which uniquely identifies this user inside Keycloak).
+    pub subject: String,
+    /// Authorized party (the client the token was issued to). Keycloak always sets it.
+    pub authorized_party: Option<String>,
+    /// Type of token. Keycloak issues ID tokens of type "ID".
+    pub token_type: Option<String>,
+    /// The nonce of the authentication request, if one was sent.
+    pub nonce: Option<String>,
+    /// Keycloak: ID of the session of the user ('sid' claim), e.g. to correlate back-channel logouts.
+    pub session_id: Option<String>,
+    /// Hash of the access token issued alongside ('at_hash' claim).
+    pub access_token_hash: Option<String>,
+    /// Authentication context class reference ('acr' claim), e.g. "1" for a password login in Keycloak.
+    pub authentication_context: Option<String>,
+
+    pub extra: Extra,
+}
+
+impl<Extra: UserProfile> IdToken<Extra> {
+    /// The preferred username of the user. `None` if the token lacks the 'profile' scope.
+    pub fn username(&self) -> Option<&str> {
+        self.extra.username()
+    }
+
+    /// The email address of the user. `None` if the token lacks the 'email' scope.
+    pub fn email(&self) -> Option<&str> {
+        self.extra.email()
+    }
+
+    /// The full name of the user. `None` if the token lacks the 'profile' scope.
+    pub fn full_name(&self) -> Option<&str> {
+        self.extra.full_name()
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims<Extra> {
+    exp: i64,
+    iat: i64,
+    nbf: Option<i64>,
+    auth_time: Option<i64>,
+    iss: String,
+    #[serde_as(deserialize_as = "OneOrMany<_>")]
+    aud: Vec<String>,
+    sub: String,
+    typ: Option<String>,
+    azp: Option<String>,
+    nonce: Option<String>,
+    sid: Option<String>,
+    at_hash: Option<String>,
+    acr: Option<String>,
+
+    #[serde(flatten)]
+    extra: Extra,
+}
+
+/// Validates the given ID token as described in OpenID Connect Core 1.0, section 3.1.3.7.
+pub(crate) async fn validate<Extra>(
+    kc_instance: &KeycloakAuthInstance,
+    id_token: &str,
+    client_id: &str,
+    expected_nonce: Option<&str>,
+) -> Result<IdToken<Extra>, AuthError>
+where
+    Extra: DeserializeOwned,
+{
+    // Introspection is defined for access and refresh tokens only, and an introspecting instance knows no keys.
+    if kc_instance.introspection.is_some() {
+        return Err(AuthError::InvalidConfiguration {
+            reason: String::from("ID tokens can not be validated by an introspecting instance."),
+        });
+    }
+    let raw_claims = decode_and_validate(
+        kc_instance,
+        RawToken(id_token),
+        &[client_id.to_owned()],
+        &[kc_instance.issuer().to_owned()],
+        &NoopAuthMetrics {},
+    )
+    .await?;
+    let id_token = parse::<Extra>(raw_claims)?;
+
+    if let Some(token_type) = &id_token.token_type {
+        if token_type != ID_TOKEN_TYPE {
+            return Err(AuthError::UnexpectedTokenType {
+                token_type: token_type.clone(),
+            });
+        }
+    }
+
+    // Tokens issued to multiple audiences must name the client they were issued to.
+    let authorized_party = id_token.authorized_party.as_deref();
+    if (authorized_party.is_some() || id_token.audience.len() > 1)
+        && authorized_party != Some(client_id)
+    {
+        return Err(AuthError::UnexpectedAuthorizedParty {
+            authorized_party: authorized_party.unwrap_or_default().to_owned(),
+        });
+    }
+
+    let now = kc_instance.config.clock.now();
+    let leeway = kc_instance.config.leeway;
+    if now - leeway > id_token.expires_at {
+        return Err(AuthError::TokenExpired);
+    }
+    if id_token
+        .not_before
+        .is_some_and(|not_before| now + leeway < not_before)
+    {
+        return Err(AuthError::TokenNotYetValid);
+    }
+
+    if let Some(expected_nonce) = expected_nonce {
+        if id_token.nonce.as_deref() != Some(expected_nonce) {
+            return Err(AuthError::UnexpectedNonce);
+        }
+    }
+    Ok(id_token)
+}
+
+fn parse<Extra: DeserializeOwned>(raw_claims: RawClaims) -> Result<IdToken<Extra>, AuthError> {
+    let claims: IdTokenClaims<Extra> =
+        serde_json::from_value(serde_json::Value::from_iter(raw_claims)).map_err(|err| {
+            AuthError::JsonParse {
+                source: Arc::new(err),
+            }
+        })?;
+    let timestamp = |claim: &str, value: i64| {
+        time::OffsetDateTime::from_unix_timestamp(value).map_err(|err| AuthError::InvalidToken {
+            reason: format!("Could not parse '{claim}' field as unix timestamp: {err}"),
+        })
+    };
+    Ok(IdToken {
+        expires_at: timestamp("exp", claims.exp)?,
+        issued_at: timestamp("iat", claims.iat)?,
+        not_before: claims.nbf.map(|nbf| timestamp("nbf", nbf)).transpose()?,
+        auth_time: claims
+            .auth_time
+            .map(|auth_time| timestamp("auth_time", auth_time))
+            .transpose()?,
+        issuer: claims.iss,
+        audience: claims.aud,
+        subject: claims.sub,
+        authorized_party: claims.azp,
+        token_type: claims.typ,
+        nonce: claims.nonce,
+        session_id: claims.sid,
+        access_token_hash: claims.at_hash,
+        authentication_context: claims.acr,
+        extra: claims.extra,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{
+        decode::ProfileAndEmail,
+        error::AuthError,
+        instance::KeycloakAuthInstance,
+        testing::{test::secret_builder, TestTokenBuilder},
+    };
+
+    use super::IdToken;
+
+    const NONCE: &str = "n-0S6_WzA2Mj";
+
+    fn builder() -> TestTokenBuilder {
+        secret_builder()
+            .token_type("ID")
+            .audience(vec![String::from("my-client")])
+            .authorized_party("my-client")
+            .claim("nonce", NONCE)
+            .claim("sid", "3c5a2a1d-9e7f-4b23-8b2d-f8f2e2e46a10")
+            .claim("preferred_username", "jdoe")
+    }
+
+    async fn validate(
+        instance: &KeycloakAuthInstance,
+        builder: TestTokenBuilder,
+        client_id: &str,
+        nonce: Option<&str>,
+    ) -> Result<IdToken<ProfileAndEmail>, AuthError> {
+        instance
+            .validate_id_token(&builder.build().unwrap(), client_id, nonce)
+            .await
+    }
+
+    #[tokio::test]
+    async fn validates_id_tokens() {
+        let builder = builder();
+        let instance = builder.instance().unwrap();
+
+        let id_token = validate(&instance, builder.clone(), "my-client", Some(NONCE))
+            .await
+            .unwrap();
+        assert_eq!(id_token.nonce.as_deref(), Some(NONCE));
+        assert_eq!(
+            id_token.session_id.as_deref(),
+            Some("3c5a2a1d-9e7f-4b23-8b2d-f8f2e2e46a10")
+        );
+        assert_eq!(id_token.username(), Some("jdoe"));
+        assert_eq!(id_token.audience, ["my-client"]);
+
+        // The nonce is only checked when expected.
+        let without_nonce = builder.claim("nonce", serde_json::Value::Null);
+        assert!(validate(&instance, without_nonce, "my-client", None)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_id_tokens() {
+        let builder = builder();
+        let instance = builder.instance().unwrap();
+        let rejection =
+            |builder: TestTokenBuilder, client_id: &'static str, nonce: Option<&'static str>| {
+                let instance = &instance;
+                async move {
+                    validate(instance, builder, client_id, nonce)
+                        .await
+                        .unwrap_err()
+                }
+            };
+
+        assert!(matches!(
+            rejection(builder.clone(), "my-client", Some("other")).await,
+            AuthError::UnexpectedNonce
+        ));
+        assert!(matches!(
+            rejection(
+                builder.clone().claim("nonce", serde_json::Value::Null),
+                "my-client",
+                Some(NONCE)
+            )
+            .await,
+            AuthError::UnexpectedNonce
+        ));
+        assert!(matches!(
+            rejection(builder.clone(), "other-client", Some(NONCE)).await,
+            AuthError::Decode { source: _ }
+        ));
+        assert!(matches!(
+            rejection(
+                builder.clone().token_type("Bearer"),
+                "my-client",
+                Some(NONCE)
+            )
+            .await,
+            AuthError::UnexpectedTokenType { token_type: _ }
+        ));
+        let multiple_audiences = builder
+            .clone()
+            .audience(vec![
+                String::from("my-client"),
+                String::from("other-client"),
+            ])
+            .authorized_party("other-client");
+        assert!(matches!(
+            rejection(multiple_audiences, "my-client", Some(NONCE)).await,
+            AuthError::UnexpectedAuthorizedParty {
+                authorized_party: _
+            }
+        ));
+        assert!(matches!(
+            rejection(
+                builder.expired(Duration::from_secs(60)),
+                "my-client",
+                Some(NONCE)
+            )
+            .await,
+            AuthError::TokenExpired
+        ));
+    }
+}
@@ -1,18 +1,36 @@
-use std::ops::Deref;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    ops::Deref,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use educe::Educe;
+use jsonwebtoken::Algorithm;
+use serde::de::DeserializeOwned;
 use snafu::ResultExt;
-use tokio::sync::RwLockReadGuard;
+use tokio::sync::{Mutex, RwLockReadGuard};
 use tracing::Instrument;
-use try_again::Retry;
+use try_again::{DelayStrategy, Retry};
 use typed_builder::TypedBuilder;
 use url::Url;
 
 use crate::{
     action::Action,
-    error::{AuthError, JwkEndpointSnafu, JwkSetDiscoverySnafu, OidcDiscoverySnafu},
-    oidc::OidcConfig,
-    oidc_discovery,
+    clock::{Clock, SystemClock},
+    decode::{self, StandardClaims},
+    error::{
+        AuthError, CreateDecodingKeySnafu, JwkEndpointSnafu, JwkSetDiscoverySnafu,
+        OidcDiscoverySnafu,
+    },
+    id_token::{self, IdToken},
+    introspection::{Introspection, IntrospectionConfig},
+    oidc::{OidcConfig, OidcEndpoints},
+    oidc_discovery::{self, HttpClient},
+    refresh::{self, ClientCredentials, TokenResponse},
+    role::RoleClaim,
 };
 
 #[derive(Debug, Clone)]
@@ -23,9 +41,17 @@ impl OidcDiscoveryEndpoint {
         let mut url = server;
         url.path_segments_mut()
             .expect("URL not to be a 'cannot-be-a-base' URL. We have to append segments.")
-            .extend(&["realms", &realm, ".well-known", "openid-configuration"]);
+            .extend(&["realms", realm, ".well-known", "openid-configuration"]);
         Self(url)
     }
+
+    /// The configured `discovery_url`, or the endpoint derived from the `server` and `realm`.
+    pub(crate) fn of_config(kc_config: &KeycloakConfig) -> Self {
+        match &kc_config.discovery_url {
+            Some(discovery_url) => Self(discovery_url.clone()),
+            None => Self::from_server_and_realm(kc_config.server.clone(), &kc_config.realm),
+        }
+    }
 }
 
 impl Deref for OidcDiscoveryEndpoint {
@@ -36,7 +62,24 @@ impl Deref for OidcDiscoveryEndpoint {
     }
 }
 
-#[derive(Debug, TypedBuilder)]
+pub(crate) fn issuer_of_realm(kc_config: &KeycloakConfig) -> String {
+    let mut url = kc_config.server.clone();
+    url.path_segments_mut()
+        .expect("URL not to be a 'cannot-be-a-base' URL. We have to append segments.")
+        .pop_if_empty()
+        .extend(&["realms", &kc_config.realm]);
+    url.to_string()
+}
+
+/// Adjusts the `jsonwebtoken::Validation` of a token, see `KeycloakConfig::customize_validation`.
+pub type CustomizeValidation = Arc<dyn Fn(&mut jsonwebtoken::Validation) + Send + Sync>;
+
+/// Notified of a changed set of discovered decoding keys, see `KeycloakConfig::on_keys_changed`.
+/// Receives the (sorted) key IDs known before and after the change.
+pub type OnKeysChanged = Arc<dyn Fn(&[String], &[String]) + Send + Sync>;
+
+#[derive(Clone, TypedBuilder, Educe)]
+#[educe(Debug)]
 pub struct KeycloakConfig {
     /// Base URL of your Keycloak server. For example: `Url::parse("https://localhost:8443/").unwrap()`.
     pub server: Url,
@@ -44,27 +87,472 @@ pub struct KeycloakConfig {
     /// The realm of you Keycloak server.
     pub realm: String,
 
+    /// URL of the OIDC discovery document, replacing the one derived from the `server` and `realm`
+    /// ("{server}/realms/{realm}/.well-known/openid-configuration").
+    /// Set this if a gateway in front of your Keycloak server does not preserve its default routes. Defaults to the derived URL.
+    #[builder(default = None, setter(strip_option))]
+    pub discovery_url: Option<Url>,
+
+    /// URL of the JWK set, replacing the `jwks_uri` announced in the OIDC discovery document.
+    /// Set this if the announced URL is not reachable from your service, e.g. because it names an internal host.
+    /// Defaults to the announced `jwks_uri`.
+    #[builder(default = None, setter(strip_option))]
+    pub jwks_url: Option<Url>,
+
     /// The retry strategy to be used: (maximum tries, delay in seconds).
     #[builder(default = (5, 1))]
     pub retry: (usize, u64),
+
+    /// When set, the delay between the `retry` tries grows exponentially (with jitter) instead of staying at the static delay.
+    /// Consider this when your service may start while your Keycloak server is (briefly) unavailable.
+    #[builder(default = None, setter(strip_option))]
+    pub backoff: Option<Backoff>,
+
+    /// Tolerated clock skew between this service and your Keycloak server.
+    /// A token is only considered expired once `now > expires_at + leeway`. Defaults to no leeway.
+    #[builder(default = Duration::ZERO)]
+    pub leeway: Duration,
+
+    /// Source of the `now` tokens are checked against for expiry and their not-before time.
+    /// Replace it with a `FixedClock` to test expiry logic deterministically. Defaults to the `SystemClock`.
+    #[builder(default = Arc::new(SystemClock))]
+    #[educe(Debug(ignore))]
+    pub clock: Arc<dyn Clock>,
+
+    /// Reject tokens issued ('iat' claim) more than `issued_at_skew` into the future with an `AuthError::InvalidToken`.
+    /// Catches issuers with badly skewed clocks and malformed tokens, e.g. issued in the year 3000.
+    /// Tokens without an 'iat' claim are not rejected by this check. Disabled by default.
+    #[builder(default = false)]
+    pub validate_issued_at: bool,
+
+    /// How far into the future tokens may be issued, when `validate_issued_at` is enabled. Defaults to the `leeway`.
+    #[builder(default = None, setter(strip_option))]
+    pub issued_at_skew: Option<Duration>,
+
+    /// Maximum length (in bytes) of accepted tokens. Longer tokens are rejected with an `AuthError::MalformedAuthorizationHeader`
+    /// before their header is even decoded, guarding against the allocations a maliciously huge token would cause.
+    /// Keycloak tokens stay well within the default of 8 KiB, unless your users carry a lot of roles or groups.
+    #[builder(default = 8 * 1024)]
+    pub max_token_length: usize,
+
+    /// Minimum time between two OIDC discoveries.
+    /// Tokens which can not be decoded with the known keys trigger a re-discovery.
+    /// A re-discovery requested sooner than this after the last one is skipped and the token is rejected.
+    /// This protects your Keycloak server from a flood of invalid tokens. Defaults to 10 seconds.
+    #[builder(default = Duration::from_secs(10))]
+    pub min_refresh_interval: Duration,
+
+    /// Algorithms tokens may be signed with. Tokens announcing any other algorithm in their header are rejected
+    /// with `AuthError::DisallowedAlgorithm` before any decoding is attempted, hardening against algorithm-confusion attacks.
+    /// Defaults to the algorithms of the known decoding keys (their 'alg' parameter or key type).
+    #[builder(default = None, setter(strip_option))]
+    pub allowed_algorithms: Option<Vec<Algorithm>>,
+
+    /// When set, OIDC discovery is periodically re-run in the background at this interval,
+    /// so that rotated keys are known before the first token signed with them arrives.
+    /// The background task is stopped once the `KeycloakAuthInstance` is dropped. Defaults to no periodic refresh.
+    #[builder(default = None, setter(strip_option))]
+    pub refresh_interval: Option<Duration>,
+
+    /// HTTP client used to reach your Keycloak server. Reused for all OIDC discovery (unless a `discovery_client` is set), token introspection and token requests.
+    /// Provide a pre-configured client to apply proxy settings, custom root certificates or timeouts.
+    /// Defaults to a `reqwest::Client` with default settings, which respects the common `HTTP(S)_PROXY` environment variables.
+    /// Each discovery request is traced in a "keycloak_discovery_request" span (at debug level), recording its URL and response status.
+    #[builder(default = reqwest::Client::new())]
+    pub http_client: reqwest::Client,
+
+    /// HTTP client used for OIDC discovery (fetching the OIDC configuration and the JWK set) instead of the `http_client`.
+    /// Enable the `reqwest-middleware` feature to pass a `reqwest_middleware::ClientWithMiddleware`,
+    /// making discovery requests participate in the tracing, retries or metrics of your application. Defaults to the `http_client`.
+    #[builder(default = None, setter(strip_option, into))]
+    pub discovery_client: Option<HttpClient>,
+
+    /// Timeout of each individual request of an OIDC discovery (fetching the OIDC configuration or the JWK set).
+    /// A timed out request fails like any other, and is retried according to `retry`.
+    /// Distinct from the timeout of `KeycloakAuthInstance::new_and_wait`, which bounds the initial discovery as a whole.
+    /// Defaults to 10 seconds.
+    #[builder(default = Duration::from_secs(10))]
+    pub discovery_request_timeout: Duration,
+
+    /// Client on whose behalf tokens are refreshed through `KeycloakAuthInstance::refresh`. Not needed for validating tokens.
+    #[builder(default = None, setter(strip_option))]
+    pub client_credentials: Option<ClientCredentials>,
+
+    /// Adjusts the `jsonwebtoken::Validation` of every token, after this crate configured its defaults
+    /// (including the expected audiences and issuers), so that these can be both extended and overridden.
+    /// For example, set `required_spec_claims` or `sub` to also require or compare these claims.
+    /// Note: Expiry and not-before time are validated by this crate against the `clock`, using the `leeway` configured here.
+    /// The `leeway`, `validate_exp` and `validate_nbf` of the `Validation` are therefore left disabled. Defaults to no adjustments.
+    #[builder(default = None, setter(strip_option))]
+    #[educe(Debug(ignore))]
+    pub customize_validation: Option<CustomizeValidation>,
+
+    /// Called whenever an OIDC discovery yields a set of keys different from the previously discovered one,
+    /// e.g. to alert on or audit key rotations. Keys replaced under the same key ID ('kid') are detected as well,
+    /// in which case the key IDs passed are equal. Refreshes returning the same keys and the initial discovery are not reported.
+    /// Called from within the discovery task, so forward the change (e.g. through a channel) instead of blocking.
+    /// Defaults to no callback.
+    #[builder(default = None, setter(strip_option))]
+    #[educe(Debug(ignore))]
+    pub on_keys_changed: Option<OnKeysChanged>,
+
+    /// Public keys known up front, e.g. read from files or the environment, see `PinnedKey::from_pem`.
+    /// They are tried before any discovered keys, and stay usable while your Keycloak server can not be reached.
+    /// Use `KeycloakAuthInstance::from_pinned_keys` to only accept tokens signed with these keys. Defaults to no keys.
+    #[builder(default = vec![])]
+    pub pinned_keys: Vec<PinnedKey>,
+
+    /// Additional claims to read roles from, besides 'realm_access' and 'resource_access'.
+    /// Their roles are merged into `KeycloakToken::roles`, just as if they were present in the standard claims.
+    /// Use this if custom mappers of your realm put roles elsewhere, e.g. into a top-level 'roles' claim.
+    /// Claims absent from a token or not holding role names are skipped. Defaults to no additional claims.
+    #[builder(default = vec![])]
+    pub role_claims: Vec<RoleClaim>,
+}
+
+/// Exponential backoff used between the tries of an OIDC discovery, see `KeycloakConfig::backoff`.
+#[derive(Debug, Clone, Copy, PartialEq, TypedBuilder)]
+pub struct Backoff {
+    /// Delay after the first failed try. Doubled after every further failed try. Defaults to 1 second.
+    #[builder(default = Duration::from_secs(1))]
+    pub initial_delay: Duration,
+
+    /// Upper bound of the delay. Defaults to 30 seconds.
+    #[builder(default = Duration::from_secs(30))]
+    pub max_delay: Duration,
+
+    /// Fraction by which each delay is randomly varied, spreading out the retries of multiple instances of your service.
+    /// A jitter of `0.2` varies a delay of 10 seconds between 8 and 12 seconds. Clamped to `0.0..=1.0`. Defaults to `0.2`.
+    #[builder(default = 0.2)]
+    pub jitter: f64,
+}
+
+impl KeycloakConfig {
+    /// Checks the configuration, returning an `AuthError::InvalidConfiguration` if the `realm` is empty,
+    /// the `server`, `discovery_url` or `jwks_url` is not a http(s) URL usable as a base of requests
+    /// or a role claim is not a JSON pointer. See `KeycloakAuthInstance::try_new`.
+    pub fn validate(&self) -> Result<(), AuthError> {
+        check_http_url("Keycloak server", &self.server)?;
+        if let Some(discovery_url) = &self.discovery_url {
+            check_http_url("discovery URL", discovery_url)?;
+        }
+        if let Some(jwks_url) = &self.jwks_url {
+            check_http_url("JWKS URL", jwks_url)?;
+        }
+        if self.realm.is_empty() {
+            return Err(AuthError::InvalidConfiguration {
+                reason: String::from("No realm was configured."),
+            });
+        }
+        if let Some(role_claim) = self.role_claims.iter().find(|role_claim| {
+            !role_claim.pointer.is_empty() && !role_claim.pointer.starts_with('/')
+        }) {
+            return Err(AuthError::InvalidConfiguration {
+                reason: format!(
+                    "Role claim '{}' is not a JSON pointer. It must start with a '/'.",
+                    role_claim.pointer
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the given URL is usable as a base of http(s) requests.
+/// `what` names the URL in the `AuthError::InvalidConfiguration` returned otherwise.
+fn check_http_url(what: &str, url: &Url) -> Result<(), AuthError> {
+    if url.cannot_be_a_base() || !matches!(url.scheme(), "http" | "https") {
+        return Err(AuthError::InvalidConfiguration {
+            reason: format!("The {what} '{url}' is not a http(s) URL."),
+        });
+    }
+    Ok(())
+}
+
+/// TLS settings for reaching a Keycloak server presenting a certificate of a private CA.
+/// Applied by building the `KeycloakConfig::http_client` through `TlsConfig::http_client`.
+#[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+#[derive(Debug, Clone, Default, TypedBuilder)]
+pub struct TlsConfig {
+    /// Root certificates trusted in addition to the system's ones,
+    /// e.g. the certificate of the private CA your Keycloak server's certificate was issued by.
+    #[builder(default = vec![])]
+    pub root_certificates: Vec<reqwest::Certificate>,
+
+    /// **DANGER**: Accept any TLS certificate presented by your Keycloak server, including expired, self-signed
+    /// or certificates issued for other hosts. This allows anyone able to intercept the connection
+    /// to serve their own decoding keys and thereby forge tokens accepted by your service!
+    ///
+    /// Only ever enable this during local development. Prefer adding your CA to the `root_certificates`.
+    /// Disabled by default. A warning is logged whenever a client is built with this.
+    #[builder(default = false)]
+    pub danger_accept_invalid_certs: bool,
+}
+
+#[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+impl TlsConfig {
+    /// Builds an HTTP client applying these settings, to be used as the `KeycloakConfig::http_client`.
+    pub fn http_client(&self) -> Result<reqwest::Client, AuthError> {
+        let mut builder = reqwest::Client::builder();
+        for certificate in &self.root_certificates {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        if self.danger_accept_invalid_certs {
+            tracing::warn!("Accepting invalid TLS certificates of the Keycloak server. Never do this in production!");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        builder
+            .build()
+            .map_err(|err| AuthError::InvalidConfiguration {
+                reason: format!("Could not build the HTTP client: {err}"),
+            })
+    }
+}
+
+/// Changes a `KeycloakConfig` assembled by the `KeycloakAuthInstanceBuilder`, see `KeycloakAuthInstanceBuilder::configure`.
+type ConfigureConfig = Box<dyn FnOnce(&mut KeycloakConfig) + Send>;
+
+/// Fluent alternative to `KeycloakAuthInstance::try_new`, obtained through `KeycloakAuthInstance::builder()`.
+/// Instead of panicking, `build` returns an `AuthError::InvalidConfiguration` if the `server` or `realm` is missing
+/// or the `server` is not a well-formed URL.
+///
+/// Only the connection to your Keycloak server is configured here.
+/// Validation policies like expected audiences, required roles or persisting raw claims are set on the `KeycloakAuthLayer`.
+#[derive(Default, Educe)]
+#[educe(Debug)]
+pub struct KeycloakAuthInstanceBuilder {
+    server: Option<String>,
+    realm: Option<String>,
+    #[educe(Debug(ignore))]
+    configure: Vec<ConfigureConfig>,
+}
+
+impl KeycloakAuthInstanceBuilder {
+    /// Base URL of your Keycloak server, e.g. "https://localhost:8443/". Required. See `KeycloakConfig::server`.
+    pub fn server(mut self, server: impl Into<String>) -> Self {
+        self.server = Some(server.into());
+        self
+    }
+
+    /// The realm of your Keycloak server. Required. See `KeycloakConfig::realm`.
+    pub fn realm(mut self, realm: impl Into<String>) -> Self {
+        self.realm = Some(realm.into());
+        self
+    }
+
+    /// See `KeycloakConfig::leeway`.
+    pub fn leeway(self, leeway: Duration) -> Self {
+        self.configure(move |config| config.leeway = leeway)
+    }
+
+    /// See `KeycloakConfig::refresh_interval`.
+    pub fn refresh_interval(self, refresh_interval: Duration) -> Self {
+        self.configure(move |config| config.refresh_interval = Some(refresh_interval))
+    }
+
+    /// Changes any other setting of the `KeycloakConfig`, e.g. `|config| config.retry = (1, 0)`.
+    /// Called in order, after the `server` and `realm` are set.
+    pub fn configure(
+        mut self,
+        configure: impl FnOnce(&mut KeycloakConfig) + Send + 'static,
+    ) -> Self {
+        self.configure.push(Box::new(configure));
+        self
+    }
+
+    /// Assembles and validates the `KeycloakConfig` (see `KeycloakConfig::validate`),
+    /// creating the instance through `KeycloakAuthInstance::try_new`.
+    pub fn build(self) -> Result<KeycloakAuthInstance, AuthError> {
+        let missing = |what: &str| AuthError::InvalidConfiguration {
+            reason: format!("No {what} was configured."),
+        };
+        let server = self.server.ok_or_else(|| missing("Keycloak server"))?;
+        let server = Url::parse(&server).map_err(|err| AuthError::InvalidConfiguration {
+            reason: format!("The Keycloak server '{server}' is not a well-formed URL: {err}"),
+        })?;
+        let realm = self.realm.ok_or_else(|| missing("realm"))?;
+        let mut config = KeycloakConfig::builder()
+            .server(server)
+            .realm(realm)
+            .build();
+        for configure in self.configure {
+            configure(&mut config);
+        }
+        KeycloakAuthInstance::try_new(config)
+    }
+}
+
+/// Sleeps for the given delay, randomly varied by `jitter`.
+#[derive(Debug, Clone, Copy)]
+struct JitteredSleep {
+    jitter: f64,
+}
+
+impl DelayStrategy<Duration> for JitteredSleep {
+    type Out = tokio::time::Sleep;
+
+    fn delay(&self, delay: Duration) -> Self::Out {
+        tokio::time::sleep(jittered(delay, self.jitter))
+    }
+}
+
+fn jittered(delay: Duration, jitter: f64) -> Duration {
+    let jitter = jitter.clamp(0.0, 1.0);
+    if jitter == 0.0 {
+        return delay;
+    }
+    // Every `RandomState` is randomly seeded, which is random enough to spread out retries.
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    delay.mul_f64(1.0 - jitter + 2.0 * jitter * random)
 }
 
 fn debug_decoding_keys(
-    decoding_keys: &Vec<jsonwebtoken::DecodingKey>,
+    decoding_keys: &KeyStore,
     f: &mut std::fmt::Formatter<'_>,
 ) -> std::fmt::Result {
-    f.write_fmt(format_args!("len: {}", decoding_keys.len()))
+    f.write_fmt(format_args!("len: {}", decoding_keys.keys.len()))
+}
+
+/// A decoding key, together with the key ID (`kid`) it was published under (if known).
+#[derive(Clone)]
+pub(crate) struct KeyEntry {
+    pub(crate) kid: Option<String>,
+    pub(crate) key: jsonwebtoken::DecodingKey,
+    /// Algorithms this key may be used with. `None` if unknown, e.g. for keys given as plain `DecodingKey`s.
+    pub(crate) algorithms: Option<Vec<Algorithm>>,
+    /// The RFC 7638 thumbprint of the JWK this key was created from, see `KeyFingerprint::thumbprint`.
+    pub(crate) thumbprint: Option<String>,
 }
 
-#[derive(TypedBuilder, Educe)]
+impl KeyEntry {
+    pub(crate) fn new(
+        kid: Option<String>,
+        key: jsonwebtoken::DecodingKey,
+        algorithms: Option<Vec<Algorithm>>,
+    ) -> Self {
+        Self {
+            kid,
+            key,
+            algorithms,
+            thumbprint: None,
+        }
+    }
+
+    fn allows(&self, algorithm: Algorithm) -> bool {
+        self.algorithms
+            .as_ref()
+            .map_or(true, |algorithms| algorithms.contains(&algorithm))
+    }
+}
+
+/// A public key configured up front, see `KeycloakConfig::pinned_keys`.
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub struct PinnedKey {
+    #[educe(Debug(method(debug_pinned_key)))]
+    entry: KeyEntry,
+}
+
+fn debug_pinned_key(entry: &KeyEntry, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_fmt(format_args!("kid: {:?}", entry.kid))
+}
+
+impl PinnedKey {
+    /// Parses a RSA or EC public key in PEM format, e.g. "-----BEGIN PUBLIC KEY-----...".
+    /// RSA keys are used with the `RS*` and `PS*` algorithms, EC keys with `ES256` and `ES384`.
+    /// Returns an `AuthError::CreateDecodingKey` if the PEM holds neither.
+    pub fn from_pem(pem: impl AsRef<[u8]>) -> Result<Self, AuthError> {
+        let pem = pem.as_ref();
+        let (key, algorithms) = match jsonwebtoken::DecodingKey::from_rsa_pem(pem) {
+            Ok(key) => (
+                key,
+                vec![
+                    Algorithm::RS256,
+                    Algorithm::RS384,
+                    Algorithm::RS512,
+                    Algorithm::PS256,
+                    Algorithm::PS384,
+                    Algorithm::PS512,
+                ],
+            ),
+            Err(_) => (
+                jsonwebtoken::DecodingKey::from_ec_pem(pem).context(CreateDecodingKeySnafu {})?,
+                vec![Algorithm::ES256, Algorithm::ES384],
+            ),
+        };
+        Ok(Self {
+            entry: KeyEntry::new(None, key, Some(algorithms)),
+        })
+    }
+
+    /// Associates the key with a key ID (`kid`). Tokens naming this ID are only checked against this key.
+    pub fn with_kid(mut self, kid: impl Into<String>) -> Self {
+        self.entry.kid = Some(kid.into());
+        self
+    }
+}
+
+/// Identifies a loaded decoding key, see `KeycloakAuthInstance::key_fingerprints`.
+///
+/// Allows verifying that the expected keys were loaded, e.g. by comparing the thumbprints against the ones of your realm.
+/// Only public information is contained. Decoding keys hold no private material to begin with,
+/// and no thumbprint is derived from secrets, as a hash of a secret would allow guessing it offline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFingerprint {
+    /// The key ID (`kid`) the key was published under. `None` for keys without an ID, e.g. configured secrets.
+    pub kid: Option<String>,
+    /// Algorithms the key may be used with. `None` if unknown, e.g. for keys given as plain `DecodingKey`s.
+    pub algorithms: Option<Vec<Algorithm>>,
+    /// The base64url encoded SHA-256 JWK thumbprint (RFC 7638) of the key.
+    /// `None` for keys not loaded from a JWK and for symmetric keys.
+    pub thumbprint: Option<String>,
+}
+
+impl From<&KeyEntry> for KeyFingerprint {
+    fn from(entry: &KeyEntry) -> Self {
+        Self {
+            kid: entry.kid.clone(),
+            algorithms: entry.algorithms.clone(),
+            thumbprint: entry.thumbprint.clone(),
+        }
+    }
+}
+
+/// A set of decoding keys, indexed by their key IDs.
+#[derive(Clone, Default)]
+pub(crate) struct KeyStore {
+    /// All keys, in the order they were received in.
+    keys: Vec<KeyEntry>,
+    /// Maps key IDs to their index in `keys`.
+    by_kid: HashMap<String, usize>,
+}
+
+impl KeyStore {
+    pub(crate) fn new(keys: Vec<KeyEntry>) -> Self {
+        let by_kid = keys
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, entry)| entry.kid.clone().map(|kid| (kid, idx)))
+            .collect();
+        Self { keys, by_kid }
+    }
+
+    pub(crate) fn get(&self, kid: &str) -> Option<&KeyEntry> {
+        self.by_kid.get(kid).map(|idx| &self.keys[*idx])
+    }
+
+    pub(crate) fn iter(&self) -> std::slice::Iter<'_, KeyEntry> {
+        self.keys.iter()
+    }
+}
+
+#[derive(Clone, TypedBuilder, Educe)]
 #[educe(Debug)]
 pub(crate) struct DiscoveredData {
-    #[allow(dead_code)]
     pub(crate) oidc_config: OidcConfig,
-    #[allow(dead_code)]
     pub(crate) jwk_set: jsonwebtoken::jwk::JwkSet,
     #[educe(Debug(method(debug_decoding_keys)))]
-    pub(crate) decoding_keys: Vec<jsonwebtoken::DecodingKey>,
+    pub(crate) decoding_keys: KeyStore,
 }
 
 /// The KeycloakAuthInstance is responsible for performing OIDC discovery
@@ -73,40 +561,79 @@ pub(crate) struct DiscoveredData {
 ///
 /// You may want to create only a single insatnce of this struct
 /// to limit the amount of requests made towards your Keycloak server.
-#[derive(Debug)]
+#[derive(Educe)]
+#[educe(Debug)]
 pub struct KeycloakAuthInstance {
     #[allow(dead_code)]
     pub(crate) id: uuid::Uuid,
     #[allow(dead_code)]
     pub(crate) config: KeycloakConfig,
     pub(crate) oidc_discovery_endpoint: OidcDiscoveryEndpoint,
-    pub(crate) discovery: Action<OidcDiscoveryEndpoint, Result<DiscoveredData, AuthError>>,
+    /// The issuer ('iss' claim) of tokens of the configured realm.
+    issuer: String,
+    /// `None` if this instance was created with statically configured decoding keys.
+    pub(crate) discovery: Option<Action<OidcDiscoveryEndpoint, Result<DiscoveredData, AuthError>>>,
+    /// Decoding keys known without performing OIDC discovery.
+    #[educe(Debug(method(debug_decoding_keys)))]
+    pub(crate) static_keys: KeyStore,
+    /// Held while a re-discovery is performed, making sure that only one is in flight at any time.
+    /// Shared with the background refresh task.
+    pub(crate) refresh_lock: Arc<Mutex<()>>,
+    /// Periodically refreshes the decoding keys, see `KeycloakConfig::refresh_interval`. Aborted on drop.
+    #[allow(dead_code)]
+    #[educe(Debug(ignore))]
+    background_refresh: Option<AbortOnDrop>,
+    /// `Some` if tokens are validated through token introspection instead of locally.
+    pub(crate) introspection: Option<Introspection>,
 }
 
 impl KeycloakAuthInstance {
+    /// Fluent alternative to `KeycloakAuthInstance::try_new`, see `KeycloakAuthInstanceBuilder`.
+    pub fn builder() -> KeycloakAuthInstanceBuilder {
+        KeycloakAuthInstanceBuilder::default()
+    }
+
     /// Creates a new KeycloakAuthInstance. This immediately starts an initial OIDC discovery process.
     /// The `is_operational` method will tell you if discovery has taken place.
     /// This may be useful in determining service health.
     pub fn new(kc_config: KeycloakConfig) -> Self {
         let id = uuid::Uuid::now_v7();
-        let oidc_discovery_endpoint = OidcDiscoveryEndpoint::from_server_and_realm(
-            kc_config.server.clone(),
-            &kc_config.realm,
-        );
+        let oidc_discovery_endpoint = OidcDiscoveryEndpoint::of_config(&kc_config);
 
         let kc_server = kc_config.server.to_string();
         let kc_realm = kc_config.realm.clone();
+        let retry_strategy = Retry {
+            max_tries: kc_config.retry.0,
+            delay: Some(match kc_config.backoff {
+                Some(backoff) => try_again::Delay::ExponentialBackoff {
+                    initial_delay: backoff.initial_delay,
+                    max_delay: Some(backoff.max_delay),
+                },
+                None => try_again::Delay::Static {
+                    delay: std::time::Duration::from_secs(kc_config.retry.1),
+                },
+            }),
+        };
+        let delay_strategy = JitteredSleep {
+            jitter: kc_config.backoff.map_or(0.0, |backoff| backoff.jitter),
+        };
 
+        let http_client = kc_config
+            .discovery_client
+            .clone()
+            .unwrap_or_else(|| HttpClient::from(kc_config.http_client.clone()));
+        let request_timeout = kc_config.discovery_request_timeout;
+        let jwks_url = kc_config.jwks_url.clone();
+        let on_keys_changed = kc_config.on_keys_changed.clone();
+        let last_discovered = Arc::new(std::sync::Mutex::new(None));
         let discovery = Action::new(move |oidc_discovery_endpoint: &OidcDiscoveryEndpoint| {
             let kc_server = kc_server.clone();
             let kc_realm = kc_realm.clone();
+            let http_client = http_client.clone();
+            let jwks_url = jwks_url.clone();
+            let on_keys_changed = on_keys_changed.clone();
+            let last_discovered = last_discovered.clone();
             let oidc_discovery_endpoint = oidc_discovery_endpoint.clone();
-            let retry_strategy = Retry {
-                max_tries: kc_config.retry.0,
-                delay: Some(try_again::Delay::Static {
-                    delay: std::time::Duration::from_secs(kc_config.retry.1),
-                }),
-            };
             async move {
                 let span = tracing::span!(
                     tracing::Level::INFO,
@@ -116,83 +643,594 @@ impl KeycloakAuthInstance {
                     kc_realm,
                     oidc_discovery_endpoint = ?oidc_discovery_endpoint.0.to_string()
                 );
-                perform_oidc_discovery(oidc_discovery_endpoint, retry_strategy)
-                    .instrument(span)
-                    .await
+                let discovered = perform_oidc_discovery(
+                    http_client,
+                    request_timeout,
+                    oidc_discovery_endpoint,
+                    jwks_url,
+                    retry_strategy,
+                    delay_strategy,
+                )
+                .instrument(span)
+                .await;
+                keep_last_discovered(discovered, &last_discovered, on_keys_changed.as_ref())
             }
         });
 
         discovery.dispatch(oidc_discovery_endpoint.clone());
 
+        let refresh_lock = Arc::new(Mutex::new(()));
+        let background_refresh = kc_config.refresh_interval.map(|interval| {
+            AbortOnDrop(tokio::spawn(refresh_periodically(
+                interval,
+                discovery.clone(),
+                oidc_discovery_endpoint.clone(),
+                refresh_lock.clone(),
+            )))
+        });
+
         Self {
             id,
+            issuer: issuer_of_realm(&kc_config),
+            static_keys: pinned_keys(&kc_config, Vec::new()),
             config: kc_config,
             oidc_discovery_endpoint,
-            discovery,
+            discovery: Some(discovery),
+            refresh_lock,
+            background_refresh,
+            introspection: None,
         }
     }
 
-    pub(crate) async fn perform_oidc_discovery(&self) {
-        // Wait for an ongoing discovery or dispatch a new discovery process.
-        if self.discovery.is_pending() {
-            self.discovery.notified().await;
-        } else {
-            self.discovery
-                .dispatch(self.oidc_discovery_endpoint.clone())
-                .await
-                .expect("No Join error");
+    /// Like `new`, but first checks the configuration, see `KeycloakConfig::validate`.
+    /// Returns an `AuthError::InvalidConfiguration` instead of starting a discovery which is bound to fail.
+    pub fn try_new(kc_config: KeycloakConfig) -> Result<Self, AuthError> {
+        kc_config.validate()?;
+        Ok(Self::new(kc_config))
+    }
+
+    /// Creates a new KeycloakAuthInstance, waiting for the initial OIDC discovery to succeed.
+    ///
+    /// Should a discovery fail (after all of its `retry` tries), another one is started after `min_refresh_interval`,
+    /// until one succeeds or the `timeout` elapses. This avoids running a service which rejects all tokens
+    /// because its decoding keys were never loaded.
+    pub async fn new_and_wait(
+        kc_config: KeycloakConfig,
+        timeout: Duration,
+    ) -> Result<Self, AuthError> {
+        let instance = Self::new(kc_config);
+        let discovery = instance
+            .discovery
+            .as_ref()
+            .expect("Instances created through `new` to perform OIDC discovery");
+        let initial_discovery = async {
+            loop {
+                discovery.wait().await;
+                if instance.is_operational().await {
+                    return;
+                }
+                tokio::time::sleep(instance.config.min_refresh_interval).await;
+                discovery
+                    .dispatch(instance.oidc_discovery_endpoint.clone())
+                    .await
+                    .expect("No Join error");
+            }
+        };
+        match tokio::time::timeout(timeout, initial_discovery).await {
+            Ok(()) => Ok(instance),
+            Err(_) => Err(AuthError::DiscoveryTimeout { timeout }),
+        }
+    }
+
+    /// Creates a new KeycloakAuthInstance validating tokens signed with a shared (symmetric) secret,
+    /// for example a Keycloak client secret used with `Algorithm::HS256`.
+    ///
+    /// No OIDC discovery is performed. The instance is immediately operational and never contacts your Keycloak server.
+    /// Only tokens announcing the given `algorithm` in their header are accepted.
+    /// This is mostly useful for local setups and for testing your handlers.
+    pub fn from_secret(kc_config: KeycloakConfig, secret: &[u8], algorithm: Algorithm) -> Self {
+        Self::from_secrets(kc_config, [secret], algorithm)
+    }
+
+    /// Like `from_secret`, but accepting tokens signed with any of the given secrets.
+    /// This allows rotating a client secret without downtime: Configure the new secret alongside the old one
+    /// until no more tokens signed with the old secret are in circulation.
+    ///
+    /// Secrets are always tried in the given order, so list the current secret first.
+    /// A token is only rejected once every secret failed to verify its signature.
+    pub fn from_secrets<S: AsRef<[u8]>>(
+        kc_config: KeycloakConfig,
+        secrets: impl IntoIterator<Item = S>,
+        algorithm: Algorithm,
+    ) -> Self {
+        Self::with_static_keys(
+            kc_config,
+            secrets
+                .into_iter()
+                .map(|secret| {
+                    KeyEntry::new(
+                        None,
+                        jsonwebtoken::DecodingKey::from_secret(secret.as_ref()),
+                        Some(vec![algorithm]),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Creates a new KeycloakAuthInstance from a JWK set given in its JSON representation,
+    /// as served by the `jwks_uri` of your Keycloak realm.
+    ///
+    /// No OIDC discovery is performed. Use this in environments which can not reach your Keycloak server at runtime.
+    /// Keys not convertible to a `DecodingKey` are ignored, `key_ids` can be used to verify that all expected keys were loaded.
+    pub fn from_jwks(kc_config: KeycloakConfig, jwks: &str) -> Result<Self, AuthError> {
+        let jwk_set = serde_json::from_str::<jsonwebtoken::jwk::JwkSet>(jwks).map_err(|err| {
+            AuthError::JsonParse {
+                source: Arc::new(err),
+            }
+        })?;
+        Ok(Self::with_static_keys(kc_config, parse_jwks(&jwk_set)))
+    }
+
+    /// Creates a new KeycloakAuthInstance using the given decoding keys,
+    /// for example created through `DecodingKey::from_rsa_pem`.
+    ///
+    /// No OIDC discovery is performed. Use this in environments which can not reach your Keycloak server at runtime.
+    pub fn from_decoding_keys(
+        kc_config: KeycloakConfig,
+        decoding_keys: Vec<jsonwebtoken::DecodingKey>,
+    ) -> Self {
+        Self::with_static_keys(
+            kc_config,
+            decoding_keys
+                .into_iter()
+                .map(|key| KeyEntry::new(None, key, None))
+                .collect(),
+        )
+    }
+
+    /// Creates a new KeycloakAuthInstance validating tokens through OAuth2 token introspection
+    /// at the `/protocol/openid-connect/token/introspect` endpoint of your Keycloak realm.
+    ///
+    /// No OIDC discovery is performed and no decoding keys are used. Every token (also opaque ones) is sent to Keycloak,
+    /// which makes revocations effective immediately but adds the latency of a request. See `IntrospectionConfig::cache_ttl`.
+    pub fn introspecting(kc_config: KeycloakConfig, introspection: IntrospectionConfig) -> Self {
+        let introspection = Introspection::new(
+            kc_config.http_client.clone(),
+            kc_config.clock.clone(),
+            kc_config.server.clone(),
+            &kc_config.realm,
+            introspection,
+        );
+        Self {
+            introspection: Some(introspection),
+            ..Self::with_static_keys(kc_config, Vec::new())
+        }
+    }
+
+    /// Creates a new KeycloakAuthInstance validating tokens with the `pinned_keys` of the config only.
+    ///
+    /// No OIDC discovery is performed. Use this to pin the keys tokens must be signed with, independent of what your Keycloak server publishes.
+    pub fn from_pinned_keys(kc_config: KeycloakConfig) -> Self {
+        Self::with_static_keys(kc_config, Vec::new())
+    }
+
+    fn with_static_keys(kc_config: KeycloakConfig, static_keys: Vec<KeyEntry>) -> Self {
+        let static_keys = pinned_keys(&kc_config, static_keys);
+        let oidc_discovery_endpoint = OidcDiscoveryEndpoint::of_config(&kc_config);
+        Self {
+            id: uuid::Uuid::now_v7(),
+            issuer: issuer_of_realm(&kc_config),
+            config: kc_config,
+            oidc_discovery_endpoint,
+            discovery: None,
+            static_keys,
+            refresh_lock: Arc::new(Mutex::new(())),
+            background_refresh: None,
+            introspection: None,
         }
     }
 
+    /// The OIDC configuration (discovery document) of the realm, as of the last successful OIDC discovery.
+    /// `None` if no discovery succeeded yet or this instance does not use OIDC discovery.
+    pub async fn oidc_config(&self) -> Option<OidcConfig> {
+        let discovery = self.discovery.as_ref()?;
+        let discovered = discovery.value().await;
+        discovered
+            .as_ref()
+            .and_then(|it| it.as_ref().ok())
+            .map(|it| it.oidc_config.clone())
+    }
+
+    /// The authorization, token, userinfo and end session endpoints of the realm, e.g. to redirect users to log in or out.
+    /// Endpoints not announced by the realm are `None`. See `oidc_config` for when this is `None` altogether.
+    pub async fn endpoints(&self) -> Option<OidcEndpoints> {
+        self.oidc_config().await.as_ref().map(OidcEndpoints::from)
+    }
+
+    /// Exchanges the given refresh token for a new access (and refresh) token, using the `client_credentials` configured.
+    ///
+    /// The token endpoint is taken from the discovered OIDC configuration, or derived from the `server` and `realm` configured.
+    /// Rejected refresh tokens (e.g. expired or revoked ones) result in an `AuthError::InvalidGrant`.
+    pub async fn refresh(&self, refresh_token: &str) -> Result<TokenResponse, AuthError> {
+        refresh::request_token(
+            &self.config.http_client,
+            self.token_endpoint().await?,
+            self.client_credentials()?,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+            ],
+        )
+        .await
+    }
+
+    /// Requests tokens for the given user through the resource owner password credentials grant ("Direct access grants"),
+    /// using the `client_credentials` configured. Requires direct access grants to be enabled for the client.
+    /// Invalid user credentials result in an `AuthError::InvalidGrant`.
+    ///
+    /// Only meant for CLI tools and integration tests of protected routes!
+    /// The password grant hands the credentials of users to your application and is deprecated
+    /// by the OAuth 2.0 Security Best Current Practice. Never use it to log in users in production.
+    #[cfg(feature = "password-grant")]
+    pub async fn password_grant(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<TokenResponse, AuthError> {
+        refresh::request_token(
+            &self.config.http_client,
+            self.token_endpoint().await?,
+            self.client_credentials()?,
+            &[
+                ("grant_type", "password"),
+                ("username", username),
+                ("password", password),
+            ],
+        )
+        .await
+    }
+
+    /// Validates an OpenID Connect ID token issued to the client `client_id`, e.g. when completing a login.
+    ///
+    /// Signature, issuer, expiry and not-before time are validated as for access tokens, using the keys of this instance.
+    /// The token must be issued to `client_id` (its 'aud' and, if present, 'azp' claim) and, if present, be of type "ID".
+    /// Pass the nonce sent with the authentication request as `expected_nonce` to detect replayed tokens.
+    ///
+    /// Never accept ID tokens as access tokens! This entry point is independent of any `KeycloakAuthLayer`:
+    /// Its expected audiences, required roles and metrics do not apply here.
+    pub async fn validate_id_token<Extra: DeserializeOwned>(
+        &self,
+        id_token: &str,
+        client_id: &str,
+        expected_nonce: Option<&str>,
+    ) -> Result<IdToken<Extra>, AuthError> {
+        id_token::validate(self, id_token, client_id, expected_nonce).await
+    }
+
+    /// Decodes the claims of the given token for diagnostic purposes, e.g. to show when a rejected token expired.
+    ///
+    /// **This does not authorize anything!** Only the signature and issuer of the token are validated.
+    /// Expired or not yet valid tokens, tokens issued for any audience and tokens lacking any role are decoded just the same.
+    /// Never grant access based on the result, protect your routes with the `KeycloakAuthLayer` instead.
+    /// Not supported by introspecting instances, as introspection only reports active tokens.
+    pub async fn decode_unverified_claims<Extra: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<StandardClaims<Extra>, AuthError> {
+        decode::decode_unenforced(self, token).await
+    }
+
+    fn client_credentials(&self) -> Result<&ClientCredentials, AuthError> {
+        self.config
+            .client_credentials
+            .as_ref()
+            .ok_or_else(|| AuthError::InvalidConfiguration {
+                reason: String::from(
+                    "No client_credentials were configured to request tokens with.",
+                ),
+            })
+    }
+
+    /// The discovered token endpoint, or the one derived from the `server` and `realm` configured.
+    async fn token_endpoint(&self) -> Result<Url, AuthError> {
+        match self
+            .oidc_config()
+            .await
+            .and_then(|config| config.standard_claims.token_endpoint)
+        {
+            Some(token_endpoint) => {
+                Url::parse(&token_endpoint).map_err(|err| AuthError::InvalidConfiguration {
+                    reason: format!("The discovered token endpoint is not a valid URL: {err}"),
+                })
+            }
+            None => Ok(refresh::derived_token_endpoint(
+                &self.config.server,
+                &self.config.realm,
+            )),
+        }
+    }
+
+    /// The issuer ('iss' claim) of tokens issued by the configured realm, derived from the `server` and `realm` configured.
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    /// Returns true if the given issuer ('iss' claim) denotes the realm of this instance.
+    /// Only the path is compared, as Keycloak may be reachable under a different hostname than configured here.
+    pub(crate) fn is_issuer_of_realm(&self, issuer: &str) -> bool {
+        issuer
+            .trim_end_matches('/')
+            .ends_with(format!("/realms/{}", self.config.realm).as_str())
+    }
+
+    /// Returns true if decoding keys are retrieved (and can be refreshed) through OIDC discovery.
+    pub(crate) fn uses_discovery(&self) -> bool {
+        self.discovery.is_some()
+    }
+
+    /// Performs a new OIDC discovery, refreshing the known decoding keys.
+    ///
+    /// `known_version` must be the `DecodingKeys::version` of the keys the caller found to be insufficient.
+    /// Concurrent calls are coalesced: Only one discovery is in flight at any time.
+    /// Callers waiting for it reuse its result instead of starting yet another discovery,
+    /// as the keys were already refreshed since they looked at them.
+    ///
+    /// Returns false if no new keys are available, either because this instance does not use OIDC discovery
+    /// or because the last discovery happened less than `min_refresh_interval` ago.
+    pub(crate) async fn perform_oidc_discovery(&self, known_version: usize) -> bool {
+        let Some(discovery) = &self.discovery else {
+            return false;
+        };
+        let _guard = self.refresh_lock.lock().await;
+
+        // The initial discovery is dispatched without holding the lock. It may still be ongoing.
+        discovery.wait().await;
+        if discovery.version().await > known_version {
+            return true;
+        }
+
+        if let Some(last_discovery) = *discovery.value_received().await {
+            let elapsed = time::OffsetDateTime::now_utc() - last_discovery;
+            if elapsed < self.config.min_refresh_interval {
+                tracing::debug!(
+                    ?elapsed,
+                    "Skipping OIDC discovery, as the last discovery happened too recently."
+                );
+                return false;
+            }
+        }
+        discovery
+            .dispatch(self.oidc_discovery_endpoint.clone())
+            .await
+            .expect("No Join error");
+        true
+    }
+
     pub(crate) fn is_ready(&self) -> bool {
-        !self.discovery.is_pending()
+        self.discovery
+            .as_ref()
+            .map_or(true, |discovery| !discovery.is_pending())
     }
 
     /// Returns true after a successful OIDC discovery.
+    /// Always returns true for instances using statically configured decoding keys.
     pub async fn is_operational(&self) -> bool {
-        self.discovery
-            .value()
-            .await
-            .as_ref()
-            .is_some_and(|it| it.is_ok())
+        match &self.discovery {
+            Some(discovery) => discovery
+                .value()
+                .await
+                .as_ref()
+                .is_some_and(|it| it.is_ok()),
+            None => true,
+        }
     }
 
     pub(crate) async fn decoding_keys(&self) -> DecodingKeys<'_> {
-        DecodingKeys {
-            // Note: Tokios RwLock implementation prioritizes write access to prevent starvation. This is fine and will not block writes.
-            lock: self.discovery.value().await,
+        match &self.discovery {
+            Some(discovery) => DecodingKeys {
+                // Note: The version is read before the keys. Should a discovery finish in between,
+                // we will only underestimate the version, which at most leads to an unnecessary retry.
+                version: discovery.version().await,
+                // Note: Tokios RwLock implementation prioritizes write access to prevent starvation. This is fine and will not block writes.
+                lock: Some(discovery.value().await),
+                static_keys: &self.static_keys,
+            },
+            None => DecodingKeys {
+                version: 0,
+                lock: None,
+                static_keys: &self.static_keys,
+            },
+        }
+    }
+
+    /// Returns the key IDs (`kid`) of all currently known decoding keys. Keys without an ID are omitted.
+    /// This may be used to verify on startup that the keys you expect to be in use are known.
+    pub async fn key_ids(&self) -> Vec<String> {
+        self.decoding_keys()
+            .await
+            .entries()
+            .filter_map(|entry| entry.kid.clone())
+            .collect()
+    }
+
+    /// Returns the `KeyFingerprint`s of all currently known decoding keys, in the order they are tried in.
+    /// Unlike `key_ids`, keys without an ID are included. Useful to expose through an admin endpoint
+    /// when investigating why valid tokens are rejected.
+    pub async fn key_fingerprints(&self) -> Vec<KeyFingerprint> {
+        self.decoding_keys()
+            .await
+            .entries()
+            .map(KeyFingerprint::from)
+            .collect()
+    }
+}
+
+/// The `pinned_keys` of the config, followed by the given keys.
+fn pinned_keys(kc_config: &KeycloakConfig, keys: Vec<KeyEntry>) -> KeyStore {
+    KeyStore::new(
+        kc_config
+            .pinned_keys
+            .iter()
+            .map(|pinned| pinned.entry.clone())
+            .chain(keys)
+            .collect(),
+    )
+}
+
+/// Aborts the wrapped task when dropped.
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Re-runs the OIDC discovery every `interval`.
+///
+/// Takes the same `refresh_lock` as on-demand re-discoveries (see `KeycloakAuthInstance::perform_oidc_discovery`),
+/// so that never more than one discovery is in flight. Both update the keys stored in the shared `discovery` action.
+async fn refresh_periodically(
+    interval: Duration,
+    discovery: Action<OidcDiscoveryEndpoint, Result<DiscoveredData, AuthError>>,
+    oidc_discovery_endpoint: OidcDiscoveryEndpoint,
+    refresh_lock: Arc<Mutex<()>>,
+) {
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let _guard = refresh_lock.lock().await;
+        discovery.wait().await;
+        tracing::debug!("Refreshing decoding keys periodically.");
+        if let Err(err) = discovery.dispatch(oidc_discovery_endpoint.clone()).await {
+            tracing::error!(?err, "Periodic OIDC discovery did not complete.");
         }
     }
 }
 
 pub(crate) struct DecodingKeys<'a> {
-    lock: RwLockReadGuard<'a, Option<Result<DiscoveredData, AuthError>>>,
+    /// How many discoveries resolved before these keys were read.
+    version: usize,
+    lock: Option<RwLockReadGuard<'a, Option<Result<DiscoveredData, AuthError>>>>,
+    static_keys: &'a KeyStore,
 }
 
 impl<'a> DecodingKeys<'a> {
-    /// Iterate over the currently known decoding keys.
-    /// This may return an empty iterator if no keys are known!
-    pub(crate) fn iter(&self) -> impl Iterator<Item = &jsonwebtoken::DecodingKey> {
+    pub(crate) fn version(&self) -> usize {
+        self.version
+    }
+
+    /// Iterate over the currently known decoding keys, including their key IDs.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = &KeyEntry> {
+        let discovered = self.discovered().map(KeyStore::iter).unwrap_or_default();
+        self.static_keys.iter().chain(discovered)
+    }
+
+    /// Iterate over the decoding keys which should be tried when validating a token with the given key ID (`kid`).
+    /// If a key with that ID is known, only that key is returned. Otherwise, all known keys are returned.
+    pub(crate) fn candidates(
+        &self,
+        kid: Option<&str>,
+    ) -> impl Iterator<Item = &jsonwebtoken::DecodingKey> {
+        let matching = kid.and_then(|kid| {
+            self.static_keys
+                .get(kid)
+                .or_else(|| self.discovered().and_then(|store| store.get(kid)))
+        });
+        matching
+            .into_iter()
+            .chain(self.entries().filter(move |_| matching.is_none()))
+            .map(|entry| &entry.key)
+    }
+
+    /// Returns true if the given algorithm may be used with any of the currently known keys.
+    /// Also true if no keys are known, leaving it to decoding to report the missing keys.
+    pub(crate) fn allow(&self, algorithm: Algorithm) -> bool {
+        let mut entries = self.entries().peekable();
+        entries.peek().is_none() || entries.any(|entry| entry.allows(algorithm))
+    }
+
+    fn discovered(&self) -> Option<&KeyStore> {
         self.lock
             .as_ref()
-            .map(|r| r.as_ref())
-            .and_then(|r| r.ok())
-            .map(|d| d.decoding_keys.iter())
-            .unwrap_or_default()
+            .and_then(|lock| lock.as_ref())
+            .and_then(|r| r.as_ref().ok())
+            .map(|d| &d.decoding_keys)
+    }
+}
+
+/// Falls back to the data of the last successful discovery should the given discovery have failed,
+/// so that a failed (e.g. timed out) re-discovery does not discard keys which are still valid.
+/// Reports changed keys of a successful re-discovery to `on_keys_changed`.
+fn keep_last_discovered(
+    discovered: Result<DiscoveredData, AuthError>,
+    last_discovered: &std::sync::Mutex<Option<DiscoveredData>>,
+    on_keys_changed: Option<&OnKeysChanged>,
+) -> Result<DiscoveredData, AuthError> {
+    let mut last_discovered = match last_discovered.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    match (discovered, last_discovered.as_ref()) {
+        (Ok(discovered), last) => {
+            let change = match (on_keys_changed, last) {
+                (Some(_), Some(last)) if !same_keys(&last.jwk_set, &discovered.jwk_set) => Some((
+                    key_ids(&last.decoding_keys),
+                    key_ids(&discovered.decoding_keys),
+                )),
+                _ => None,
+            };
+            *last_discovered = Some(discovered.clone());
+            drop(last_discovered);
+            if let (Some(on_keys_changed), Some((old, new))) = (on_keys_changed, change) {
+                tracing::info!(?old, ?new, "Discovered a changed set of decoding keys.");
+                on_keys_changed(&old, &new);
+            }
+            Ok(discovered)
+        }
+        (Err(err), Some(last)) => {
+            tracing::warn!(
+                err = snafu::Report::from_error(err).to_string(),
+                "OIDC discovery failed. Keeping the previously discovered keys."
+            );
+            Ok(last.clone())
+        }
+        (Err(err), None) => Err(err),
     }
 }
 
+/// Returns true if both sets contain the same keys, in whatever order. Compares the key material, not only the key IDs,
+/// as a key may be replaced under the same ID.
+fn same_keys(a: &jsonwebtoken::jwk::JwkSet, b: &jsonwebtoken::jwk::JwkSet) -> bool {
+    a.keys.len() == b.keys.len() && a.keys.iter().all(|key| b.keys.contains(key))
+}
+
+/// The sorted IDs of the given keys. Keys without an ID are skipped.
+fn key_ids(keys: &KeyStore) -> Vec<String> {
+    let mut key_ids: Vec<String> = keys.iter().filter_map(|entry| entry.kid.clone()).collect();
+    key_ids.sort();
+    key_ids
+}
+
 async fn perform_oidc_discovery(
+    http_client: HttpClient,
+    request_timeout: Duration,
     oidc_discovery_endpoint: OidcDiscoveryEndpoint,
+    jwks_url: Option<Url>,
     retry_strategy: Retry,
+    delay_strategy: JitteredSleep,
 ) -> Result<DiscoveredData, AuthError> {
     tracing::info!("Starting OIDC discovery.");
 
     // Load OIDC config.
-    let oidc_config = try_again::retry_async(retry_strategy, try_again::TokioSleep {}, move || {
+    let client = http_client.clone();
+    let oidc_config = try_again::retry_async(retry_strategy, delay_strategy, move || {
+        let client = client.clone();
         let url = oidc_discovery_endpoint.0.clone();
         async move {
-            oidc_discovery::retrieve_oidc_config(url.clone())
+            oidc_discovery::retrieve_oidc_config(&client, url.clone(), request_timeout)
                 .await
                 .context(OidcDiscoverySnafu {})
         }
@@ -206,22 +1244,26 @@ async fn perform_oidc_discovery(
         err
     })?;
 
-    // Parse JWK endpoint if OIDC config is available.
-    let jwk_set_endpoint = Url::parse(&oidc_config.standard_claims.jwks_uri)
-        .context(JwkEndpointSnafu {})
-        .map_err(|err| {
-            tracing::error!(
-                err = snafu::Report::from_error(err.clone()).to_string(),
-                "Could not retrieve jwk_set_endpoint_url."
-            );
-            err
-        })?;
+    // Parse JWK endpoint if OIDC config is available and no endpoint is configured.
+    let jwk_set_endpoint = match jwks_url {
+        Some(jwks_url) => jwks_url,
+        None => Url::parse(&oidc_config.standard_claims.jwks_uri)
+            .context(JwkEndpointSnafu {})
+            .map_err(|err| {
+                tracing::error!(
+                    err = snafu::Report::from_error(err.clone()).to_string(),
+                    "Could not retrieve jwk_set_endpoint_url."
+                );
+                err
+            })?,
+    };
 
     // Load JWK set if endpoint was parsable.
-    let jwk_set = try_again::retry_async(retry_strategy, try_again::TokioSleep {}, move || {
+    let jwk_set = try_again::retry_async(retry_strategy, delay_strategy, move || {
+        let client = http_client.clone();
         let url = jwk_set_endpoint.clone();
         async move {
-            oidc_discovery::retrieve_jwk_set(url.clone())
+            oidc_discovery::retrieve_jwk_set(&client, url.clone(), request_timeout)
                 .await
                 .context(JwkSetDiscoverySnafu {})
         }
@@ -245,7 +1287,7 @@ async fn perform_oidc_discovery(
     );
 
     // Create DecodingKey instances from received JWKs.
-    let decoding_keys = parse_jwks(&jwk_set);
+    let decoding_keys = KeyStore::new(parse_jwks(&jwk_set));
 
     Ok(DiscoveredData {
         oidc_config,
@@ -254,10 +1296,15 @@ async fn perform_oidc_discovery(
     })
 }
 
-fn parse_jwks(jwk_set: &jsonwebtoken::jwk::JwkSet) -> Vec<jsonwebtoken::DecodingKey> {
+/// Creates a decoding key for every JWK of the set. Supported are RSA (`RS*`/`PS*`), EC (`ES256`/`ES384`),
+/// OKP (`EdDSA`) and symmetric (`HS*`) keys, covering all signature algorithms Keycloak can be configured with.
+fn parse_jwks(jwk_set: &jsonwebtoken::jwk::JwkSet) -> Vec<KeyEntry> {
     jwk_set.keys.iter().filter_map(|jwk| {
         match jsonwebtoken::DecodingKey::from_jwk(jwk) {
-            Ok(decoding_key) => Some(decoding_key),
+            Ok(decoding_key) => Some(KeyEntry {
+                thumbprint: jwk_thumbprint(jwk),
+                ..KeyEntry::new(jwk.common.key_id.clone(), decoding_key, Some(jwk_algorithms(jwk)))
+            }),
             Err(err) => {
                 tracing::error!(?err, "Received JWK from Keycloak which could not be parsed as a DecodingKey. Ignoring the JWK.");
                 None
@@ -265,3 +1312,435 @@ fn parse_jwks(jwk_set: &jsonwebtoken::jwk::JwkSet) -> Vec<jsonwebtoken::Decoding
         }
     }).collect::<Vec<_>>()
 }
+
+/// The SHA-256 JWK thumbprint (RFC 7638) of the given asymmetric key: The hash of the JSON object
+/// of the required members of its key type in lexicographic order, without any whitespace.
+/// `None` for symmetric keys, whose thumbprint would be a hash of the secret.
+fn jwk_thumbprint(jwk: &jsonwebtoken::jwk::Jwk) -> Option<String> {
+    use base64::Engine;
+    use jsonwebtoken::jwk::AlgorithmParameters;
+
+    fn json<T: serde::Serialize>(value: &T) -> Option<String> {
+        serde_json::to_string(value).ok()
+    }
+    let members = match &jwk.algorithm {
+        AlgorithmParameters::RSA(params) => format!(
+            r#"{{"e":{},"kty":{},"n":{}}}"#,
+            json(&params.e)?,
+            json(&params.key_type)?,
+            json(&params.n)?
+        ),
+        AlgorithmParameters::EllipticCurve(params) => format!(
+            r#"{{"crv":{},"kty":{},"x":{},"y":{}}}"#,
+            json(&params.curve)?,
+            json(&params.key_type)?,
+            json(&params.x)?,
+            json(&params.y)?
+        ),
+        AlgorithmParameters::OctetKeyPair(params) => format!(
+            r#"{{"crv":{},"kty":{},"x":{}}}"#,
+            json(&params.curve)?,
+            json(&params.key_type)?,
+            json(&params.x)?
+        ),
+        AlgorithmParameters::OctetKey(_) => return None,
+    };
+    let digest = ring::digest::digest(&ring::digest::SHA256, members.as_bytes());
+    Some(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(digest))
+}
+
+/// The signature algorithms the given JWK may be used with: The one announced in its 'alg' parameter,
+/// or all algorithms matching its key type if it announces none. Encryption keys (e.g. 'RSA-OAEP') allow no algorithm.
+fn jwk_algorithms(jwk: &jsonwebtoken::jwk::Jwk) -> Vec<Algorithm> {
+    use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve};
+
+    if let Some(key_algorithm) = jwk.common.key_algorithm {
+        return Algorithm::from_str(&key_algorithm.to_string())
+            .into_iter()
+            .collect();
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        AlgorithmParameters::EllipticCurve(params) => match params.curve {
+            EllipticCurve::P256 => vec![Algorithm::ES256],
+            EllipticCurve::P384 => vec![Algorithm::ES384],
+            _ => vec![],
+        },
+        AlgorithmParameters::OctetKey(_) => {
+            vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512]
+        }
+        AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use jsonwebtoken::Algorithm;
+
+    use url::Url;
+
+    use crate::error::AuthError;
+
+    use crate::{
+        layer::KeycloakAuthLayer,
+        role::RoleClaim,
+        testing::{TestKey, TestTokenBuilder},
+    };
+
+    use super::{Backoff, KeyFingerprint, KeycloakAuthInstance, KeycloakConfig, PinnedKey};
+
+    /// Creates an instance pointing to a server which refuses all connections.
+    async fn unreachable_instance(min_refresh_interval: Duration) -> KeycloakAuthInstance {
+        let instance = KeycloakAuthInstance::new(
+            KeycloakConfig::builder()
+                .server(Url::parse("http://127.0.0.1:1/").unwrap())
+                .realm(String::from("MyRealm"))
+                .retry((1, 0))
+                .min_refresh_interval(min_refresh_interval)
+                .build(),
+        );
+        let discovery = instance.discovery.as_ref().unwrap();
+        while discovery.version().await == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        instance
+    }
+
+    #[tokio::test]
+    async fn skips_rediscovery_within_min_refresh_interval() {
+        let instance = unreachable_instance(Duration::from_secs(60)).await;
+        assert!(!instance.perform_oidc_discovery(1).await);
+        assert_eq!(instance.discovery.as_ref().unwrap().version().await, 1);
+    }
+
+    #[tokio::test]
+    async fn performs_rediscovery_after_min_refresh_interval() {
+        let instance = unreachable_instance(Duration::ZERO).await;
+        assert!(instance.perform_oidc_discovery(1).await);
+        assert_eq!(instance.discovery.as_ref().unwrap().version().await, 2);
+    }
+
+    #[test]
+    fn jitters_delays_within_bounds() {
+        let delay = Duration::from_secs(10);
+        assert_eq!(super::jittered(delay, 0.0), delay);
+        for _ in 0..100 {
+            let jittered = super::jittered(delay, 0.2);
+            assert!(jittered >= Duration::from_secs(8) && jittered <= Duration::from_secs(12));
+        }
+    }
+
+    #[tokio::test]
+    async fn waiting_for_initial_discovery_times_out() {
+        let result = KeycloakAuthInstance::new_and_wait(
+            KeycloakConfig::builder()
+                .server(Url::parse("http://127.0.0.1:1/").unwrap())
+                .realm(String::from("MyRealm"))
+                .retry((3, 0))
+                .backoff(
+                    Backoff::builder()
+                        .initial_delay(Duration::from_millis(10))
+                        .build(),
+                )
+                .min_refresh_interval(Duration::from_millis(10))
+                .build(),
+            Duration::from_millis(300),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(AuthError::DiscoveryTimeout { timeout: _ })
+        ));
+    }
+
+    #[test]
+    fn derives_the_issuer_of_the_realm() {
+        let issuer = |server: &str| {
+            KeycloakAuthInstance::from_secret(
+                KeycloakConfig::builder()
+                    .server(Url::parse(server).unwrap())
+                    .realm(String::from("MyRealm"))
+                    .build(),
+                b"secret",
+                jsonwebtoken::Algorithm::HS256,
+            )
+            .issuer()
+            .to_owned()
+        };
+        assert_eq!(
+            issuer("https://localhost:8443/"),
+            "https://localhost:8443/realms/MyRealm"
+        );
+        assert_eq!(
+            issuer("https://localhost:8443/auth/"),
+            "https://localhost:8443/auth/realms/MyRealm"
+        );
+        assert_eq!(
+            issuer("https://localhost:8443/auth"),
+            "https://localhost:8443/auth/realms/MyRealm"
+        );
+    }
+
+    #[tokio::test]
+    async fn exposes_key_fingerprints() {
+        let config = || {
+            KeycloakConfig::builder()
+                .server(Url::parse("https://localhost:8443/").unwrap())
+                .realm(String::from("MyRealm"))
+                .build()
+        };
+        // The example key of RFC 7638, section 3.1.
+        let jwks = serde_json::json!({ "keys": [{
+            "kty": "RSA",
+            "kid": "2011-04-29",
+            "alg": "RS256",
+            "e": "AQAB",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+        }]});
+        let instance = KeycloakAuthInstance::from_jwks(config(), &jwks.to_string()).unwrap();
+        assert_eq!(
+            instance.key_fingerprints().await,
+            vec![KeyFingerprint {
+                kid: Some(String::from("2011-04-29")),
+                algorithms: Some(vec![Algorithm::RS256]),
+                thumbprint: Some(String::from("NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs")),
+            }]
+        );
+
+        let instance = KeycloakAuthInstance::from_secret(config(), b"secret", Algorithm::HS256);
+        assert_eq!(
+            instance.key_fingerprints().await,
+            vec![KeyFingerprint {
+                kid: None,
+                algorithms: Some(vec![Algorithm::HS256]),
+                thumbprint: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_pinned_pem_keys() {
+        const EC_PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEvJwWIeUJISULBGzeknSFO5dP7Dy3
+p7hwoRc2vbQ/8OrkCtJl+X9mg3UQLUerD03xsPETIs3FrcXcEvmVnEeHLQ==
+-----END PUBLIC KEY-----";
+        let rsa = PinnedKey::from_pem(include_bytes!("testing/test_key.pub.pem")).unwrap();
+        assert!(rsa.entry.allows(Algorithm::RS256));
+        assert!(!rsa.entry.allows(Algorithm::HS256));
+        let ec = PinnedKey::from_pem(EC_PUBLIC_KEY).unwrap().with_kid("ec");
+        assert_eq!(ec.entry.kid.as_deref(), Some("ec"));
+        assert_eq!(
+            ec.entry.algorithms,
+            Some(vec![Algorithm::ES256, Algorithm::ES384])
+        );
+        assert!(matches!(
+            PinnedKey::from_pem("-----BEGIN PUBLIC KEY-----\nnope\n-----END PUBLIC KEY-----"),
+            Err(AuthError::CreateDecodingKey { source: _ })
+        ));
+    }
+
+    #[tokio::test]
+    async fn validates_tokens_with_pinned_keys_while_discovery_fails() {
+        let instance = KeycloakAuthInstance::new(
+            KeycloakConfig::builder()
+                .server(Url::parse("http://127.0.0.1:1/").unwrap())
+                .realm(String::from("MyRealm"))
+                .retry((1, 0))
+                .pinned_keys(vec![PinnedKey::from_pem(include_bytes!(
+                    "testing/test_key.pub.pem"
+                ))
+                .unwrap()])
+                .build(),
+        );
+        let discovery = instance.discovery.as_ref().unwrap();
+        while discovery.version().await == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!instance.is_operational().await);
+
+        let token = TestTokenBuilder::new(
+            TestKey::rsa(
+                include_bytes!("testing/test_key.pem"),
+                include_bytes!("testing/test_key.pub.pem"),
+            ),
+            instance.config.clone(),
+        )
+        .build()
+        .unwrap();
+        let layer = |instance: KeycloakAuthInstance| {
+            KeycloakAuthLayer::<String>::builder()
+                .instance(instance)
+                .expected_audiences(vec![String::from("account")])
+                .build()
+        };
+        let pinned_only = KeycloakAuthInstance::from_pinned_keys(instance.config.clone());
+        assert!(layer(instance).validate_raw_token(&token).await.is_ok());
+        assert!(layer(pinned_only).validate_raw_token(&token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn discovers_through_configured_urls() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let gateway = format!("http://{}/gateway", listener.local_addr().unwrap());
+        let router = axum::Router::new()
+            .route(
+                "/gateway/oidc",
+                axum::routing::get(|| async {
+                    axum::Json(serde_json::json!({
+                        "issuer": "https://localhost:8443/realms/MyRealm",
+                        "authorization_endpoint": "https://localhost:8443/realms/MyRealm/protocol/openid-connect/auth",
+                        // Not reachable through the gateway.
+                        "jwks_uri": "http://keycloak.internal:8080/realms/MyRealm/protocol/openid-connect/certs",
+                        "response_types_supported": ["code"],
+                        "subject_types_supported": ["public"],
+                        "id_token_signing_alg_values_supported": ["HS256"],
+                    }))
+                }),
+            )
+            .route(
+                "/gateway/keys",
+                axum::routing::get(|| async {
+                    axum::Json(serde_json::json!({ "keys": [
+                        { "kty": "oct", "kid": "k1", "alg": "HS256", "k": "c2VjcmV0" }
+                    ] }))
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let instance = KeycloakAuthInstance::new(
+            KeycloakConfig::builder()
+                .server(Url::parse("https://localhost:8443/").unwrap())
+                .realm(String::from("MyRealm"))
+                .retry((1, 0))
+                .discovery_url(Url::parse(&format!("{gateway}/oidc")).unwrap())
+                .jwks_url(Url::parse(&format!("{gateway}/keys")).unwrap())
+                .build(),
+        );
+        let discovery = instance.discovery.as_ref().unwrap();
+        while discovery.version().await == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(instance.is_operational().await);
+        assert_eq!(instance.key_ids().await, ["k1"]);
+    }
+
+    #[test]
+    fn derives_algorithms_of_jwks() {
+        let jwk = |json: serde_json::Value| serde_json::from_value(json).unwrap();
+        assert_eq!(
+            super::jwk_algorithms(&jwk(serde_json::json!({
+                "kty": "EC", "alg": "ES384", "crv": "P-384",
+                "x": "vJwWIeUJISULBGzeknSFO5dP7Dy3p7hwoRc2vbQ_8Oo",
+                "y": "5ArSZfl_ZoN1EC1Hqw9N8bDxEyLNxa3F3BL5lZxHhy0",
+            }))),
+            vec![Algorithm::ES384]
+        );
+        assert_eq!(
+            super::jwk_algorithms(&jwk(serde_json::json!({
+                "kty": "EC", "crv": "P-256",
+                "x": "vJwWIeUJISULBGzeknSFO5dP7Dy3p7hwoRc2vbQ_8Oo",
+                "y": "5ArSZfl_ZoN1EC1Hqw9N8bDxEyLNxa3F3BL5lZxHhy0",
+            }))),
+            vec![Algorithm::ES256]
+        );
+        // Keycloak also publishes its encryption keys. These must never verify a signature.
+        assert_eq!(
+            super::jwk_algorithms(&jwk(serde_json::json!({
+                "kty": "RSA", "alg": "RSA-OAEP", "use": "enc", "n": "AQAB", "e": "AQAB",
+            }))),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = |server: &str, realm: &str| {
+            KeycloakConfig::builder()
+                .server(Url::parse(server).unwrap())
+                .realm(String::from(realm))
+        };
+        let valid = || config("https://localhost:8443/", "MyRealm");
+        assert!(valid().build().validate().is_ok());
+        for config in [
+            config("https://localhost:8443/", "").build(),
+            config("localhost:8443", "MyRealm").build(),
+            config("mailto:admin@localhost", "MyRealm").build(),
+            valid()
+                .jwks_url(Url::parse("ftp://localhost/certs").unwrap())
+                .build(),
+            valid().role_claims(vec![RoleClaim::realm("roles")]).build(),
+        ] {
+            assert!(matches!(
+                config.validate(),
+                Err(AuthError::InvalidConfiguration { reason: _ })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn builds_instance() {
+        let config = |realm: &str| {
+            KeycloakConfig::builder()
+                .server(Url::parse("http://127.0.0.1:1/").unwrap())
+                .realm(String::from(realm))
+                .retry((1, 0))
+                .build()
+        };
+        let instance = KeycloakAuthInstance::try_new(config("MyRealm")).unwrap();
+        assert_eq!(instance.issuer(), "http://127.0.0.1:1/realms/MyRealm");
+        assert!(matches!(
+            KeycloakAuthInstance::try_new(config("")),
+            Err(AuthError::InvalidConfiguration { reason: _ })
+        ));
+    }
+
+    #[tokio::test]
+    async fn builds_instance_fluently() {
+        let builder = || {
+            KeycloakAuthInstance::builder()
+                .realm("MyRealm")
+                .leeway(Duration::from_secs(30))
+                .configure(|config| config.retry = (1, 0))
+        };
+        let instance = builder().server("http://127.0.0.1:1/").build().unwrap();
+        assert_eq!(instance.issuer(), "http://127.0.0.1:1/realms/MyRealm");
+        assert_eq!(instance.config.leeway, Duration::from_secs(30));
+        assert_eq!(instance.config.retry, (1, 0));
+        for result in [
+            builder().build(),
+            builder().server("localhost:8443").build(),
+            builder().server("http://[::1").build(),
+            KeycloakAuthInstance::builder()
+                .server("http://127.0.0.1:1/")
+                .build(),
+        ] {
+            assert!(matches!(
+                result,
+                Err(AuthError::InvalidConfiguration { reason: _ })
+            ));
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+    fn builds_http_client_with_tls_settings() {
+        let tls = super::TlsConfig::builder()
+            .danger_accept_invalid_certs(true)
+            .build();
+        let config = KeycloakConfig::builder()
+            .server(Url::parse("https://localhost:8443/").unwrap())
+            .realm(String::from("MyRealm"))
+            .http_client(tls.http_client().unwrap())
+            .build();
+        assert!(config.validate().is_ok());
+    }
+}
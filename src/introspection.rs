@@ -0,0 +1,376 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use educe::Educe;
+use serde_json::Value;
+use snafu::ResultExt;
+use tokio::sync::Mutex;
+use typed_builder::TypedBuilder;
+use url::Url;
+
+use crate::{
+    clock::Clock,
+    decode::RawClaims,
+    error::{AuthError, IntrospectionSnafu},
+    oidc_discovery,
+};
+
+/// Configuration of OAuth2 token introspection (RFC 7662).
+///
+/// Instead of validating a token locally, its validity is requested from the introspection endpoint of your Keycloak realm.
+/// This allows for opaque tokens and makes revocations effective immediately,
+/// at the cost of one request towards your Keycloak server per token (see `cache_ttl`).
+#[derive(Clone, TypedBuilder, Educe)]
+#[educe(Debug)]
+pub struct IntrospectionConfig {
+    /// ID of the (confidential) client used to authenticate against the introspection endpoint.
+    #[builder(setter(into))]
+    pub client_id: String,
+
+    /// Secret of the client used to authenticate against the introspection endpoint.
+    #[builder(setter(into))]
+    #[educe(Debug(ignore))]
+    pub client_secret: String,
+
+    /// How long a positive introspection result is reused for the same token, limiting the amount of introspection calls.
+    /// Results are never reused past the tokens expiration. Revocations may go unnoticed for up to this long.
+    /// Set to `Duration::ZERO` to introspect every token. Defaults to 10 seconds.
+    #[builder(default = Duration::from_secs(10))]
+    pub cache_ttl: Duration,
+
+    /// Timeout of each introspection request. Introspection happens while handling a request,
+    /// so an unresponsive Keycloak server would otherwise stall every request carrying an uncached token.
+    /// A timed out introspection fails with an `AuthError::Introspection`. Defaults to 10 seconds.
+    #[builder(default = Duration::from_secs(10))]
+    pub request_timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct CachedClaims {
+    valid_until: time::OffsetDateTime,
+    claims: RawClaims,
+}
+
+#[derive(Educe)]
+#[educe(Debug)]
+pub(crate) struct Introspection {
+    #[educe(Debug(ignore))]
+    client: reqwest::Client,
+    endpoint: Url,
+    config: IntrospectionConfig,
+    #[educe(Debug(ignore))]
+    clock: Arc<dyn Clock>,
+    /// Claims of tokens found to be active, keyed by the raw token.
+    #[educe(Debug(ignore))]
+    cache: Mutex<HashMap<String, CachedClaims>>,
+}
+
+impl Introspection {
+    pub(crate) fn new(
+        client: reqwest::Client,
+        clock: Arc<dyn Clock>,
+        server: Url,
+        realm: &str,
+        config: IntrospectionConfig,
+    ) -> Self {
+        let mut endpoint = server;
+        endpoint
+            .path_segments_mut()
+            .expect("URL not to be a 'cannot-be-a-base' URL. We have to append segments.")
+            .extend(&[
+                "realms",
+                realm,
+                "protocol",
+                "openid-connect",
+                "token",
+                "introspect",
+            ]);
+        Self {
+            client,
+            endpoint,
+            config,
+            clock,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Introspects the given token, returning its claims if it is active and meant for one of the `expected_audiences`.
+    pub(crate) async fn introspect(
+        &self,
+        token: &str,
+        expected_audiences: &[String],
+    ) -> Result<RawClaims, AuthError> {
+        let claims = match self.cached(token).await {
+            Some(claims) => claims,
+            None => {
+                let claims = self.request(token).await?;
+                self.cache(token, &claims).await;
+                claims
+            }
+        };
+        // The cache is shared between all layers using this instance. Audiences must be checked on every use.
+        assert_audience(&claims, expected_audiences)?;
+        Ok(claims)
+    }
+
+    async fn cached(&self, token: &str) -> Option<RawClaims> {
+        let now = self.clock.now();
+        self.cache
+            .lock()
+            .await
+            .get(token)
+            .filter(|cached| cached.valid_until > now)
+            .map(|cached| cached.claims.clone())
+    }
+
+    async fn request(&self, token: &str) -> Result<RawClaims, AuthError> {
+        tracing::debug!("Introspecting token.");
+        let mut claims = oidc_discovery::introspect_token(
+            &self.client,
+            self.endpoint.clone(),
+            &self.config.client_id,
+            &self.config.client_secret,
+            token,
+            self.config.request_timeout,
+        )
+        .await
+        .context(IntrospectionSnafu {})?;
+
+        match claims.remove("active") {
+            Some(Value::Bool(true)) => {}
+            _ => return Err(AuthError::InactiveToken),
+        }
+
+        // Map the RFC 7662 response fields onto the claims they correspond to in a JWT.
+        if let Some(client_id) = claims.remove("client_id") {
+            claims.entry(String::from("azp")).or_insert(client_id);
+        }
+        if let Some(token_type) = claims.remove("token_type") {
+            claims.entry(String::from("typ")).or_insert(token_type);
+        }
+        Ok(claims)
+    }
+
+    async fn cache(&self, token: &str, claims: &RawClaims) {
+        if self.config.cache_ttl.is_zero() {
+            return;
+        }
+        let now = self.clock.now();
+        let mut valid_until = now + self.config.cache_ttl;
+        if let Some(expires_at) = claims
+            .get("exp")
+            .and_then(Value::as_i64)
+            .and_then(|exp| time::OffsetDateTime::from_unix_timestamp(exp).ok())
+        {
+            valid_until = valid_until.min(expires_at);
+        }
+
+        let mut cache = self.cache.lock().await;
+        cache.retain(|_, cached| cached.valid_until > now);
+        cache.insert(
+            token.to_owned(),
+            CachedClaims {
+                valid_until,
+                claims: claims.clone(),
+            },
+        );
+    }
+}
+
+fn assert_audience(claims: &RawClaims, expected_audiences: &[String]) -> Result<(), AuthError> {
+    // Consistent with locally validated tokens.
+    if expected_audiences.is_empty() {
+        return Ok(());
+    }
+    let matches = |aud: &Value| {
+        aud.as_str()
+            .is_some_and(|aud| expected_audiences.iter().any(|expected| expected == aud))
+    };
+    let valid = match claims.get("aud") {
+        Some(Value::Array(audiences)) => audiences.iter().any(matches),
+        Some(aud) => matches(aud),
+        None => false,
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(AuthError::InvalidToken {
+            reason: String::from("Token was not issued for any of the expected audiences"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use axum::response::IntoResponse;
+    use serde_json::json;
+    use url::Url;
+
+    use crate::{
+        decode::test::claims,
+        error::AuthError,
+        instance::{KeycloakAuthInstance, KeycloakConfig},
+        layer::KeycloakAuthLayer,
+    };
+
+    use super::IntrospectionConfig;
+
+    /// Serves an introspection endpoint considering only the token "active" to be active.
+    async fn serve_introspection() -> (Url, Arc<AtomicUsize>) {
+        let introspections = Arc::new(AtomicUsize::new(0));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+        let counter = introspections.clone();
+        let issuer_base = url.to_string();
+        let router = axum::Router::new().route(
+            "/realms/MyRealm/protocol/openid-connect/token/introspect",
+            axum::routing::post(
+                move |headers: axum::http::HeaderMap,
+                      axum::Form(form): axum::Form<HashMap<String, String>>| {
+                    let counter = counter.clone();
+                    let issuer_base = issuer_base.clone();
+                    async move {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        // "my-client:my-secret"
+                        if headers.get("authorization").unwrap()
+                            != "Basic bXktY2xpZW50Om15LXNlY3JldA=="
+                        {
+                            return axum::http::StatusCode::UNAUTHORIZED.into_response();
+                        }
+                        match form.get("token").map(String::as_str) {
+                            Some("active") => {
+                                let mut claims = claims();
+                                claims["active"] = json!(true);
+                                claims["iss"] = json!(format!("{issuer_base}realms/MyRealm"));
+                                axum::Json(claims).into_response()
+                            }
+                            Some("unanswered") => {
+                                tokio::time::sleep(Duration::from_secs(5)).await;
+                                axum::Json(json!({ "active": false })).into_response()
+                            }
+                            _ => axum::Json(json!({ "active": false })).into_response(),
+                        }
+                    }
+                },
+            ),
+        );
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        (url, introspections)
+    }
+
+    fn layer(url: Url, client_secret: &str, cache_ttl: Duration) -> KeycloakAuthLayer<String> {
+        layer_with(
+            url,
+            IntrospectionConfig::builder()
+                .client_id("my-client")
+                .client_secret(client_secret)
+                .cache_ttl(cache_ttl)
+                .build(),
+        )
+    }
+
+    fn layer_with(url: Url, introspection: IntrospectionConfig) -> KeycloakAuthLayer<String> {
+        let instance = KeycloakAuthInstance::introspecting(
+            KeycloakConfig::builder()
+                .server(url)
+                .realm(String::from("MyRealm"))
+                .build(),
+            introspection,
+        );
+        KeycloakAuthLayer::<String>::builder()
+            .instance(instance)
+            .expected_audiences(vec![String::from("account")])
+            .build()
+    }
+
+    #[tokio::test]
+    async fn accepts_active_tokens() {
+        let (url, _) = serve_introspection().await;
+        let layer = layer(url, "my-secret", Duration::from_secs(10));
+
+        let (_, token) = layer.validate_raw_token("active").await.unwrap();
+        assert_eq!(token.authorized_party, "my-client");
+        assert!(token.has_role("administrator"));
+    }
+
+    #[tokio::test]
+    async fn rejects_inactive_tokens() {
+        let (url, _) = serve_introspection().await;
+        let layer = layer(url, "my-secret", Duration::from_secs(10));
+
+        assert!(matches!(
+            layer.validate_raw_token("revoked").await,
+            Err(AuthError::InactiveToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_for_other_audiences() {
+        let (url, _) = serve_introspection().await;
+        let mut layer = layer(url, "my-secret", Duration::from_secs(10));
+        layer.expected_audiences = vec![String::from("other")];
+
+        assert!(matches!(
+            layer.validate_raw_token("active").await,
+            Err(AuthError::InvalidToken { reason: _ })
+        ));
+    }
+
+    #[tokio::test]
+    async fn fails_on_invalid_client_credentials() {
+        let (url, _) = serve_introspection().await;
+        let layer = layer(url, "wrong-secret", Duration::from_secs(10));
+
+        assert!(matches!(
+            layer.validate_raw_token("active").await,
+            Err(AuthError::Introspection { source: _ })
+        ));
+    }
+
+    #[tokio::test]
+    async fn fails_on_unanswered_introspection() {
+        let (url, _) = serve_introspection().await;
+        let layer = layer_with(
+            url,
+            IntrospectionConfig::builder()
+                .client_id("my-client")
+                .client_secret("my-secret")
+                .request_timeout(Duration::from_millis(100))
+                .build(),
+        );
+
+        let started = std::time::Instant::now();
+        assert!(matches!(
+            layer.validate_raw_token("unanswered").await,
+            Err(AuthError::Introspection { source: _ })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn caches_positive_results() {
+        let (url, introspections) = serve_introspection().await;
+        let cached = layer(url.clone(), "my-secret", Duration::from_secs(10));
+        for _ in 0..3 {
+            cached.validate_raw_token("active").await.unwrap();
+            let _ = cached.validate_raw_token("revoked").await;
+        }
+        // One for "active", three for "revoked".
+        assert_eq!(introspections.load(Ordering::SeqCst), 4);
+
+        let (url, introspections) = serve_introspection().await;
+        let uncached = layer(url, "my-secret", Duration::ZERO);
+        for _ in 0..3 {
+            uncached.validate_raw_token("active").await.unwrap();
+        }
+        assert_eq!(introspections.load(Ordering::SeqCst), 3);
+    }
+}
@@ -15,6 +15,7 @@
 //! The library will then only check that a request was performed with a valid JWT.
 //! Consider using this builder field if you have a long list of route-handlers
 //! which all require the same roles to be present.
+//! By default, all `required_roles` must be present. Set `required_roles_mode` to `RequiredRolesMode::Any` to only require one of them.
 //!
 //! ```rust
 //! use std::sync::Arc;
@@ -162,6 +163,65 @@
 //! }
 //! ```
 //!
+//! If you do not want to represent unknown roles at all, implement `TryFrom<String>` instead of `From<String>`.
+//! Roles failing the conversion are dropped, or kept by their raw name in `KeycloakToken::unknown_roles`
+//! when setting `unknown_roles(UnknownRoles::Collect)` on the `KeycloakAuthLayer`.
+//!
+//! ```rust
+//! #[derive(Debug, PartialEq, Eq, Clone)]
+//! pub enum Role {
+//!     Administrator,
+//! }
+//!
+//! impl axum_keycloak_auth::role::Role for Role {}
+//!
+//! impl std::fmt::Display for Role {
+//!     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+//!         f.write_str("Administrator")
+//!     }
+//! }
+//!
+//! impl TryFrom<String> for Role {
+//!     type Error = String;
+//!
+//!     fn try_from(value: String) -> Result<Self, Self::Error> {
+//!         match value.as_ref() {
+//!             "administrator" => Ok(Role::Administrator),
+//!             _ => Err(value),
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! # Per-route role requirements
+//!
+//! The `required_roles` of a `KeycloakAuthLayer` apply to all routes it protects.
+//! To require different roles on some routes, attach a `guard::RequireRolesLayer` to just these routes.
+//! It checks the roles of the `KeycloakToken` the outer `KeycloakAuthLayer` stored, without validating the token again.
+//!
+//! ```rust
+//! use axum::{routing::get, Router};
+//! use axum_keycloak_auth::{guard::RequireRolesLayer, instance::KeycloakAuthInstance, layer::KeycloakAuthLayer};
+//!
+//! pub fn protected_router(instance: KeycloakAuthInstance) -> Router {
+//!     Router::new()
+//!         .route(
+//!             "/reports",
+//!             get(|| async { "reports" }).layer(RequireRolesLayer::<String>::any(["auditor", "administrator"])),
+//!         )
+//!         .route(
+//!             "/settings",
+//!             get(|| async { "settings" }).layer(RequireRolesLayer::<String>::all(["administrator"])),
+//!         )
+//!         .layer(
+//!             KeycloakAuthLayer::<String>::builder()
+//!                 .instance(instance)
+//!                 .expected_audiences(vec![String::from("account")])
+//!                 .build(),
+//!         )
+//! }
+//! ```
+//!
 //! # Passthrough modes
 //!
 //! The `KeycloakAuthLayer` provides a `passthrough_mode` field, allowing you to choose between the following modes:
@@ -209,12 +269,109 @@
 //! The token from the first extractor able to successfully extract one is used to further validate the request.
 //! Other extractors are no longer considered.
 //!
-//! This crate implements two extraction strategies:
+//! Should multiple extractors be configured and none of them find a token, the request is rejected with `AuthError::NoToken`, listing why each extractor failed.
+//!
+//! This crate implements six extraction strategies:
 //!   - `AuthHeaderTokenExtractor`: Extracts the token from the `http::header::AUTHORIZATION` header.
+//!   - `AuthSchemeTokenExtractor`: Extracts the token from the `http::header::AUTHORIZATION` header, accepting the configured schemes (for example "JWT").
+//!   - `HeaderTokenExtractor`: Extracts the token from a custom header (for example `X-Forwarded-Access-Token`), stripping an optional prefix (by default "Bearer ").
 //!   - `QueryParamTokenExtractor`: Extracts the token from a query parameter (by default named "token"). Use with caution!
+//!   - `CookieTokenExtractor`: Extracts the token from a cookie (by default named "access_token"), useful for browser-based apps storing the token in an HttpOnly cookie.
+//!   - `WebSocketProtocolTokenExtractor`: Extracts the token following the "access_token" subprotocol of a WebSocket handshake.
+//!
+//! Browsers can not send an `Authorization` header when opening a WebSocket. To protect WebSocket routes,
+//! additionally configure a `QueryParamTokenExtractor::extracting_key("access_token")` or a `WebSocketProtocolTokenExtractor::default()`.
+//! Browsers then either connect to `wss://<url>?access_token=<token>` or request the subprotocols `["access_token", token]`.
+//! In the latter case, the handler must select the "access_token" subprotocol through `WebSocketUpgrade::protocols`.
 //!
 //! By default, when not explicitly setting `token_extractors`, a single `AuthHeaderTokenExtractor::default()` is used.
 //!
+//! # Token introspection
+//!
+//! Instead of validating tokens locally, a `KeycloakAuthInstance` created through `KeycloakAuthInstance::introspecting`
+//! asks the introspection endpoint of your Keycloak realm about every token, authenticating with the credentials
+//! of a confidential client. This supports opaque tokens and makes revocations effective immediately,
+//! trading in latency. Positive results are cached for `IntrospectionConfig::cache_ttl`.
+//!
+//! # Refreshing tokens
+//!
+//! Configure `KeycloakConfig::client_credentials` to exchange refresh tokens for new tokens through `KeycloakAuthInstance::refresh`.
+//! Rejected refresh tokens result in an `AuthError::InvalidGrant`. `KeycloakAuthInstance::endpoints` provides further
+//! endpoints of your realm, e.g. to redirect users to log in or out.
+//!
+//! For CLI tools and integration tests, the `password-grant` feature adds `KeycloakAuthInstance::password_grant`,
+//! requesting tokens with the credentials of a user. Do not use it in production, the password grant is deprecated.
+//!
+//! # Validating ID tokens
+//!
+//! When completing an OpenID Connect login, validate the ID token received through `KeycloakAuthInstance::validate_id_token`,
+//! passing your client ID and the nonce sent with the authentication request. This reuses the keys of the instance,
+//! but is independent of the `KeycloakAuthLayer`. Note that the layer does not check the type of tokens by default,
+//! accepting ID tokens naming an expected audience as well. Set its `expected_token_type(String::from("Bearer"))`
+//! to only accept access tokens.
+//!
+//! # Multi-tenancy
+//!
+//! Set the `tenant_claim` of the `KeycloakAuthLayer` to the name of a custom claim holding the tenant of a token.
+//! Tokens lacking this claim are rejected. Add `ClaimExtension::TenantId` to the `claim_extensions` of the layer
+//! to let handlers use the `TenantId` extractor to access the tenant.
+//! Likewise, `ClaimExtension::Subject` and the `persist_username` of the layer make the `Subject` and `Username` extractors available,
+//! sparing deeply nested handlers the extraction of the whole `KeycloakToken`.
+//!
+//! # Skipping audience validation
+//!
+//! Requests carrying the `SkipAudienceValidation` extension when reaching the `KeycloakAuthLayer` are accepted
+//! regardless of the audiences of their token. Insert it in a middleware running before the `KeycloakAuthLayer`,
+//! for example for a health route which should not require a dedicated token.
+//! The signature, expiration and all other checks configured on the layer are still performed.
+//!
+//! ```
+//! use axum::{extract::Request, middleware::map_request, routing::get, Router};
+//! use axum_keycloak_auth::SkipAudienceValidation;
+//!
+//! # fn with_auth(router: Router) -> Router { router }
+//! let router = with_auth(
+//!     Router::new()
+//!         .route("/health", get(|| async { "ok" }))
+//!         .route("/orders", get(|| async { "orders" })),
+//! )
+//! .layer(map_request(|mut request: Request| async move {
+//!     if request.uri().path() == "/health" {
+//!         request.extensions_mut().insert(SkipAudienceValidation);
+//!     }
+//!     request
+//! }));
+//! ```
+//!
+//! # gRPC
+//!
+//! Enable the `tonic` feature to protect tonic servers with the same validation. Create a `grpc::KeycloakGrpcAuthLayer`
+//! from your `KeycloakAuthLayer` and add it through `Server::layer`. The token is read from the `authorization` metadata
+//! of each call. Rejected calls receive a `tonic::Status` corresponding to the `AuthErrorKind` of the error.
+//! Accepted ones carry the `KeycloakToken` in their extensions.
+//!
+//! # Metrics
+//!
+//! Implement the `metrics::AuthMetrics` trait and pass it as `metrics` to the `KeycloakAuthLayer`
+//! to get notified about decode successes and failures, on-demand key refreshes and failed role checks.
+//! This allows recording them with the metrics backend of your choice. Nothing is recorded by default.
+//!
+//! Enable the `reqwest-middleware` feature to pass a `reqwest_middleware::ClientWithMiddleware` as the `KeycloakConfig::discovery_client`,
+//! letting the OIDC discovery requests pass the tracing, retry or metrics middleware of your application.
+//!
+//! # Testing
+//!
+//! Enable the `testing` feature to get access to the `testing::TestTokenBuilder`, minting signed tokens
+//! together with a matching `KeycloakAuthInstance`. This allows testing your protected handlers,
+//! including their 401 and 403 responses, without a running Keycloak server.
+//! Set a `clock::FixedClock` as the `KeycloakConfig::clock` to test expiry logic without depending on the current time.
+//!
+//! # Serializing tokens
+//!
+//! Enable the `serialize` feature to implement `serde::Serialize` for `KeycloakToken`, e.g. to store a validated token
+//! as part of a server-side session. Time fields are serialized as RFC 3339 strings.
+//! Requires your role type and `Extra` type to implement `Serialize` as well.
+//!
 
 #![forbid(unsafe_code)]
 //#![warn(missing_docs)]
@@ -225,15 +382,29 @@ use std::sync::Arc;
 use role::Role;
 
 mod action;
+pub mod alb;
+pub mod authorization;
+pub mod cache;
+pub mod clock;
 pub mod decode;
 pub mod error;
 pub mod extract;
+#[cfg(feature = "tonic")]
+pub mod grpc;
+pub mod guard;
+pub mod id_token;
 pub mod instance;
+pub mod introspection;
 pub mod layer;
+pub mod metrics;
 pub mod oidc;
 pub mod oidc_discovery;
+pub mod refresh;
+pub mod revocation;
 pub mod role;
 pub mod service;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 // Re-export the Url struct used when configuring a `KeycloakAuthInstance`.
 pub use url::Url;
@@ -267,3 +438,372 @@ where
     Success(decode::KeycloakToken<R, Extra>),
     Failure(Arc<error::AuthError>),
 }
+
+/// Extracts the `KeycloakToken` of the request, if it was successfully authenticated. Yields `None` otherwise.
+///
+/// Use this for routes serving both anonymous and authenticated users, protected by a `KeycloakAuthLayer`
+/// operating in `PassthroughMode::Pass`. Missing, expired or malformed tokens all result in `None`,
+/// the reason is available through the `KeycloakAuthStatus` extension.
+/// With `PassthroughMode::Block`, requests without a valid token never reach the handler.
+///
+/// ```rust
+/// use axum_keycloak_auth::OptionalKeycloakToken;
+///
+/// async fn greet(OptionalKeycloakToken(token): OptionalKeycloakToken<String>) -> String {
+///     match token {
+///         Some(token) => format!("Hello, {}!", token.subject),
+///         None => String::from("Hello, stranger!"),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct OptionalKeycloakToken<R, Extra = decode::ProfileAndEmail>(
+    pub Option<decode::KeycloakToken<R, Extra>>,
+)
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone;
+
+#[axum::async_trait]
+impl<S, R, Extra> axum::extract::FromRequestParts<S> for OptionalKeycloakToken<R, Extra>
+where
+    S: Send + Sync,
+    R: Role + 'static,
+    Extra: DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(token) = parts.extensions.get::<decode::KeycloakToken<R, Extra>>() {
+            return Ok(Self(Some(token.clone())));
+        }
+        Ok(Self(
+            match parts.extensions.get::<KeycloakAuthStatus<R, Extra>>() {
+                Some(KeycloakAuthStatus::Success(token)) => Some(token.clone()),
+                Some(KeycloakAuthStatus::Failure(_)) | None => None,
+            },
+        ))
+    }
+}
+
+/// Extracts the tenant of an authenticated request, read from the claim configured as `KeycloakAuthLayer::tenant_claim`.
+/// Requires `ClaimExtension::TenantId` in the `claim_extensions` of the `KeycloakAuthLayer`.
+///
+/// Rejects the request with `AuthError::MissingTenantId` if no tenant is known,
+/// e.g. because authentication failed in `PassthroughMode::Pass` or the extension is not configured.
+///
+/// ```rust
+/// use axum_keycloak_auth::TenantId;
+///
+/// async fn dashboard(TenantId(tenant): TenantId) -> String {
+///     format!("Dashboard of {tenant}")
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TenantId(pub String);
+
+#[axum::async_trait]
+impl<S> axum::extract::FromRequestParts<S> for TenantId
+where
+    S: Send + Sync,
+{
+    type Rejection = error::AuthError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<TenantId>()
+            .cloned()
+            .ok_or(error::AuthError::MissingTenantId)
+    }
+}
+
+/// Single claim values the `KeycloakAuthLayer` can persist as their own `Extension`, see `KeycloakAuthLayer::claim_extensions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimExtension {
+    /// Persists the 'sub' claim as a `Subject`.
+    Subject,
+    /// Persists the tenant read from the `KeycloakAuthLayer::tenant_claim` as a `TenantId`.
+    TenantId,
+}
+
+/// Extracts the subject ('sub' claim) of an authenticated request, the UUID identifying the user inside Keycloak.
+/// Requires `ClaimExtension::Subject` in the `claim_extensions` of the `KeycloakAuthLayer`.
+///
+/// Rejects the request with `AuthError::MissingClaim` if no subject is known,
+/// e.g. because authentication failed in `PassthroughMode::Pass` or the extension is not configured.
+///
+/// ```rust
+/// use axum_keycloak_auth::Subject;
+///
+/// async fn orders(Subject(user_id): Subject) -> String {
+///     format!("Orders of {user_id}")
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subject(pub String);
+
+#[axum::async_trait]
+impl<S> axum::extract::FromRequestParts<S> for Subject
+where
+    S: Send + Sync,
+{
+    type Rejection = error::AuthError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Subject>()
+            .cloned()
+            .ok_or_else(|| error::AuthError::MissingClaim {
+                claim: String::from("sub"),
+            })
+    }
+}
+
+/// Extracts the username ('preferred_username' claim) of an authenticated request.
+/// Requires the `persist_username` of the `KeycloakAuthLayer`, e.g. set to `UserProfile::username`.
+///
+/// Rejects the request with `AuthError::MissingClaim` if no username is known,
+/// e.g. because the token lacks the claim or the extension is not configured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Username(pub String);
+
+#[axum::async_trait]
+impl<S> axum::extract::FromRequestParts<S> for Username
+where
+    S: Send + Sync,
+{
+    type Rejection = error::AuthError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Username>()
+            .cloned()
+            .ok_or_else(|| error::AuthError::MissingClaim {
+                claim: String::from("preferred_username"),
+            })
+    }
+}
+
+/// Request extension loosening the validation of the `KeycloakAuthLayer` for a single request,
+/// accepting tokens regardless of their audiences ('aud' claim).
+///
+/// Only ever skips the audience checks. Signature, expiration and issuer are always validated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkipAudienceValidation;
+
+#[cfg(test)]
+mod test {
+    use axum::{routing::get, Router};
+
+    use crate::{
+        decode::UserProfile,
+        layer::KeycloakAuthLayer,
+        testing::test::{secret_builder, serve},
+        ClaimExtension, OptionalKeycloakToken, PassthroughMode, SkipAudienceValidation, Subject,
+        TenantId, Username,
+    };
+
+    #[tokio::test]
+    async fn extracts_optional_tokens() {
+        let builder = secret_builder().subject("f9b0c3b4-6d0e-4c2a-9a63-3b4c1d2e5f60");
+        let router = Router::new()
+            .route(
+                "/",
+                get(
+                    |OptionalKeycloakToken(token): OptionalKeycloakToken<String>| async move {
+                        token.map_or(String::from("anonymous"), |token| token.subject)
+                    },
+                ),
+            )
+            .layer(
+                KeycloakAuthLayer::<String>::builder()
+                    .instance(builder.instance().unwrap())
+                    .passthrough_mode(PassthroughMode::Pass)
+                    .expected_audiences(vec![String::from("account")])
+                    .build(),
+            );
+        let url = serve(router).await;
+
+        let greet = |token: Option<String>| {
+            let url = url.clone();
+            async move {
+                let mut request = reqwest::Client::new().get(url);
+                if let Some(token) = token {
+                    request = request.bearer_auth(token);
+                }
+                request.send().await.unwrap().text().await.unwrap()
+            }
+        };
+
+        assert_eq!(greet(None).await, "anonymous");
+        assert_eq!(greet(Some(String::from("malformed"))).await, "anonymous");
+        let expired = builder.clone().expired(std::time::Duration::from_secs(60));
+        assert_eq!(greet(Some(expired.build().unwrap())).await, "anonymous");
+        assert_eq!(
+            greet(Some(builder.build().unwrap())).await,
+            "f9b0c3b4-6d0e-4c2a-9a63-3b4c1d2e5f60"
+        );
+    }
+
+    #[tokio::test]
+    async fn extracts_tenant_ids() {
+        let builder = secret_builder();
+        let router = Router::new()
+            .route("/", get(|TenantId(tenant): TenantId| async move { tenant }))
+            .layer(
+                KeycloakAuthLayer::<String>::builder()
+                    .instance(builder.instance().unwrap())
+                    .expected_audiences(vec![String::from("account")])
+                    .tenant_claim("tenant_id")
+                    .claim_extensions(vec![ClaimExtension::TenantId])
+                    .build(),
+            );
+        let url = serve(router).await;
+
+        let response = reqwest::Client::new()
+            .get(&url)
+            .bearer_auth(builder.clone().claim("tenant_id", "acme").build().unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "acme");
+
+        let response = reqwest::Client::new()
+            .get(&url)
+            .bearer_auth(builder.build().unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+        assert!(response
+            .text()
+            .await
+            .unwrap()
+            .contains("missing the required claim 'tenant_id'"));
+    }
+
+    #[tokio::test]
+    async fn skips_audience_validation_on_request() {
+        let builder = secret_builder().audience(vec![String::from("other")]);
+        let router = Router::new()
+            .route("/health", get(|| async { "ok" }))
+            .route("/orders", get(|| async { "orders" }))
+            .layer(
+                KeycloakAuthLayer::<String>::builder()
+                    .instance(builder.instance().unwrap())
+                    .expected_audiences(vec![String::from("account")])
+                    .build(),
+            )
+            .layer(axum::middleware::map_request(
+                |mut request: axum::extract::Request| async move {
+                    if request.uri().path() == "/health" {
+                        request.extensions_mut().insert(SkipAudienceValidation);
+                    }
+                    request
+                },
+            ));
+        let url = serve(router).await;
+
+        let status = |path: &str, token: String| {
+            let url = format!("{url}{path}");
+            async move {
+                reqwest::Client::new()
+                    .get(url)
+                    .bearer_auth(token)
+                    .send()
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        let token = builder.build().unwrap();
+        assert_eq!(status("/health", token.clone()).await, http::StatusCode::OK);
+        assert_eq!(
+            status("/orders", token).await,
+            http::StatusCode::UNAUTHORIZED
+        );
+
+        // Only the audience is ever ignored.
+        let expired = builder.expired(std::time::Duration::from_secs(60));
+        assert_eq!(
+            status("/health", expired.build().unwrap()).await,
+            http::StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn persists_configured_claim_extensions() {
+        let builder = secret_builder().subject("f9b0c3b4-6d0e-4c2a-9a63-3b4c1d2e5f60");
+        let router = |claim_extensions: Vec<ClaimExtension>, persist_username: bool| {
+            let mut layer = KeycloakAuthLayer::<String>::builder()
+                .instance(builder.instance().unwrap())
+                .expected_audiences(vec![String::from("account")])
+                .claim_extensions(claim_extensions)
+                .build();
+            if persist_username {
+                layer.persist_username = Some(UserProfile::username);
+            }
+            Router::new()
+                .route(
+                    "/subject",
+                    get(|Subject(subject): Subject| async move { subject }),
+                )
+                .route(
+                    "/username",
+                    get(|Username(username): Username| async move { username }),
+                )
+                .layer(layer)
+        };
+        let get = |url: String, token: String| async move {
+            let response = reqwest::Client::new()
+                .get(url)
+                .bearer_auth(token)
+                .send()
+                .await
+                .unwrap();
+            (response.status(), response.text().await.unwrap())
+        };
+
+        let with_username = builder
+            .clone()
+            .claim("preferred_username", "jdoe")
+            .build()
+            .unwrap();
+        let all = serve(router(vec![ClaimExtension::Subject], true)).await;
+        assert_eq!(
+            get(format!("{all}/subject"), with_username.clone()).await,
+            (
+                http::StatusCode::OK,
+                String::from("f9b0c3b4-6d0e-4c2a-9a63-3b4c1d2e5f60")
+            )
+        );
+        assert_eq!(
+            get(format!("{all}/username"), with_username.clone()).await,
+            (http::StatusCode::OK, String::from("jdoe"))
+        );
+        let (status, _) = get(format!("{all}/username"), builder.build().unwrap()).await;
+        assert_eq!(status, http::StatusCode::UNAUTHORIZED);
+
+        let none = serve(router(vec![], false)).await;
+        let (status, _) = get(format!("{none}/subject"), with_username.clone()).await;
+        assert_eq!(status, http::StatusCode::UNAUTHORIZED);
+        let (status, _) = get(format!("{none}/username"), with_username).await;
+        assert_eq!(status, http::StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::error::AuthError;
+
+/// Callbacks invoked by the `KeycloakAuthLayer` on validation outcomes, allowing you to record metrics.
+///
+/// Every method does nothing by default. Implement the ones you are interested in,
+/// for example by incrementing counters of your `metrics` or `prometheus` registry.
+/// Callbacks are invoked on the request path and should therefore return quickly.
+pub trait AuthMetrics: Send + Sync {
+    /// A token was successfully decoded and its signature, expiration, audience and issuer validated.
+    fn decode_succeeded(&self) {}
+
+    /// A token could not be decoded or validated. Inspect `reason` to categorize the failure.
+    fn decode_failed(&self, reason: &AuthError) {
+        let _ = reason;
+    }
+
+    /// Decoding keys of the given realm were refreshed, because a token could not be decoded with the known keys.
+    /// Periodic refreshes (see `KeycloakConfig::refresh_interval`) are not reported.
+    fn keys_refreshed(&self, realm: &str) {
+        let _ = realm;
+    }
+
+    /// A token was successfully decoded, but only after refreshing the decoding keys of the given realm.
+    /// Tokens signed with keys not yet known are a sign of key rotations the periodic refresh did not yet pick up.
+    /// Also reported as a tracing event with the target `decode::KEY_REFRESH_TRACING_TARGET`. Always preceded by a `decode_succeeded` call.
+    fn decode_succeeded_after_refresh(&self, realm: &str) {
+        let _ = realm;
+    }
+
+    /// A successfully decoded token lacked the `required_roles` or `required_scoped_roles` of the layer,
+    /// or carried no roles at all while the layer is configured to `reject_tokens_without_roles`.
+    fn role_check_failed(&self, reason: &AuthError) {
+        let _ = reason;
+    }
+}
+
+/// Records nothing. Used when no `metrics` are configured on the `KeycloakAuthLayer`.
+#[derive(Debug, Clone, Default)]
+pub struct NoopAuthMetrics {}
+
+impl AuthMetrics for NoopAuthMetrics {}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use jsonwebtoken::Algorithm;
+    use serde_json::json;
+    use url::Url;
+
+    use crate::{
+        decode::test::claims,
+        error::AuthError,
+        instance::{KeycloakAuthInstance, KeycloakConfig},
+        layer::KeycloakAuthLayer,
+    };
+
+    use super::AuthMetrics;
+
+    #[derive(Default)]
+    struct CountingMetrics {
+        decode_succeeded: AtomicUsize,
+        decode_failed: AtomicUsize,
+        role_check_failed: AtomicUsize,
+    }
+
+    impl AuthMetrics for CountingMetrics {
+        fn decode_succeeded(&self) {
+            self.decode_succeeded.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn decode_failed(&self, _reason: &AuthError) {
+            self.decode_failed.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn role_check_failed(&self, _reason: &AuthError) {
+            self.role_check_failed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn encode(roles: &[&str]) -> String {
+        let mut claims = claims();
+        claims["realm_access"] = json!({ "roles": roles });
+        crate::decode::test::encode(&claims)
+    }
+
+    #[tokio::test]
+    async fn reports_validation_outcomes() {
+        let metrics = Arc::new(CountingMetrics::default());
+        let layer = KeycloakAuthLayer::<String>::builder()
+            .instance(KeycloakAuthInstance::from_secret(
+                KeycloakConfig::builder()
+                    .server(Url::parse("https://localhost:8443/").unwrap())
+                    .realm(String::from("MyRealm"))
+                    .build(),
+                b"secret",
+                Algorithm::HS256,
+            ))
+            .expected_audiences(vec![String::from("account")])
+            .required_roles(vec![String::from("administrator")])
+            .metrics(metrics.clone())
+            .build();
+
+        layer
+            .validate_raw_token(&encode(&["administrator"]))
+            .await
+            .unwrap();
+        assert!(layer.validate_raw_token(&encode(&["user"])).await.is_err());
+        assert!(layer.validate_raw_token("not-a-jwt").await.is_err());
+
+        assert_eq!(metrics.decode_succeeded.load(Ordering::SeqCst), 2);
+        assert_eq!(metrics.decode_failed.load(Ordering::SeqCst), 1);
+        assert_eq!(metrics.role_check_failed.load(Ordering::SeqCst), 1);
+    }
+}
@@ -39,6 +39,28 @@ pub struct OidcConfig {
     pub additional_claims: HashMap<String, serde_json::Value>,
 }
 
+/// The endpoints of a realm commonly needed to implement login and logout flows, taken from its `OidcConfig`.
+/// See `KeycloakAuthInstance::endpoints`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OidcEndpoints {
+    pub authorization_endpoint: Option<String>,
+    pub token_endpoint: Option<String>,
+    pub userinfo_endpoint: Option<String>,
+    pub end_session_endpoint: Option<String>,
+}
+
+impl From<&OidcConfig> for OidcEndpoints {
+    fn from(config: &OidcConfig) -> Self {
+        Self {
+            authorization_endpoint: Some(config.standard_claims.authorization_endpoint.clone())
+                .filter(|endpoint| !endpoint.is_empty()),
+            token_endpoint: config.standard_claims.token_endpoint.clone(),
+            userinfo_endpoint: config.standard_claims.userinfo_endpoint.clone(),
+            end_session_endpoint: config.rp_initialized_claims.end_session_endpoint.clone(),
+        }
+    }
+}
+
 /// See: `https://www.rfc-editor.org/rfc/rfc8414.html#section-2`
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct OAuthDiscoveryClaims {
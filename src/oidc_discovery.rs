@@ -1,9 +1,14 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use crate::oidc::OidcConfig;
+use crate::{
+    oidc::OidcConfig,
+    refresh::{TokenErrorResponse, TokenResponse},
+};
 use reqwest::IntoUrl;
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize};
 use snafu::{ResultExt, Snafu};
+use tracing::Instrument;
+use url::Url;
 
 #[derive(Debug, Clone, Snafu)]
 pub enum RequestError {
@@ -12,40 +17,145 @@ pub enum RequestError {
 
     #[snafu(display("RequestError: Could not decode payload"))]
     Decode { source: Arc<reqwest::Error> },
+
+    #[snafu(display("RequestError: Server responded with an error status"))]
+    Status { source: Arc<reqwest::Error> },
+
+    #[cfg(feature = "reqwest-middleware")]
+    #[snafu(display("RequestError: A middleware of the HTTP client failed"))]
+    Middleware {
+        source: Arc<reqwest_middleware::Error>,
+    },
+}
+
+/// HTTP client performing the OIDC discovery requests, see `KeycloakConfig::discovery_client`.
+/// Converts from a `reqwest::Client`, or with the `reqwest-middleware` feature enabled,
+/// from a `reqwest_middleware::ClientWithMiddleware`, letting discovery requests pass your tracing, retry or metrics middleware.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum HttpClient {
+    Reqwest(reqwest::Client),
+    #[cfg(feature = "reqwest-middleware")]
+    Middleware(reqwest_middleware::ClientWithMiddleware),
+}
+
+impl HttpClient {
+    async fn get(&self, url: Url, timeout: Duration) -> Result<reqwest::Response, RequestError> {
+        match self {
+            HttpClient::Reqwest(client) => client
+                .get(url)
+                .timeout(timeout)
+                .send()
+                .await
+                .map_err(Arc::new)
+                .context(SendSnafu {}),
+            #[cfg(feature = "reqwest-middleware")]
+            HttpClient::Middleware(client) => client
+                .get(url)
+                .timeout(timeout)
+                .send()
+                .await
+                .map_err(|err| match err {
+                    reqwest_middleware::Error::Reqwest(source) => RequestError::Send {
+                        source: Arc::new(source),
+                    },
+                    err => RequestError::Middleware {
+                        source: Arc::new(err),
+                    },
+                }),
+        }
+    }
+}
+
+impl From<reqwest::Client> for HttpClient {
+    fn from(client: reqwest::Client) -> Self {
+        HttpClient::Reqwest(client)
+    }
+}
+
+#[cfg(feature = "reqwest-middleware")]
+impl From<reqwest_middleware::ClientWithMiddleware> for HttpClient {
+    fn from(client: reqwest_middleware::ClientWithMiddleware) -> Self {
+        HttpClient::Middleware(client)
+    }
+}
+
+/// Fetches the JSON document at `url` within a "keycloak_discovery_request" span (at debug level),
+/// recording the URL and response status, so that discovery requests show up in the traces of your application.
+async fn get_json<T: DeserializeOwned>(
+    client: &HttpClient,
+    url: Url,
+    timeout: Duration,
+) -> Result<T, RequestError> {
+    let span = tracing::debug_span!(
+        "keycloak_discovery_request",
+        url = %url,
+        status = tracing::field::Empty
+    );
+    async move {
+        let response = client.get(url, timeout).await?;
+        tracing::Span::current().record("status", response.status().as_u16());
+        response
+            .json::<T>()
+            .await
+            .map_err(Arc::new)
+            .context(DecodeSnafu {})
+    }
+    .instrument(span)
+    .await
+}
+
+/// Fetches the PEM encoded public key at `url`, traced like the other discovery requests.
+pub(crate) async fn retrieve_pem(
+    client: &reqwest::Client,
+    url: Url,
+    timeout: Duration,
+) -> Result<String, RequestError> {
+    let span = tracing::debug_span!(
+        "keycloak_discovery_request",
+        url = %url,
+        status = tracing::field::Empty
+    );
+    async move {
+        let response = client
+            .get(url)
+            .timeout(timeout)
+            .send()
+            .await
+            .map_err(Arc::new)
+            .context(SendSnafu {})?;
+        tracing::Span::current().record("status", response.status().as_u16());
+        response
+            .error_for_status()
+            .map_err(Arc::new)
+            .context(StatusSnafu {})?
+            .text()
+            .await
+            .map_err(Arc::new)
+            .context(DecodeSnafu {})
+    }
+    .instrument(span)
+    .await
 }
 
 pub(crate) async fn retrieve_oidc_config(
-    discovery_endpoint: impl IntoUrl,
+    client: &HttpClient,
+    discovery_endpoint: Url,
+    timeout: Duration,
 ) -> Result<OidcConfig, RequestError> {
-    reqwest::Client::new()
-        .get(discovery_endpoint)
-        .send()
-        .await
-        .map_err(Arc::new)
-        .context(SendSnafu {})?
-        .json::<OidcConfig>()
-        .await
-        .map_err(Arc::new)
-        .context(DecodeSnafu {})
+    get_json(client, discovery_endpoint, timeout).await
 }
 
 pub(crate) async fn retrieve_jwk_set(
-    jwk_set_endpoint: impl IntoUrl,
+    client: &HttpClient,
+    jwk_set_endpoint: Url,
+    timeout: Duration,
 ) -> Result<jsonwebtoken::jwk::JwkSet, RequestError> {
     #[derive(Deserialize)]
     pub struct RawJwkSet {
         pub keys: Vec<serde_json::Value>,
     }
-    let raw_set = reqwest::Client::new()
-        .get(jwk_set_endpoint)
-        .send()
-        .await
-        .map_err(Arc::new)
-        .context(SendSnafu {})?
-        .json::<RawJwkSet>()
-        .await
-        .map_err(Arc::new)
-        .context(DecodeSnafu {})?;
+    let raw_set = get_json::<RawJwkSet>(client, jwk_set_endpoint, timeout).await?;
     let mut set = jsonwebtoken::jwk::JwkSet { keys: Vec::new() };
     for key in raw_set.keys {
         match serde_json::from_value::<jsonwebtoken::jwk::Jwk>(key) {
@@ -55,3 +165,60 @@ pub(crate) async fn retrieve_jwk_set(
     }
     Ok(set)
 }
+
+/// Asks the introspection endpoint about the given token, authenticating with the client credentials (`client_secret_basic`).
+pub(crate) async fn introspect_token(
+    client: &reqwest::Client,
+    introspection_endpoint: impl IntoUrl,
+    client_id: &str,
+    client_secret: &str,
+    token: &str,
+    timeout: Duration,
+) -> Result<HashMap<String, serde_json::Value>, RequestError> {
+    client
+        .post(introspection_endpoint)
+        .basic_auth(client_id, Some(client_secret))
+        .form(&[("token", token)])
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(Arc::new)
+        .context(SendSnafu {})?
+        .error_for_status()
+        .map_err(Arc::new)
+        .context(StatusSnafu {})?
+        .json::<HashMap<String, serde_json::Value>>()
+        .await
+        .map_err(Arc::new)
+        .context(DecodeSnafu {})
+}
+
+/// Requests a token from the token endpoint, authenticating as the given client.
+/// Confidential clients authenticate with `client_secret_basic`, public clients only send their `client_id`.
+/// Error responses of the endpoint are returned as `Ok(Err(_))`.
+pub(crate) async fn request_token(
+    client: &reqwest::Client,
+    token_endpoint: impl IntoUrl,
+    client_id: &str,
+    client_secret: Option<&str>,
+    params: &[(&str, &str)],
+) -> Result<Result<TokenResponse, TokenErrorResponse>, RequestError> {
+    let request = client.post(token_endpoint);
+    let request = match client_secret {
+        Some(client_secret) => request
+            .basic_auth(client_id, Some(client_secret))
+            .form(params),
+        None => request.form(&[params, &[("client_id", client_id)]].concat()),
+    };
+    let response = request
+        .send()
+        .await
+        .map_err(Arc::new)
+        .context(SendSnafu {})?;
+    match response.status().is_success() {
+        true => response.json::<TokenResponse>().await.map(Ok),
+        false => response.json::<TokenErrorResponse>().await.map(Err),
+    }
+    .map_err(Arc::new)
+    .context(DecodeSnafu {})
+}
@@ -0,0 +1,248 @@
+use educe::Educe;
+use serde::Deserialize;
+use snafu::ResultExt;
+use typed_builder::TypedBuilder;
+use url::Url;
+
+use crate::{
+    error::{AuthError, TokenRequestSnafu},
+    oidc_discovery,
+};
+
+/// Credentials of the client tokens are requested for, see `KeycloakAuthInstance::refresh`.
+#[derive(Clone, TypedBuilder, Educe)]
+#[educe(Debug)]
+pub struct ClientCredentials {
+    /// ID of the client the refresh tokens were issued to.
+    #[builder(setter(into))]
+    pub client_id: String,
+
+    /// Secret of the client. Leave this empty for public clients.
+    #[builder(default = None, setter(strip_option, into))]
+    #[educe(Debug(ignore))]
+    pub client_secret: Option<String>,
+}
+
+/// Successful response of the token endpoint (RFC 6749, section 5.1), including the Keycloak specific `refresh_expires_in`.
+/// The tokens are omitted from its `Debug` output, so that they do not end up in logs.
+#[derive(Clone, PartialEq, Eq, Deserialize, Educe)]
+#[educe(Debug)]
+pub struct TokenResponse {
+    /// The new access token.
+    #[educe(Debug(ignore))]
+    pub access_token: String,
+    /// Type of the access token, "Bearer" for Keycloak.
+    pub token_type: String,
+    /// Lifetime of the access token in seconds.
+    pub expires_in: Option<u64>,
+    /// The new refresh token. Keycloak rotates refresh tokens, always use the latest one.
+    #[educe(Debug(ignore))]
+    pub refresh_token: Option<String>,
+    /// Keycloak: Lifetime of the refresh token in seconds. `0` for offline tokens.
+    pub refresh_expires_in: Option<u64>,
+    /// The new ID token, if the 'openid' scope was requested.
+    #[educe(Debug(ignore))]
+    pub id_token: Option<String>,
+    /// Space-delimited scopes granted to the access token.
+    pub scope: Option<String>,
+}
+
+/// Error response of the token endpoint (RFC 6749, section 5.2).
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct TokenErrorResponse {
+    pub(crate) error: String,
+    pub(crate) error_description: Option<String>,
+}
+
+impl From<TokenErrorResponse> for AuthError {
+    fn from(response: TokenErrorResponse) -> Self {
+        match response.error.as_str() {
+            "invalid_grant" => AuthError::InvalidGrant {
+                description: response.error_description,
+            },
+            _ => AuthError::TokenEndpoint {
+                error: response.error,
+                description: response.error_description,
+            },
+        }
+    }
+}
+
+/// The token endpoint of the realm, as derived from the `server` and `realm` configured.
+/// Used when the endpoint is not known through OIDC discovery.
+pub(crate) fn derived_token_endpoint(server: &Url, realm: &str) -> Url {
+    let mut endpoint = server.clone();
+    endpoint
+        .path_segments_mut()
+        .expect("URL not to be a 'cannot-be-a-base' URL. We have to append segments.")
+        .pop_if_empty()
+        .extend(&["realms", realm, "protocol", "openid-connect", "token"]);
+    endpoint
+}
+
+/// Requests tokens with the given grant parameters, mapping error responses onto `AuthError`s.
+pub(crate) async fn request_token(
+    client: &reqwest::Client,
+    token_endpoint: Url,
+    credentials: &ClientCredentials,
+    params: &[(&str, &str)],
+) -> Result<TokenResponse, AuthError> {
+    tracing::debug!("Requesting token.");
+    oidc_discovery::request_token(
+        client,
+        token_endpoint,
+        &credentials.client_id,
+        credentials.client_secret.as_deref(),
+        params,
+    )
+    .await
+    .context(TokenRequestSnafu {})?
+    .map_err(AuthError::from)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use axum::response::IntoResponse;
+    use jsonwebtoken::Algorithm;
+    use serde_json::json;
+    use url::Url;
+
+    use crate::{
+        error::AuthError,
+        instance::{KeycloakAuthInstance, KeycloakConfig},
+    };
+
+    use super::ClientCredentials;
+
+    /// Serves a token endpoint accepting only the refresh token "valid" and the user "jdoe" (password "pass")
+    /// of client "my-client" (secret "my-secret").
+    async fn serve_token_endpoint() -> Url {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let router = axum::Router::new().route(
+            "/realms/MyRealm/protocol/openid-connect/token",
+            axum::routing::post(
+                |headers: axum::http::HeaderMap,
+                 axum::Form(form): axum::Form<HashMap<String, String>>| async move {
+                    // "my-client:my-secret"
+                    if headers
+                        .get("authorization")
+                        .map_or(true, |it| it != "Basic bXktY2xpZW50Om15LXNlY3JldA==")
+                    {
+                        return (
+                            axum::http::StatusCode::UNAUTHORIZED,
+                            axum::Json(json!({ "error": "unauthorized_client" })),
+                        )
+                            .into_response();
+                    }
+                    let field = |name: &str| form.get(name).map(String::as_str);
+                    let granted = match field("grant_type") {
+                        Some("refresh_token") => field("refresh_token") == Some("valid"),
+                        Some("password") => {
+                            field("username") == Some("jdoe") && field("password") == Some("pass")
+                        }
+                        _ => false,
+                    };
+                    match granted {
+                        true => axum::Json(json!({
+                            "access_token": "new-access",
+                            "token_type": "Bearer",
+                            "expires_in": 300,
+                            "refresh_token": "new-refresh",
+                            "refresh_expires_in": 1800,
+                            "scope": "openid email",
+                        }))
+                        .into_response(),
+                        false => (
+                            axum::http::StatusCode::BAD_REQUEST,
+                            axum::Json(json!({
+                                "error": "invalid_grant",
+                                "error_description": "Token is not active",
+                            })),
+                        )
+                            .into_response(),
+                    }
+                },
+            ),
+        );
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        url
+    }
+
+    fn instance(url: Url, client_secret: &str) -> KeycloakAuthInstance {
+        KeycloakAuthInstance::from_secret(
+            KeycloakConfig::builder()
+                .server(url)
+                .realm(String::from("MyRealm"))
+                .client_credentials(
+                    ClientCredentials::builder()
+                        .client_id("my-client")
+                        .client_secret(client_secret)
+                        .build(),
+                )
+                .build(),
+            b"secret",
+            Algorithm::HS256,
+        )
+    }
+
+    #[tokio::test]
+    async fn refreshes_tokens() {
+        let url = serve_token_endpoint().await;
+        let response = instance(url, "my-secret").refresh("valid").await.unwrap();
+        assert_eq!(response.access_token, "new-access");
+        assert_eq!(response.refresh_token.as_deref(), Some("new-refresh"));
+        assert_eq!(response.expires_in, Some(300));
+        assert_eq!(response.refresh_expires_in, Some(1800));
+        assert!(!format!("{response:?}").contains("new-"));
+    }
+
+    #[tokio::test]
+    async fn reports_invalid_grants() {
+        let url = serve_token_endpoint().await;
+        match instance(url.clone(), "my-secret").refresh("expired").await {
+            Err(AuthError::InvalidGrant { description }) => {
+                assert_eq!(description.as_deref(), Some("Token is not active"))
+            }
+            other => panic!("Unexpected result: {other:?}"),
+        }
+        assert!(matches!(
+            instance(url, "wrong-secret").refresh("valid").await,
+            Err(AuthError::TokenEndpoint {
+                error: _,
+                description: _
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn requires_client_credentials() {
+        let instance = KeycloakAuthInstance::from_secret(
+            KeycloakConfig::builder()
+                .server(Url::parse("https://localhost:8443/").unwrap())
+                .realm(String::from("MyRealm"))
+                .build(),
+            b"secret",
+            Algorithm::HS256,
+        );
+        assert!(matches!(
+            instance.refresh("valid").await,
+            Err(AuthError::InvalidConfiguration { reason: _ })
+        ));
+    }
+
+    #[cfg(feature = "password-grant")]
+    #[tokio::test]
+    async fn requests_tokens_with_user_credentials() {
+        let url = serve_token_endpoint().await;
+        let instance = instance(url, "my-secret");
+        let response = instance.password_grant("jdoe", "pass").await.unwrap();
+        assert_eq!(response.access_token, "new-access");
+        assert!(matches!(
+            instance.password_grant("jdoe", "wrong").await,
+            Err(AuthError::InvalidGrant { description: _ })
+        ));
+    }
+}
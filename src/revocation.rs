@@ -0,0 +1,49 @@
+/// Decides whether a token was revoked, based on its JWT ID (the 'jti' claim).
+///
+/// Configure an implementation as the `jti_blocklist` of the `KeycloakAuthLayer` to reject revoked tokens
+/// with `AuthError::TokenRevoked`, e.g. after a logout reported through a Keycloak event listener.
+/// It is consulted for every token that passed all other validations, so lookups should be fast (an in-memory set or a Redis lookup).
+/// Entries only need to be retained until the revoked token expires.
+#[axum::async_trait]
+pub trait JtiBlocklist: Send + Sync {
+    async fn is_revoked(&self, jti: &str) -> bool;
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::{error::AuthError, layer::KeycloakAuthLayer, testing::test::secret_builder};
+
+    use super::JtiBlocklist;
+
+    struct Revoked(HashSet<String>);
+
+    #[axum::async_trait]
+    impl JtiBlocklist for Revoked {
+        async fn is_revoked(&self, jti: &str) -> bool {
+            self.0.contains(jti)
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_revoked_tokens() {
+        let builder = secret_builder();
+        let layer = KeycloakAuthLayer::<String>::builder()
+            .instance(builder.instance().unwrap())
+            .expected_audiences(vec![String::from("account")])
+            .jti_blocklist(std::sync::Arc::new(Revoked(HashSet::from([String::from(
+                "revoked",
+            )]))))
+            .build();
+
+        let active = builder.clone().jwt_id("active").build().unwrap();
+        assert!(layer.validate_raw_token(&active).await.is_ok());
+
+        let revoked = builder.jwt_id("revoked").build().unwrap();
+        assert!(matches!(
+            layer.validate_raw_token(&revoked).await,
+            Err(AuthError::TokenRevoked)
+        ));
+    }
+}
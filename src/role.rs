@@ -1,18 +1,82 @@
-use std::fmt::{Debug, Display};
+use std::{
+    fmt::{Debug, Display},
+    sync::Arc,
+};
 
 use axum::response::IntoResponse;
 use serde::{Deserialize, Serialize};
 
 /// Describes any type that can act as a role.
-pub trait Role: Debug + Display + Clone + PartialEq + Eq + Send + Sync + From<String> {}
+///
+/// Roles are read from the token as `String`s and converted using the types `TryFrom<String>` impl.
+/// Types implementing `From<String>` (like `String` itself) get this impl for free and accept every role.
+/// A closed enum may implement `TryFrom<String>` directly, failing for role names it does not know.
+/// These unknown roles are dropped or collected separately, as configured through `UnknownRoles`.
+pub trait Role: Debug + Display + Clone + PartialEq + Eq + Send + Sync + TryFrom<String> {}
 
 /// Roles are read from JSON and are therefore always present as `String`s.
 /// Using `String` as the `Role` should be the default when not providing a custom `Role` type.
 impl Role for String {}
 
-/// A realm or client role.
+/// Determines how roles are compared when checking for their presence.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+pub enum RoleMatching {
+    /// Roles must be equal (using their `PartialEq` implementation).
+    #[default]
+    Exact,
+
+    /// Roles are compared using the ASCII case-insensitive equality of their `Display` representations.
+    ///
+    /// Note: This renders both roles to `String`s for every comparison performed.
+    /// Each check is performed against each role of the token, so this allocates `O(#expected * #present)` strings.
+    /// Prefer `Exact` matching when dealing with large role sets and perform normalization in your `TryFrom<String>` (or `From<String>`) impl instead.
+    CaseInsensitive,
+}
+
+impl RoleMatching {
+    pub fn matches<R: Role>(&self, a: &R, b: &R) -> bool {
+        match self {
+            RoleMatching::Exact => a == b,
+            RoleMatching::CaseInsensitive => a.to_string().eq_ignore_ascii_case(&b.to_string()),
+        }
+    }
+}
+
+/// Determines how a set of required roles must be satisfied.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RequiredRolesMode {
+    /// Every required role must be present.
+    #[default]
+    All,
+
+    /// At least one of the required roles must be present.
+    Any,
+}
+
+/// Determines what happens to roles of a token which could not be converted into the `Role` type in use.
+/// Only relevant for `Role` types with a fallible `TryFrom<String>` impl.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownRoles {
+    /// Unknown roles are silently dropped.
+    #[default]
+    Ignore,
+
+    /// Unknown roles are kept (by their raw name) in `KeycloakToken::unknown_roles`.
+    /// Note: This clones every role name once before attempting its conversion.
+    Collect,
+}
+
+/// Derives further roles from a role of a token, e.g. to model permission hierarchies
+/// ("administrator" implies "editor" and "viewer"). See `KeycloakAuthLayer::role_expansion`.
+pub type RoleExpansion<R> = Arc<dyn Fn(&KeycloakRole<R>) -> Vec<R> + Send + Sync>;
+
+/// A realm or client role, or a scope treated as a role.
+///
+/// New variants may be added in minor releases, so matches must include a wildcard arm.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type")]
+#[non_exhaustive]
 pub enum KeycloakRole<R: Role> {
     /// A realm role
     Realm {
@@ -26,18 +90,180 @@ pub enum KeycloakRole<R: Role> {
         /// Name of the role
         role: R,
     },
+    /// A scope granted to the token, treated as a role (see `KeycloakAuthLayer::roles_from_scopes`),
+    /// or a scope of a UMA permission on a resource (see `KeycloakAuthLayer::uma_permissions`).
+    Scope {
+        /// The resource the scope is granted on. `None` for scopes of the 'scope' claim.
+        resource: Option<String>,
+        /// Name of the scope
+        scope: R,
+    },
 }
 
 impl<R: Role> KeycloakRole<R> {
+    /// The name of the role, or of the scope for `KeycloakRole::Scope`.
     pub fn role(&self) -> &R {
         match self {
             KeycloakRole::Realm { role } => role,
             KeycloakRole::Client { client: _, role } => role,
+            KeycloakRole::Scope { resource: _, scope } => scope,
+        }
+    }
+
+    /// Returns true for scopes of UMA permissions, which are only granted on their resource.
+    /// These never match a role by its name alone, see `KeycloakToken::has_role`.
+    pub(crate) fn is_resource_scoped(&self) -> bool {
+        matches!(
+            self,
+            KeycloakRole::Scope {
+                resource: Some(_),
+                scope: _
+            }
+        )
+    }
+
+    /// A role of the given name, in the same scope (realm, client or resource) as this one.
+    pub(crate) fn in_same_scope(&self, role: R) -> Self {
+        match self {
+            KeycloakRole::Realm { role: _ } => KeycloakRole::Realm { role },
+            KeycloakRole::Client { client, role: _ } => KeycloakRole::Client {
+                client: client.clone(),
+                role,
+            },
+            KeycloakRole::Scope { resource, scope: _ } => KeycloakRole::Scope {
+                resource: resource.clone(),
+                scope: role,
+            },
+        }
+    }
+
+    fn scoped(client: Option<String>, role: R) -> Self {
+        match client {
+            Some(client) => KeycloakRole::Client { client, role },
+            None => KeycloakRole::Realm { role },
         }
     }
 }
 
+/// Renders the role along with its scope, e.g. "realm:admin", "client:billing:manage" or "scope:invoices.read".
+impl<R: Role> Display for KeycloakRole<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeycloakRole::Realm { role } => write!(f, "realm:{role}"),
+            KeycloakRole::Client { client, role } => write!(f, "client:{client}:{role}"),
+            KeycloakRole::Scope {
+                resource: None,
+                scope,
+            } => write!(f, "scope:{scope}"),
+            KeycloakRole::Scope {
+                resource: Some(resource),
+                scope,
+            } => write!(f, "scope:{resource}:{scope}"),
+        }
+    }
+}
+
+/// An additional claim to read roles from, for tokens shaped by custom mappers. See `KeycloakConfig::role_claims`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoleClaim {
+    /// JSON pointer (RFC 6901) to the claim, e.g. "/roles" for a top-level claim or "/app/permissions" for a nested one.
+    pub pointer: String,
+    /// The client the roles are assigned to. `None` to treat them as realm roles.
+    pub client: Option<String>,
+}
+
+impl RoleClaim {
+    /// Reads realm roles from the claim at the given JSON pointer.
+    pub fn realm(pointer: impl Into<String>) -> Self {
+        Self {
+            pointer: pointer.into(),
+            client: None,
+        }
+    }
+
+    /// Reads roles of the given client from the claim at the given JSON pointer.
+    pub fn client(pointer: impl Into<String>, client: impl Into<String>) -> Self {
+        Self {
+            pointer: pointer.into(),
+            client: Some(client.into()),
+        }
+    }
+
+    /// Reads the role names from the claims. The claim may hold an array of role names or a single one.
+    /// An absent claim holds no roles. Values of any other type, in the claim or its array, are skipped.
+    pub(crate) fn read(&self, claims: &serde_json::Value) -> Vec<String> {
+        match claims.pointer(&self.pointer) {
+            Some(serde_json::Value::Array(roles)) => roles
+                .iter()
+                .filter_map(|role| role.as_str().map(str::to_owned))
+                .collect(),
+            Some(serde_json::Value::String(role)) => vec![role.clone()],
+            Some(other) => {
+                tracing::debug!(pointer = self.pointer, claim = ?other, "Ignoring role claim of unexpected type");
+                Vec::new()
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Converts the raw role name `raw` (of the given client, or the realm if `None`) and pushes it to `target`.
+/// Roles not convertible to `R` are pushed to `unknown` if present, or dropped otherwise.
+/// Roles already present in the same scope are not pushed again.
+pub(crate) fn push_role<R: Role>(
+    target: &mut Vec<KeycloakRole<R>>,
+    unknown: Option<&mut Vec<KeycloakRole<String>>>,
+    client: Option<String>,
+    raw: String,
+) {
+    match unknown {
+        None => {
+            if let Ok(role) = R::try_from(raw) {
+                push_unique(target, KeycloakRole::scoped(client, role));
+            }
+        }
+        Some(unknown) => match R::try_from(raw.clone()) {
+            Ok(role) => push_unique(target, KeycloakRole::scoped(client, role)),
+            Err(_) => push_unique(unknown, KeycloakRole::scoped(client, raw)),
+        },
+    }
+}
+
+/// Converts the raw scope name `raw` of a UMA permission on `resource` and pushes it to `target` as a `KeycloakRole::Scope`.
+/// Handles unconvertible and repeated scopes just like `push_role`.
+pub(crate) fn push_permission<R: Role>(
+    target: &mut Vec<KeycloakRole<R>>,
+    unknown: Option<&mut Vec<KeycloakRole<String>>>,
+    resource: Option<String>,
+    raw: String,
+) {
+    match unknown {
+        None => {
+            if let Ok(scope) = R::try_from(raw) {
+                push_unique(target, KeycloakRole::Scope { resource, scope });
+            }
+        }
+        Some(unknown) => match R::try_from(raw.clone()) {
+            Ok(scope) => push_unique(target, KeycloakRole::Scope { resource, scope }),
+            Err(_) => push_unique(
+                unknown,
+                KeycloakRole::Scope {
+                    resource,
+                    scope: raw,
+                },
+            ),
+        },
+    }
+}
+
+fn push_unique<R: Role>(target: &mut Vec<KeycloakRole<R>>, role: KeycloakRole<R>) {
+    if !target.contains(&role) {
+        target.push(role);
+    }
+}
+
 pub trait NumRoles {
+    /// The number of distinct roles, not counting a role repeated in the same scope.
     fn num_roles(&self) -> usize;
 }
 
@@ -48,7 +274,22 @@ impl<T: NumRoles> NumRoles for Option<T> {
 }
 
 pub trait ExtractRoles<R: Role> {
+    /// Pushes all roles convertible to `R` to `target`. All others are dropped.
     fn extract_roles(self, target: &mut Vec<KeycloakRole<R>>);
+
+    /// Like `extract_roles`, but pushes all roles not convertible to `R` to `unknown` if present.
+    ///
+    /// By default, unconvertible roles are dropped regardless. Override this to collect them (see `UnknownRoles::Collect`).
+    fn extract_roles_into(
+        self,
+        target: &mut Vec<KeycloakRole<R>>,
+        unknown: Option<&mut Vec<KeycloakRole<String>>>,
+    ) where
+        Self: Sized,
+    {
+        let _ = unknown;
+        self.extract_roles(target);
+    }
 }
 
 /// If type `T` implements `ExtractRoles`, `ExtractRoles` should also be implemented for `Option<T>`,
@@ -59,6 +300,16 @@ impl<R: Role, T: ExtractRoles<R>> ExtractRoles<R> for Option<T> {
             inner.extract_roles(target)
         }
     }
+
+    fn extract_roles_into(
+        self,
+        target: &mut Vec<KeycloakRole<R>>,
+        unknown: Option<&mut Vec<KeycloakRole<String>>>,
+    ) {
+        if let Some(inner) = self {
+            inner.extract_roles_into(target, unknown)
+        }
+    }
 }
 
 /// If two type `A` and `B` implement `ExtractRoles` (with the impl above this might as well be an `Option<T>`),
@@ -73,13 +324,77 @@ where
         self.0.extract_roles(target);
         self.1.extract_roles(target);
     }
+
+    fn extract_roles_into(
+        self,
+        target: &mut Vec<KeycloakRole<R>>,
+        mut unknown: Option<&mut Vec<KeycloakRole<String>>>,
+    ) {
+        target.reserve(self.0.num_roles() + self.1.num_roles());
+        self.0.extract_roles_into(target, unknown.as_deref_mut());
+        self.1.extract_roles_into(target, unknown);
+    }
 }
 
 pub trait ExpectRoles<R: Role> {
     type Rejection: IntoResponse;
 
+    /// Succeeds if all of the given roles are present. Reports every missing role, not just the first one.
     fn expect_roles<I: Into<R> + Clone>(&self, roles: &[I]) -> Result<(), Self::Rejection>;
+
+    /// Succeeds if at least one of the given roles is present. An empty `roles` slice is always satisfied.
+    ///
+    /// By default, each role is checked using `expect_roles`, reporting the rejection of the last role if none is present.
+    fn expect_any_role<I: Into<R> + Clone>(&self, roles: &[I]) -> Result<(), Self::Rejection> {
+        let mut rejection = None;
+        for role in roles {
+            match self.expect_roles(std::slice::from_ref(role)) {
+                Ok(()) => return Ok(()),
+                Err(err) => rejection = Some(err),
+            }
+        }
+        rejection.map_or(Ok(()), Err)
+    }
+
+    /// Succeeds if the given role is present in the given scope, distinguishing realm roles and the roles of each client.
+    ///
+    /// By default, only the role itself is checked using `expect_roles`, as the scope of a role is not known to this trait.
+    /// Override this if your implementation can tell the scopes of its roles apart.
+    fn expect_scoped_role(&self, role: &KeycloakRole<R>) -> Result<(), Self::Rejection> {
+        self.expect_roles(std::slice::from_ref(role.role()))
+    }
+
+    /// Succeeds if the given role is present for the given client.
+    fn expect_client_role<I: Into<R>>(&self, client: &str, role: I) -> Result<(), Self::Rejection> {
+        self.expect_scoped_role(&KeycloakRole::Client {
+            client: client.to_owned(),
+            role: role.into(),
+        })
+    }
+
+    /// Fails if any of the given roles is present. Succeeds only if none of them is present.
     fn not_expect_roles<I: Into<R> + Clone>(&self, roles: &[I]) -> Result<(), Self::Rejection>;
+
+    /// Same as `not_expect_roles`, named for its intent: Fails if any (at least one) of the given roles is present.
+    fn not_expect_any_role<I: Into<R> + Clone>(&self, roles: &[I]) -> Result<(), Self::Rejection> {
+        self.not_expect_roles(roles)
+    }
+
+    /// Fails only if all of the given roles are present. Succeeds if at least one of them is missing.
+    /// Use this for separation-of-duties checks, e.g. rejecting users being both "requester" and "approver".
+    /// Unlike `not_expect_roles`, holding just some of the roles is fine. An empty `roles` slice is always satisfied.
+    ///
+    /// By default, each role is checked using `not_expect_roles`, reporting the rejection of the last role if all are present.
+    fn not_expect_all_roles<I: Into<R> + Clone>(&self, roles: &[I]) -> Result<(), Self::Rejection> {
+        let mut rejection = None;
+        for role in roles {
+            match self.not_expect_roles(std::slice::from_ref(role)) {
+                Ok(()) => return Ok(()),
+                Err(err) => rejection = Some(err),
+            }
+        }
+        rejection.map_or(Ok(()), Err)
+    }
 }
 
 #[macro_export]
@@ -100,6 +415,15 @@ macro_rules! expect_role {
     };
 }
 
+#[macro_export]
+macro_rules! expect_any_role {
+    ($token: expr, $roles: expr) => {
+        if let Err(err) = axum_keycloak_auth::role::ExpectRoles::expect_any_role($token, $roles) {
+            return axum::response::IntoResponse::into_response(err);
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! not_expect_roles {
     ($token: expr, $roles: expr) => {
@@ -109,6 +433,17 @@ macro_rules! not_expect_roles {
     };
 }
 
+#[macro_export]
+macro_rules! not_expect_all_roles {
+    ($token: expr, $roles: expr) => {
+        if let Err(err) =
+            axum_keycloak_auth::role::ExpectRoles::not_expect_all_roles($token, $roles)
+        {
+            return axum::response::IntoResponse::into_response(err);
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! not_expect_role {
     ($token: expr, $role: expr) => {
@@ -118,3 +453,128 @@ macro_rules! not_expect_role {
         }
     };
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::decode::{Access, RealmAccess, ResourceAccess};
+
+    use super::{ExpectRoles, ExtractRoles, KeycloakRole, NumRoles};
+
+    /// Implements only the required methods of `ExpectRoles`, like implementations predating the provided ones.
+    struct Roles(Vec<String>);
+
+    impl ExpectRoles<String> for Roles {
+        type Rejection = String;
+
+        fn expect_roles<I: Into<String> + Clone>(&self, roles: &[I]) -> Result<(), String> {
+            match roles
+                .iter()
+                .find(|role| !self.0.contains(&(*role).clone().into()))
+            {
+                Some(missing) => Err(missing.clone().into()),
+                None => Ok(()),
+            }
+        }
+
+        fn not_expect_roles<I: Into<String> + Clone>(&self, roles: &[I]) -> Result<(), String> {
+            match roles
+                .iter()
+                .find(|role| self.0.contains(&(*role).clone().into()))
+            {
+                Some(unexpected) => Err(unexpected.clone().into()),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn provides_role_checks_based_on_the_required_ones() {
+        let roles = Roles(vec![String::from("editor"), String::from("requester")]);
+        assert!(roles.expect_any_role(&["admin", "editor"]).is_ok());
+        assert_eq!(
+            roles.expect_any_role(&["admin", "viewer"]),
+            Err(String::from("viewer"))
+        );
+        assert!(roles.expect_any_role::<&str>(&[]).is_ok());
+        assert!(roles
+            .expect_scoped_role(&KeycloakRole::Realm {
+                role: String::from("editor")
+            })
+            .is_ok());
+        assert!(roles.expect_client_role("billing", "admin").is_err());
+        assert!(roles
+            .not_expect_all_roles(&["requester", "approver"])
+            .is_ok());
+        assert_eq!(
+            roles.not_expect_all_roles(&["requester", "editor"]),
+            Err(String::from("editor"))
+        );
+    }
+
+    #[test]
+    fn displays_role_with_its_scope() {
+        let realm = KeycloakRole::Realm {
+            role: String::from("admin"),
+        };
+        let client = KeycloakRole::Client {
+            client: String::from("billing"),
+            role: String::from("manage"),
+        };
+        assert_eq!(realm.to_string(), "realm:admin");
+        assert_eq!(client.to_string(), "client:billing:manage");
+        assert_eq!(client.role(), "manage");
+    }
+
+    #[test]
+    fn deduplicates_roles_within_their_scope() {
+        let access = |roles: &[&str]| Access {
+            roles: roles.iter().map(|role| String::from(*role)).collect(),
+        };
+        let realm_access = RealmAccess(access(&["admin", "user", "admin"]));
+        let resource_access = ResourceAccess(HashMap::from([
+            (String::from("billing"), access(&["admin", "admin"])),
+            (String::from("account"), access(&["admin"])),
+        ]));
+        assert_eq!(realm_access.num_roles(), 2);
+        assert_eq!(resource_access.num_roles(), 2);
+
+        let mut roles = Vec::<KeycloakRole<String>>::new();
+        (Some(realm_access), Some(resource_access)).extract_roles(&mut roles);
+        assert_eq!(roles.len(), 4);
+        let count = |role: KeycloakRole<String>| roles.iter().filter(|it| **it == role).count();
+        assert_eq!(
+            count(KeycloakRole::Realm {
+                role: String::from("admin")
+            }),
+            1
+        );
+        assert_eq!(
+            count(KeycloakRole::Client {
+                client: String::from("billing"),
+                role: String::from("admin")
+            }),
+            1
+        );
+    }
+
+    /// Implements only `extract_roles`, like implementations predating `extract_roles_into`.
+    struct Extracted(Vec<String>);
+
+    impl ExtractRoles<String> for Extracted {
+        fn extract_roles(self, target: &mut Vec<KeycloakRole<String>>) {
+            target.extend(self.0.into_iter().map(|role| KeycloakRole::Realm { role }));
+        }
+    }
+
+    #[test]
+    fn extracts_roles_of_implementations_predating_extract_roles_into() {
+        let mut roles = Vec::new();
+        let mut unknown = Vec::new();
+        Some(Extracted(vec![String::from("admin"), String::from("user")]))
+            .extract_roles_into(&mut roles, Some(&mut unknown));
+        assert_eq!(roles.len(), 2);
+        assert!(unknown.is_empty());
+    }
+}
@@ -3,12 +3,15 @@ use std::{
     task::{Context, Poll},
 };
 
-use axum::{body::Body, response::IntoResponse};
+use axum::body::Body;
 use futures::future::BoxFuture;
 use http::Request;
 use serde::de::DeserializeOwned;
 
-use crate::{extract, layer::KeycloakAuthLayer, role::Role, KeycloakAuthStatus, PassthroughMode};
+use crate::{
+    decode::RawJwt, extract, layer::KeycloakAuthLayer, role::Role, ClaimExtension,
+    KeycloakAuthStatus, PassthroughMode, SkipAudienceValidation, Subject, TenantId, Username,
+};
 
 #[derive(Clone)]
 pub struct KeycloakAuthService<S, R, Extra>
@@ -46,7 +49,7 @@ where
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        match (self.layer.instance.is_ready(), self.inner.poll_ready(cx)) {
+        match (self.layer.is_ready(), self.inner.poll_ready(cx)) {
             (true, Poll::Ready(t)) => Poll::Ready(t),
             (false, _) => Poll::Pending,
             (_, Poll::Pending) => Poll::Pending,
@@ -55,7 +58,14 @@ where
 
     fn call(&mut self, mut request: Request<Body>) -> Self::Future {
         let clone = self.inner.clone();
-        let cloned_layer = self.layer.clone();
+        let mut cloned_layer = self.layer.clone();
+        if request
+            .extensions()
+            .get::<SkipAudienceValidation>()
+            .is_some()
+        {
+            cloned_layer.expected_audiences = Vec::new();
+        }
 
         // Take the service that was ready!
         let mut inner = std::mem::replace(&mut self.inner, clone);
@@ -63,7 +73,7 @@ where
         let passthrough_mode = cloned_layer.passthrough_mode;
 
         Box::pin(async move {
-            let prev_keycloak_status = request.extensions().get::<KeycloakAuthStatus::<R, Extra>>();
+            let prev_keycloak_status = request.extensions().get::<KeycloakAuthStatus<R, Extra>>();
 
             if let Some(status) = prev_keycloak_status {
                 match status {
@@ -71,26 +81,81 @@ where
                         //skip current layer check if previous was successful
                         return inner.call(request).await;
                     }
-                    KeycloakAuthStatus::Failure(_) => {
-                    }
+                    KeycloakAuthStatus::Failure(_) => {}
                 }
             }
 
+            // Named in the `WWW-Authenticate` header of rejections. Tokens are rejected in the name of the realm they claim to be issued by.
+            let mut realm = cloned_layer.instance.config.realm.as_str();
+
             // Process the request.
             let result = {
                 let extracted_token =
                     extract::extract_jwt(&request, &cloned_layer.token_extractors);
                 match extracted_token {
-                    Ok(extracted_token) => cloned_layer.validate_raw_token(&extracted_token).await,
+                    Ok(extracted_token) => {
+                        let instance = cloned_layer.select_instance(&extracted_token);
+                        if let Ok(instance) = &instance {
+                            realm = instance.config.realm.as_str();
+                        }
+                        cloned_layer
+                            .validate_for(instance, &extracted_token)
+                            .await
+                            .map(|validated| {
+                                let raw_token = cloned_layer
+                                    .persist_raw_token
+                                    .then(|| RawJwt::new(extracted_token.into_owned()));
+                                (validated, raw_token)
+                            })
+                    }
                     Err(err) => Err(err),
                 }
             };
 
+            // Authorization is the final gate, consulted only for otherwise valid tokens.
+            let result = match (result, &cloned_layer.authorizer) {
+                (Ok((validated, raw_token)), Some(authorizer)) => {
+                    let (parts, body) = request.into_parts();
+                    let authorized = authorizer.authorize(&validated.token, &parts).await;
+                    request = Request::from_parts(parts, body);
+                    authorized.map(|()| (validated, raw_token))
+                }
+                (result, _) => result,
+            };
+
             match result {
-                Ok((raw_claims, keycloak_token)) => {
-                    if let Some(raw_claims) = raw_claims {
+                Ok((validated, raw_token)) => {
+                    let keycloak_token = validated.token;
+                    if let Some(raw_claims) = validated.raw_claims {
                         request.extensions_mut().insert(raw_claims);
                     }
+                    if let Some(raw_token) = raw_token {
+                        request.extensions_mut().insert(raw_token);
+                    }
+                    if let (Some(tenant_id), true) = (
+                        &keycloak_token.tenant_id,
+                        cloned_layer
+                            .claim_extensions
+                            .contains(&ClaimExtension::TenantId),
+                    ) {
+                        request.extensions_mut().insert(TenantId(tenant_id.clone()));
+                    }
+                    if cloned_layer
+                        .claim_extensions
+                        .contains(&ClaimExtension::Subject)
+                    {
+                        request
+                            .extensions_mut()
+                            .insert(Subject(keycloak_token.subject.clone()));
+                    }
+                    if let Some(username) = cloned_layer
+                        .persist_username
+                        .and_then(|username| username(&keycloak_token.extra))
+                    {
+                        request
+                            .extensions_mut()
+                            .insert(Username(username.to_owned()));
+                    }
                     match cloned_layer.passthrough_mode {
                         PassthroughMode::Block => {
                             request.extensions_mut().insert(keycloak_token);
@@ -104,7 +169,12 @@ where
                     inner.call(request).await
                 }
                 Err(err) => match passthrough_mode {
-                    PassthroughMode::Block => Ok(err.into_response()),
+                    PassthroughMode::Block => Ok(match &cloned_layer.error_response {
+                        Some(error_response) => {
+                            err.into_custom_response_for_realm(realm, error_response)
+                        }
+                        None => err.into_response_for_realm(realm),
+                    }),
                     PassthroughMode::Pass => {
                         request
                             .extensions_mut()
@@ -0,0 +1,601 @@
+//! Helpers for testing handlers protected by a `KeycloakAuthLayer`, without requiring a running Keycloak server.
+//!
+//! Enable the `testing` feature to use this module, preferably only as part of your `dev-dependencies`.
+//!
+//! ```rust,no_run
+//! use axum_keycloak_auth::{Url, instance::KeycloakConfig, testing::{TestKey, TestTokenBuilder}};
+//!
+//! let builder = TestTokenBuilder::new(
+//!     TestKey::rsa(
+//!         &std::fs::read("tests/keys/private.pem").unwrap(),
+//!         &std::fs::read("tests/keys/public.pem").unwrap(),
+//!     ),
+//!     KeycloakConfig::builder()
+//!         .server(Url::parse("https://localhost:8443/").unwrap())
+//!         .realm(String::from("MyRealm"))
+//!         .build(),
+//! );
+//! // Never contacts a Keycloak server. Use it to build your `KeycloakAuthLayer`.
+//! let instance = builder.instance().unwrap();
+//! let token = builder
+//!     .clone()
+//!     .realm_role("administrator")
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! To unit test authorization logic without any JWT at all, build a `KeycloakToken` in memory:
+//!
+//! ```rust
+//! use axum_keycloak_auth::{decode::KeycloakToken, role::ExpectRoles};
+//!
+//! let token = KeycloakToken::<String>::builder()
+//!     .realm_role("administrator")
+//!     .build()
+//!     .unwrap();
+//! assert!(token.expect_roles(&["administrator"]).is_ok());
+//! ```
+
+use std::{collections::HashMap, time::Duration};
+
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    decode::KeycloakToken,
+    error::AuthError,
+    instance::{KeycloakAuthInstance, KeycloakConfig},
+    role::{KeycloakRole, Role, RoleMatching},
+};
+
+/// The key tokens are signed with.
+#[derive(Clone)]
+pub enum TestKey {
+    /// An RSA key pair, given as PEM. Tokens are signed using `Algorithm::RS256`.
+    Rsa {
+        private_pem: Vec<u8>,
+        public_pem: Vec<u8>,
+    },
+    /// A shared secret. Tokens are signed using `Algorithm::HS256`.
+    Secret(Vec<u8>),
+}
+
+impl TestKey {
+    pub fn rsa(private_pem: &[u8], public_pem: &[u8]) -> Self {
+        Self::Rsa {
+            private_pem: private_pem.to_vec(),
+            public_pem: public_pem.to_vec(),
+        }
+    }
+
+    pub fn secret(secret: &[u8]) -> Self {
+        Self::Secret(secret.to_vec())
+    }
+}
+
+impl std::fmt::Debug for TestKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TestKey::Rsa { .. } => f.write_str("TestKey::Rsa"),
+            TestKey::Secret(_) => f.write_str("TestKey::Secret"),
+        }
+    }
+}
+
+/// Builds signed tokens resembling the access tokens issued by Keycloak, together with a matching `KeycloakAuthInstance`.
+///
+/// The claims are modeled on the `StandardClaims`. By default, a token
+/// - is issued by the realm of the given `KeycloakConfig` (see `KeycloakAuthInstance::issuer`),
+/// - is valid for 5 minutes, starting now,
+/// - is meant for the "account" audience and the "test-client" client (its authorized party),
+/// - carries no roles, scopes or groups.
+#[derive(Debug, Clone)]
+pub struct TestTokenBuilder {
+    key: TestKey,
+    config: KeycloakConfig,
+    issued_at: time::OffsetDateTime,
+    expires_at: time::OffsetDateTime,
+    not_before: Option<time::OffsetDateTime>,
+    jwt_id: String,
+    issuer: Option<String>,
+    audience: Vec<String>,
+    subject: String,
+    token_type: String,
+    authorized_party: String,
+    scope: Option<String>,
+    realm_roles: Vec<String>,
+    client_roles: HashMap<String, Vec<String>>,
+    groups: Option<Vec<String>>,
+    extra: Map<String, Value>,
+}
+
+impl TestTokenBuilder {
+    /// Tokens are issued at the current time of the `clock` configured, expiring 5 minutes later.
+    pub fn new(key: TestKey, config: KeycloakConfig) -> Self {
+        let now = config.clock.now();
+        Self {
+            key,
+            config,
+            issued_at: now,
+            expires_at: now + Duration::from_secs(300),
+            not_before: None,
+            jwt_id: uuid::Uuid::now_v7().to_string(),
+            issuer: None,
+            audience: vec![String::from("account")],
+            subject: uuid::Uuid::now_v7().to_string(),
+            token_type: String::from("Bearer"),
+            authorized_party: String::from("test-client"),
+            scope: None,
+            realm_roles: Vec::new(),
+            client_roles: HashMap::new(),
+            groups: None,
+            extra: Map::new(),
+        }
+    }
+
+    /// Creates an instance validating the tokens built, using the `KeycloakConfig` given.
+    /// No OIDC discovery is performed.
+    pub fn instance(&self) -> Result<KeycloakAuthInstance, jsonwebtoken::errors::Error> {
+        Ok(match &self.key {
+            TestKey::Rsa { public_pem, .. } => KeycloakAuthInstance::from_decoding_keys(
+                self.config.clone(),
+                vec![DecodingKey::from_rsa_pem(public_pem)?],
+            ),
+            TestKey::Secret(secret) => {
+                KeycloakAuthInstance::from_secret(self.config.clone(), secret, Algorithm::HS256)
+            }
+        })
+    }
+
+    pub fn issued_at(mut self, issued_at: time::OffsetDateTime) -> Self {
+        self.issued_at = issued_at;
+        self
+    }
+
+    pub fn expires_at(mut self, expires_at: time::OffsetDateTime) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    /// Let the token expire `expires_in` after it was issued.
+    pub fn expires_in(mut self, expires_in: Duration) -> Self {
+        self.expires_at = self.issued_at + expires_in;
+        self
+    }
+
+    /// Let the token be expired for `expired_for` already. Useful to test rejections.
+    pub fn expired(mut self, expired_for: Duration) -> Self {
+        self.expires_at = self.config.clock.now() - expired_for;
+        self
+    }
+
+    pub fn not_before(mut self, not_before: time::OffsetDateTime) -> Self {
+        self.not_before = Some(not_before);
+        self
+    }
+
+    pub fn jwt_id(mut self, jwt_id: impl Into<String>) -> Self {
+        self.jwt_id = jwt_id.into();
+        self
+    }
+
+    /// Overrides the issuer, which defaults to the issuer of the configured realm.
+    pub fn issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Replaces the audiences of the token.
+    pub fn audience(mut self, audience: Vec<String>) -> Self {
+        self.audience = audience;
+        self
+    }
+
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = subject.into();
+        self
+    }
+
+    pub fn token_type(mut self, token_type: impl Into<String>) -> Self {
+        self.token_type = token_type.into();
+        self
+    }
+
+    pub fn authorized_party(mut self, authorized_party: impl Into<String>) -> Self {
+        self.authorized_party = authorized_party.into();
+        self
+    }
+
+    /// Sets the space-delimited 'scope' claim.
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Adds a realm role.
+    pub fn realm_role(mut self, role: impl Into<String>) -> Self {
+        self.realm_roles.push(role.into());
+        self
+    }
+
+    /// Adds a role of the given client.
+    pub fn client_role(mut self, client: impl Into<String>, role: impl Into<String>) -> Self {
+        self.client_roles
+            .entry(client.into())
+            .or_default()
+            .push(role.into());
+        self
+    }
+
+    /// Adds a group.
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.groups.get_or_insert_with(Vec::new).push(group.into());
+        self
+    }
+
+    /// Sets an additional claim, e.g. one read through a custom `Extra` type. Overrides any claim set before.
+    pub fn claim(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.extra.insert(key.into(), value.into());
+        self
+    }
+
+    /// All claims of the token to be built.
+    pub fn claims(&self) -> Map<String, Value> {
+        let issuer = match &self.issuer {
+            Some(issuer) => issuer.to_owned(),
+            None => crate::instance::issuer_of_realm(&self.config),
+        };
+        let mut claims = Map::new();
+        claims.insert(String::from("exp"), json!(self.expires_at.unix_timestamp()));
+        claims.insert(String::from("iat"), json!(self.issued_at.unix_timestamp()));
+        if let Some(not_before) = self.not_before {
+            claims.insert(String::from("nbf"), json!(not_before.unix_timestamp()));
+        }
+        claims.insert(String::from("jti"), json!(self.jwt_id));
+        claims.insert(String::from("iss"), json!(issuer));
+        claims.insert(String::from("aud"), json!(self.audience));
+        claims.insert(String::from("sub"), json!(self.subject));
+        claims.insert(String::from("typ"), json!(self.token_type));
+        claims.insert(String::from("azp"), json!(self.authorized_party));
+        if let Some(scope) = &self.scope {
+            claims.insert(String::from("scope"), json!(scope));
+        }
+        if !self.realm_roles.is_empty() {
+            claims.insert(
+                String::from("realm_access"),
+                json!({ "roles": self.realm_roles }),
+            );
+        }
+        if !self.client_roles.is_empty() {
+            let resource_access: Map<String, Value> = self
+                .client_roles
+                .iter()
+                .map(|(client, roles)| (client.to_owned(), json!({ "roles": roles })))
+                .collect();
+            claims.insert(String::from("resource_access"), resource_access.into());
+        }
+        if let Some(groups) = &self.groups {
+            claims.insert(String::from("groups"), json!(groups));
+        }
+        claims.extend(self.extra.clone());
+        claims
+    }
+
+    /// Builds the signed (compact) JWT.
+    pub fn build(&self) -> Result<String, jsonwebtoken::errors::Error> {
+        let (header, key) = match &self.key {
+            TestKey::Rsa { private_pem, .. } => (
+                Header::new(Algorithm::RS256),
+                EncodingKey::from_rsa_pem(private_pem)?,
+            ),
+            TestKey::Secret(secret) => (
+                Header::new(Algorithm::HS256),
+                EncodingKey::from_secret(secret),
+            ),
+        };
+        jsonwebtoken::encode(&header, &self.claims(), &key)
+    }
+}
+
+impl<R, Extra> KeycloakToken<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone + Default,
+{
+    /// Builds a token in memory, without parsing (or even signing) a JWT. See `KeycloakTokenBuilder`.
+    pub fn builder() -> KeycloakTokenBuilder<R, Extra> {
+        KeycloakTokenBuilder::new()
+    }
+}
+
+/// Builds a `KeycloakToken` in memory, e.g. to unit test authorization logic relying on `ExpectRoles`.
+///
+/// Unlike tokens built by the `TestTokenBuilder`, the token is not validated by any `KeycloakAuthLayer`.
+/// By default, a token
+/// - is issued by "https://localhost:8443/realms/test",
+/// - is valid for 5 minutes, starting now (according to the system clock),
+/// - is meant for the "account" audience and the "test-client" client (its authorized party),
+/// - carries no roles, scopes or groups and the default `Extra`.
+#[derive(Debug, Clone)]
+pub struct KeycloakTokenBuilder<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    token: KeycloakToken<R, Extra>,
+}
+
+impl<R, Extra> KeycloakTokenBuilder<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone + Default,
+{
+    fn new() -> Self {
+        let now = time::OffsetDateTime::now_utc();
+        Self {
+            token: KeycloakToken {
+                expires_at: now + Duration::from_secs(300),
+                not_before: None,
+                issued_at: Some(now),
+                auth_time: None,
+                jwt_id: uuid::Uuid::now_v7().to_string(),
+                issuer: String::from("https://localhost:8443/realms/test"),
+                audience: Some(vec![String::from("account")]),
+                subject: uuid::Uuid::now_v7().to_string(),
+                authorized_party: String::from("test-client"),
+                token_type: String::from("Bearer"),
+                scopes: Vec::new(),
+                authentication_context: None,
+                authentication_methods: None,
+                roles: Vec::new(),
+                unknown_roles: Vec::new(),
+                role_matching: RoleMatching::Exact,
+                groups: None,
+                tenant_id: None,
+                extra: Extra::default(),
+            },
+        }
+    }
+}
+
+impl<R, Extra> KeycloakTokenBuilder<R, Extra>
+where
+    R: Role,
+    Extra: DeserializeOwned + Clone,
+{
+    /// Sets the issued at time. Set it to `None` for tokens without an 'iat' claim.
+    pub fn issued_at(mut self, issued_at: Option<time::OffsetDateTime>) -> Self {
+        self.token.issued_at = issued_at;
+        self
+    }
+
+    pub fn expires_at(mut self, expires_at: time::OffsetDateTime) -> Self {
+        self.token.expires_at = expires_at;
+        self
+    }
+
+    pub fn not_before(mut self, not_before: time::OffsetDateTime) -> Self {
+        self.token.not_before = Some(not_before);
+        self
+    }
+
+    pub fn jwt_id(mut self, jwt_id: impl Into<String>) -> Self {
+        self.token.jwt_id = jwt_id.into();
+        self
+    }
+
+    pub fn issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.token.issuer = issuer.into();
+        self
+    }
+
+    /// Replaces the audiences of the token.
+    pub fn audience(mut self, audience: Vec<String>) -> Self {
+        self.token.audience = Some(audience);
+        self
+    }
+
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.token.subject = subject.into();
+        self
+    }
+
+    pub fn authorized_party(mut self, authorized_party: impl Into<String>) -> Self {
+        self.token.authorized_party = authorized_party.into();
+        self
+    }
+
+    /// Adds a granted scope.
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.token.scopes.push(scope.into());
+        self
+    }
+
+    /// Adds a realm role.
+    pub fn realm_role(self, role: impl Into<R>) -> Self {
+        self.role(KeycloakRole::Realm { role: role.into() })
+    }
+
+    /// Adds a role of the given client.
+    pub fn client_role(self, client: impl Into<String>, role: impl Into<R>) -> Self {
+        self.role(KeycloakRole::Client {
+            client: client.into(),
+            role: role.into(),
+        })
+    }
+
+    /// Adds a role in the scope given, unless already present.
+    pub fn role(mut self, role: KeycloakRole<R>) -> Self {
+        if !self.token.roles.contains(&role) {
+            self.token.roles.push(role);
+        }
+        self
+    }
+
+    pub fn role_matching(mut self, role_matching: RoleMatching) -> Self {
+        self.token.role_matching = role_matching;
+        self
+    }
+
+    /// Adds a group.
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.token
+            .groups
+            .get_or_insert_with(Vec::new)
+            .push(group.into());
+        self
+    }
+
+    pub fn tenant_id(mut self, tenant_id: impl Into<String>) -> Self {
+        self.token.tenant_id = Some(tenant_id.into());
+        self
+    }
+
+    pub fn extra(mut self, extra: Extra) -> Self {
+        self.token.extra = extra;
+        self
+    }
+
+    /// Builds the token, failing with an `AuthError::InvalidToken` if it would expire before it was issued.
+    pub fn build(self) -> Result<KeycloakToken<R, Extra>, AuthError> {
+        if let Some(issued_at) = self.token.issued_at {
+            if self.token.expires_at < issued_at {
+                return Err(AuthError::InvalidToken {
+                    reason: format!(
+                        "The token would expire at {}, before it was issued at {issued_at}",
+                        self.token.expires_at
+                    ),
+                });
+            }
+        }
+        Ok(self.token)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use url::Url;
+
+    use crate::{
+        decode::KeycloakToken, error::AuthError, instance::KeycloakConfig,
+        layer::KeycloakAuthLayer, role::ExpectRoles,
+    };
+
+    use super::{TestKey, TestTokenBuilder};
+
+    /// Builds tokens of the realm "MyRealm" of a Keycloak server at "https://localhost:8443/".
+    pub(crate) fn builder(key: TestKey) -> TestTokenBuilder {
+        TestTokenBuilder::new(
+            key,
+            KeycloakConfig::builder()
+                .server(Url::parse("https://localhost:8443/").unwrap())
+                .realm(String::from("MyRealm"))
+                .build(),
+        )
+    }
+
+    /// Like `builder`, signing tokens with the secret "secret".
+    pub(crate) fn secret_builder() -> TestTokenBuilder {
+        builder(TestKey::secret(b"secret"))
+    }
+
+    /// Serves the router on a free local port, returning its URL, e.g. "http://127.0.0.1:4321".
+    pub(crate) async fn serve(router: axum::Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        url
+    }
+
+    fn layer(builder: &TestTokenBuilder) -> KeycloakAuthLayer<String> {
+        KeycloakAuthLayer::<String>::builder()
+            .instance(builder.instance().unwrap())
+            .expected_audiences(vec![String::from("account")])
+            .validate_issuer_of_realm(true)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn builds_tokens_accepted_by_its_instance() {
+        for key in [
+            TestKey::rsa(
+                include_bytes!("testing/test_key.pem"),
+                include_bytes!("testing/test_key.pub.pem"),
+            ),
+            TestKey::secret(b"secret"),
+        ] {
+            let builder = builder(key);
+            let layer = layer(&builder);
+            let raw_token = builder
+                .clone()
+                .subject("f9b0c3b4-6d0e-4c2a-9a63-3b4c1d2e5f60")
+                .realm_role("administrator")
+                .client_role("billing", "manage")
+                .scope("openid email")
+                .claim("tenant_id", "acme")
+                .build()
+                .unwrap();
+
+            let (_, token) = layer.validate_raw_token(&raw_token).await.unwrap();
+            assert_eq!(token.subject, "f9b0c3b4-6d0e-4c2a-9a63-3b4c1d2e5f60");
+            assert_eq!(token.issuer, "https://localhost:8443/realms/MyRealm");
+            assert!(token.expect_roles(&["administrator"]).is_ok());
+            assert!(token.has_client_role("billing", "manage"));
+            assert!(token.has_scope("email"));
+            assert_eq!(builder.claims()["aud"], json!(["account"]));
+        }
+    }
+
+    #[tokio::test]
+    async fn builds_rejected_tokens() {
+        let builder = secret_builder();
+        let layer = layer(&builder);
+
+        let expired = builder.clone().expired(Duration::from_secs(60)).build();
+        assert!(matches!(
+            layer.validate_raw_token(&expired.unwrap()).await,
+            Err(AuthError::TokenExpired)
+        ));
+
+        let foreign = builder
+            .clone()
+            .issuer("https://evil.example.com/realms/MyRealm");
+        assert!(matches!(
+            layer.validate_raw_token(&foreign.build().unwrap()).await,
+            Err(AuthError::InvalidIssuer)
+        ));
+    }
+
+    #[test]
+    fn builds_tokens_in_memory() {
+        let token = KeycloakToken::<String>::builder()
+            .subject("f9b0c3b4-6d0e-4c2a-9a63-3b4c1d2e5f60")
+            .realm_role("administrator")
+            .realm_role("administrator")
+            .client_role("billing", "manage")
+            .scope("email")
+            .build()
+            .unwrap();
+        assert_eq!(token.subject, "f9b0c3b4-6d0e-4c2a-9a63-3b4c1d2e5f60");
+        assert_eq!(token.roles.len(), 2);
+        assert!(token.expect_roles(&["administrator", "manage"]).is_ok());
+        assert!(token.expect_roles(&["editor"]).is_err());
+        assert!(token.has_client_role("billing", "manage"));
+        assert!(token.has_scope("email"));
+        assert!(token.username().is_none());
+
+        let issued_at = time::OffsetDateTime::now_utc();
+        let expires_before_issued = KeycloakToken::<String>::builder()
+            .issued_at(Some(issued_at))
+            .expires_at(issued_at - Duration::from_secs(1))
+            .build();
+        assert!(matches!(
+            expires_before_issued,
+            Err(AuthError::InvalidToken { reason: _ })
+        ));
+    }
+}